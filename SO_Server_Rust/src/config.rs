@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+use std::thread::available_parallelism;
+use std::time::Duration;
+
+use http_common::access_log::{AccessLogFormat, AccessLogSettings};
+use http_common::config::{self, Settings};
+use http_common::log::{Level, LogFormat};
+use http_common::request::Limits;
+use http_common::trace::{TraceExporter, TraceSettings};
+
+// Configuracion del worker, ver http_common::config::Settings para las fuentes
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub listen_addr: String,           // Direccion y puerto donde escucha
+    pub pool_threads: usize,           // Hilos que atienden conexiones
+    pub pool_queue_depth: usize,       // Conexiones que pueden esperar en cola antes de responder 503
+    pub limits: Limits,                // Tamaño maximo de headers y body de una solicitud
+    pub legacy_get_aliases: bool,      // Permite GET en /createfile y /deletefile (compatibilidad)
+    pub keepalive_idle: Duration,      // Tiempo que una conexion puede estar inactiva entre solicitudes
+    pub keepalive_max_requests: usize, // Solicitudes por conexion antes de cerrarla
+    pub files_dir: PathBuf,            // Carpeta donde /createfile y /deletefile guardan los archivos
+    pub register_url: String,          // Dispatcher donde se auto-registra, vacio lo desactiva
+    pub advertise_url: String,         // URL que se anuncia al dispatcher, por defecto se arma con HOSTNAME
+    pub heartbeat_interval: Duration,  // Cada cuanto se renueva el registro
+    pub worker_weight: u32,            // Peso con el que se registra
+    pub admin_token: String,           // Token de /admin del dispatcher
+    pub drain_delay: Duration,         // Tiempo que se sigue atendiendo despues de SIGTERM con /ready en 503
+    pub shutdown_timeout: Duration,    // Maximo que se espera a las solicitudes en curso al apagarse
+    pub log_level: Level,              // Nivel minimo de los logs
+    pub log_format: LogFormat,         // text o json
+    pub trace: TraceSettings,          // A donde se exportan las trazas
+    pub access_log: AccessLogSettings, // Una linea por solicitud en formato common, combined, extended o json
+}
+
+impl Config {
+    // Flags, variables de entorno y archivo de configuracion; termina el proceso si hay un error
+    pub fn load() -> Config {
+        config::load("SO_Server_Rust", "worker", Config::from_settings)
+    }
+
+    pub fn from_env() -> Config {
+        Config::from_settings(&Settings::from_env())
+    }
+
+    pub fn from_settings(settings: &Settings) -> Config {
+        let default_threads = available_parallelism().map(|n| n.get()).unwrap_or(4);
+
+        Config {
+            listen_addr: settings.get("listen_addr", "0.0.0.0:7878".to_string()),
+            pool_threads: settings.get("pool_threads", default_threads).max(1),
+            pool_queue_depth: settings.get("pool_queue_depth", 64),
+            limits: Limits {
+                max_header_bytes: settings.get("max_header_bytes", 8 * 1024),
+                max_body_bytes: settings.get("max_body_bytes", 1024 * 1024),
+            },
+            legacy_get_aliases: settings.get("legacy_get_aliases", true),
+            keepalive_idle: Duration::from_secs(settings.get("keepalive_idle_secs", 5)),
+            keepalive_max_requests: settings.get("keepalive_max_requests", 100).max(1),
+            files_dir: PathBuf::from(settings.get("files_dir", "archivos".to_string())),
+            register_url: settings.get("register_url", String::new()),
+            advertise_url: settings.get("advertise_url", String::new()),
+            heartbeat_interval: Duration::from_secs(settings.get("heartbeat_interval_secs", 10)),
+            worker_weight: settings.get("worker_weight", 1),
+            admin_token: settings.get("admin_token", String::new()),
+            // Cubre un HEALTH_INTERVAL_SECS del dispatcher (10 s) mas el timeout del ping
+            drain_delay: Duration::from_secs(settings.get("drain_delay_secs", 12)),
+            shutdown_timeout: Duration::from_secs(settings.get("shutdown_timeout_secs", 8)),
+            log_level: settings.get("log_level", Level::Info),
+            log_format: settings.get("log_format", LogFormat::Text),
+            trace: TraceSettings {
+                exporter: settings.get("trace_exporter", TraceExporter::None),
+                otlp_endpoint: settings.get("trace_otlp_endpoint", "http://localhost:4318/v1/traces".to_string()),
+                file: settings.get("trace_file", "trazas-worker.jsonl".to_string()),
+                flush_interval: Duration::from_millis(settings.get("trace_flush_ms", 1000)),
+            },
+            access_log: AccessLogSettings {
+                target: settings.get("access_log", String::new()),
+                format: settings.get("access_log_format", AccessLogFormat::Common),
+                max_bytes: settings.get("access_log_max_bytes", 10 * 1024 * 1024),
+                max_files: settings.get("access_log_max_files", 5),
+            },
+        }
+    }
+}
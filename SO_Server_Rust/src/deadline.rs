@@ -0,0 +1,63 @@
+/*
+Tiempo limite de una solicitud, lo envia el dispatcher en el header X-Deadline-Ms.
+Las tareas largas lo revisan mientras calculan y se detienen cuando vence
+(cancelacion cooperativa): el hilo del pool queda libre y se responde 504.
+*/
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use http_common::protocol::DEADLINE_HEADER;
+use http_common::request::Request;
+
+// Revisar el reloj en cada paso haria mas lento el calculo, se revisa cada tantos pasos
+const CHECK_EVERY: u32 = 4096;
+
+// La tarea se detuvo porque vencio el tiempo limite
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Expired;
+
+#[derive(Debug)]
+pub struct Deadline {
+    at: Option<Instant>, // None: la solicitud no trae limite
+    steps: Cell<u32>,    // Pasos desde la ultima vez que se miro el reloj
+}
+
+impl Deadline {
+    pub fn none() -> Deadline {
+        Deadline { at: None, steps: Cell::new(0) }
+    }
+
+    pub fn after(timeout: Duration) -> Deadline {
+        Deadline { at: Some(Instant::now() + timeout), steps: Cell::new(0) }
+    }
+
+    // Un header que no se puede leer se ignora, como si no viniera
+    pub fn from_request(request: &Request) -> Deadline {
+        match request.header(DEADLINE_HEADER).and_then(|ms| ms.trim().parse::<u64>().ok()) {
+            Some(ms) => Deadline::after(Duration::from_millis(ms)),
+            None => Deadline::none(),
+        }
+    }
+
+    pub fn remaining(&self) -> Option<Duration> {
+        self.at.map(|at| at.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.at.is_some_and(|at| Instant::now() >= at)
+    }
+
+    // Para llamar dentro de ciclos y recursiones: solo mira el reloj cada CHECK_EVERY llamadas
+    pub fn check(&self) -> Result<(), Expired> {
+        if self.at.is_none() {
+            return Ok(());
+        }
+        let steps = self.steps.get() + 1;
+        if steps < CHECK_EVERY {
+            self.steps.set(steps);
+            return Ok(());
+        }
+        self.steps.set(0);
+        if self.is_expired() { Err(Expired) } else { Ok(()) }
+    }
+}
@@ -1,59 +1,95 @@
 use rand::Rng;
 use sha2::{Sha256, Digest};
 use chrono::{self, DateTime, Utc};
-use std::fs::{File, create_dir_all, remove_file};
-use std::io::Write;
+use std::fs::{File, create_dir_all, hard_link, remove_file};
+use std::io::{ErrorKind, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::deadline::{Deadline, Expired};
 
 
 // Este archivo va a ser un mòdulo que va a contener la lògica de todos los endpoints
 
 // /fibonacci
-pub fn fibonacci(n: u64) -> u64{
+// Recursivo a proposito para generar carga; se detiene si vence el tiempo limite
+pub fn fibonacci(n: u64, deadline: &Deadline) -> Result<u64, Expired> {
+    deadline.check()?;
     match n {
-        0 => 0,
-        1 => 1,
-        _ => fibonacci(n - 1) + fibonacci(n - 2),
+        0 => Ok(0),
+        1 => Ok(1),
+        _ => Ok(fibonacci(n - 1, deadline)? + fibonacci(n - 2, deadline)?),
+    }
+}
+
+// /sleep?seconds=n, duerme como maximo hasta el tiempo limite
+pub fn simulate_delay(seconds: u64, deadline: &Deadline) -> Result<(), Expired> {
+    let delay = Duration::from_secs(seconds);
+    match deadline.remaining() {
+        Some(remaining) if remaining < delay => {
+            sleep(remaining);
+            Err(Expired)
+        }
+        _ => {
+            sleep(delay);
+            Ok(())
+        }
     }
 }
+// Numera los archivos temporales de create_file, dos solicitudes con el mismo nombre no comparten temporal
+static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
 // / createfile?name=filename&content=text&repeat=X
-pub fn create_file (name : &str, content: &str) -> Result<String, String> {
+pub fn create_file (folder: &Path, name : &str, content: &str) -> Result<String, String> {
     if !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
         return Err("Nombre del archivo invàlido (Solo se permiten alfanùmericos)".to_string());
     }
 
-    let folder = "archivos";
-    if let Err(_) = create_dir_all(folder) {
+    if create_dir_all(folder).is_err() {
         return Err("No se pudo crear el directorio".to_string());
     }
 
-    let path = format!("{}/{}.txt", folder, name);
-    let path_original = Path::new(&path);
+    let path_original = folder.join(format!("{}.txt", name));
+    let path = path_original.display();
 
     if path_original.exists() {
         return Err(format!("El archivo '{}' ya existe", path));
     }
 
-    match File::create(&path_original) {
-        Ok(mut file) => {
-            if let Err(_) = file.write_all(content.as_bytes()) {
-                return Err("Error escribiendo en el archivo".to_string());
-            }
-            Ok(format!("Archivo '{}' creado exitosamente", path))
-        }
-        Err(_) => return Err("No se pudo crear el archivo".to_string()),
+    // Se escribe en un temporal y se publica con un hard link: si el worker se apaga a mitad no queda
+    // un archivo a medias. El nombre empieza con punto, asi no choca con ningun nombre valido de /createfile
+    let temp_id = NEXT_TEMP.fetch_add(1, Ordering::Relaxed);
+    let path_temp = folder.join(format!(".{}.txt.tmp-{}-{}", name, std::process::id(), temp_id));
+    let written = File::create(&path_temp).and_then(|mut file| {
+        file.write_all(content.as_bytes())?;
+        file.sync_all()
+    });
+    if written.is_err() {
+        let _ = remove_file(&path_temp);
+        return Err("Error escribiendo en el archivo".to_string());
+    }
+
+    // A diferencia de rename, hard_link falla si el archivo ya existe: de dos solicitudes
+    // concurrentes con el mismo nombre solo una lo crea y la otra recibe el error
+    let linked = hard_link(&path_temp, &path_original);
+    let _ = remove_file(&path_temp);
+    match linked {
+        Ok(_) => Ok(format!("Archivo '{}' creado exitosamente", path)),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => Err(format!("El archivo '{}' ya existe", path)),
+        Err(_) => Err("No se pudo crear el archivo".to_string()),
     }
 }
 
 // /deletefile?name=filename
-pub fn delete_file (name: &str) -> Result<String, String> {
+pub fn delete_file (folder: &Path, name: &str) -> Result<String, String> {
     if !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
         return Err("Nombre del archivo invàlido (Solo se permiten alfanùmericos)".to_string());
     }
 
-    let folder = "archivos";
-    let path = format!("{}/{}.txt", folder, name);
-    let path_original = Path::new(&path);
+    let path_original = folder.join(format!("{}.txt", name));
+    let path = path_original.display();
 
     if !path_original.exists() {
         return Err(format!("El archivo '{}' no existe", path));
@@ -83,8 +119,7 @@ pub fn timestamp_iso() -> String {
 
     //Convertimos en formato ISO
     let datetime: DateTime<Utc> = now.into();
-    let iso = datetime.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
-    format!("{}", iso)
+    datetime.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
 }
 
 // /hash?text=abc
@@ -96,11 +131,12 @@ pub fn sha256_hash(input: &str) -> String{
 }
 
 //Parte de calculo de pi con MonteCarlo
-pub fn calculate_monte_carlo(points: u64) -> u64 {
+pub fn calculate_monte_carlo(points: u64, deadline: &Deadline) -> Result<u64, Expired> {
     let mut rng = rand::rng();
     let mut hits = 0;
 
     for _ in 0..points {
+        deadline.check()?;
         let x: f64 = rng.random();
         let y: f64 = rng.random();
 
@@ -109,5 +145,5 @@ pub fn calculate_monte_carlo(points: u64) -> u64 {
             hits += 1;
         }
     }
-    return hits;
+    Ok(hits)
 }
@@ -1,112 +1,293 @@
-use std::{collections::HashMap, io::{Read, Write}, net::TcpStream, thread::sleep, time::{Duration}};
+use std::{io::{ErrorKind, Write}, net::TcpStream, time::{Duration, Instant, SystemTime}};
 
-use crate::{endpoints::{calculate_monte_carlo, create_file, delete_file, fibonacci, generate_random_numbers, rerverse_text, sha256_hash, timestamp_iso}, responses::{http_resonse_400, http_resonse_404, http_response_200, http_response_500}};
+use http_common::access_log::{AccessEntry, AccessLog};
+use http_common::request::{ParseError, ReadError, Request};
+use http_common::protocol::{new_request_id, MontecarloResult, MontecarloTask, MONTECARLO_PATH, REQUEST_ID_HEADER};
+use http_common::response::Response;
+use http_common::trace::{Span, SpanKind, TraceContext, Tracer, TRACEPARENT_HEADER};
+use http_common::{debug, info, warn};
+
+use crate::{config::Config, health::Health, metrics::Metrics, models::{help, FibonacciResult, FileResult, HashResult, RandomResult, ReverseResult, SleepResult, TimestampResult}, endpoints::{calculate_monte_carlo, create_file, delete_file, fibonacci, generate_random_numbers, rerverse_text, sha256_hash, simulate_delay, timestamp_iso}, deadline::Deadline, request::Connection, responses::{http_resonse_400, http_resonse_404, http_response_200, http_response_204_allow, http_response_405, http_response_413, http_response_431, http_response_500, http_response_504, http_response_metrics, SERVER_NAME}, thread_pool::PoolState};
+
+// Cada cuanto se revisa la cola del pool mientras una conexion espera la siguiente solicitud
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// Conexion aceptada que espera en la cola del pool, con la hora en que se acepto
+pub struct Accepted {
+    pub stream: TcpStream,
+    pub at: SystemTime,
+}
 
 /*
     Funcion encargada de gestionar la conexion
+    Atiende solicitudes en la misma conexion (keep-alive) hasta que el cliente la
+    cierre, se agote el tiempo de inactividad o se llegue al maximo de solicitudes.
 */
-pub fn handle_connection(mut stream: TcpStream) {
-    let mut buffer = [0u8; 1024];
-    let read_bytes = stream.read(&mut buffer).unwrap_or(0);
+pub fn handle_connection(
+    accepted: Accepted,
+    config: &Config,
+    health: &Health,
+    metrics: &Metrics,
+    tracer: &Tracer,
+    access_log: &AccessLog,
+    pool: &PoolState,
+) {
+    let dequeued = SystemTime::now();
+    let client = accepted.stream.peer_addr().ok().map(|addr| addr.ip());
+    let mut connection = Connection::new(accepted.stream);
+    let mut served = 0;
+    let _active = metrics.connections_active.track(&[]);
 
-    if read_bytes == 0 {
-        return ;
-    }
+    loop {
+        // En la primera solicitud no se cede el hilo, la conexion acaba de salir de la cola
+        if !wait_for_request(&mut connection, config.keepalive_idle, pool, health, served == 0) {
+            return;
+        }
+        let _ = connection.stream().set_read_timeout(Some(config.keepalive_idle));
+
+        let started = Instant::now();
+        let received = SystemTime::now();
+        let (response, client_keep_alive, served_request) = match connection.read_request(&config.limits) {
+            Ok(mut request) => {
+                let request_id = request.ensure_request_id().to_string();
+                debug!("http", request_id = request_id, method = request.method, path = request.path; "Solicitud recibida");
 
-    let request = String::from_utf8_lossy(&buffer[..]);
-    let (_method, path) = parse_request(&request);
+                //Si viene del dispatcher, los spans del worker cuelgan del envio del dispatcher
+                let parent = request.header(TRACEPARENT_HEADER).and_then(TraceContext::parse);
+                let mut span = Span::server(&request.method, parent);
+                //La primera solicitud de la conexion incluye lo que espero en la cola del pool
+                if served == 0 {
+                    span = span.starting_at(accepted.at);
+                    tracer.end_at(span.child("queue", SpanKind::Internal).starting_at(accepted.at), dequeued);
+                }
+                let execute = span.child("execute", SpanKind::Internal);
+                let response = {
+                    let _in_flight = metrics.requests_in_flight.track(&[]);
+                    route_request(&request, config, health, metrics)
+                };
+                tracer.end(execute);
 
-    let response = route_request(&path);
+                let route = route_label(&request.path, config.legacy_get_aliases).to_string();
+                span.record_response(&request.method, &request.path, &route, response.status(), &request_id);
+                tracer.end(span);
+                (response, request.keep_alive(), Some((route, request)))
+            }
+            Err(ReadError::Closed) => return,
+            Err(ReadError::Io(e)) => {
+                warn!("http", "Fallo al leer la solicitud: {}", e);
+                return;
+            }
+            // Despues de un error de parseo no se sabe donde empieza la siguiente solicitud
+            Err(ReadError::Parse(ParseError::BadRequest(msg))) => (http_resonse_400(&msg), false, None),
+            Err(ReadError::Parse(ParseError::HeadersTooLarge)) => (http_response_431("Los headers de la solicitud son demasiado grandes"), false, None),
+            Err(ReadError::Parse(ParseError::PayloadTooLarge)) => (http_response_413("El body de la solicitud es demasiado grande"), false, None),
+        };
+        served += 1;
 
-    if let Err(e) = stream.write_all(response.as_bytes()) {
-        eprintln!("Fallo al escribir la respuesta en el stream: {}", e);
-        return;
-    }
-    if let Err(e) = stream.flush() {
-        eprintln!("Fallo al hacer flush en el stream: {}", e);
+        // Una solicitud que no se pudo leer no tiene ruta ni metodo, se le da un id para el log
+        let elapsed = started.elapsed();
+        let (route, request, request_id) = match &served_request {
+            Some((route, request)) => (route.as_str(), Some(request), request.request_id().to_string()),
+            None => ("other", None, new_request_id()),
+        };
+        let (method, path) = request.map_or(("unknown", "-"), |r| (r.method.as_str(), r.path.as_str()));
+        metrics.record_request(route, method, response.status(), elapsed);
+        info!(
+            "http",
+            request_id = request_id,
+            method = method,
+            path = path,
+            status = response.status(),
+            duration_ms = elapsed.as_millis();
+            "Solicitud atendida"
+        );
+        access_log.write(&AccessEntry {
+            client,
+            time: received,
+            request,
+            status: response.status(),
+            bytes: response.sent_body_len(),
+            duration: elapsed,
+            worker: None,
+            request_id: &request_id,
+        });
+        let response = response.header(REQUEST_ID_HEADER, &request_id);
+
+        let keep_alive = client_keep_alive && served < config.keepalive_max_requests;
+        let response = response.connection(
+            keep_alive.then(|| (config.keepalive_idle.as_secs(), config.keepalive_max_requests - served)),
+        );
+
+        let stream = connection.stream_mut();
+        if let Err(e) = stream.write_all(&response.to_bytes(SERVER_NAME)) {
+            warn!("http", request_id = request_id; "Fallo al escribir la respuesta en el stream: {}", e);
+            return;
+        }
+        if let Err(e) = stream.flush() {
+            warn!("http", request_id = request_id; "Fallo al hacer flush en el stream: {}", e);
+            return;
+        }
+
+        if !keep_alive {
+            return;
+        }
     }
 }
 
 /*
-Extrae mètodo HTTP de la solicitud y la ruta.
+Espera a que llegue el inicio de la siguiente solicitud.
+Devuelve false si el cliente cerro, se agoto `idle_timeout`, el worker se esta apagando o,
+cuando `first` es false, si hay conexiones esperando en la cola del pool (se libera el hilo para ellas).
 */
-pub fn parse_request(request: &str) -> (&str, String) {
-    let mut lines = request.lines();
-    if let Some(request_line) = lines.next() {
-        let parts: Vec<&str> = request_line.split_whitespace().collect();
-        if parts.len() >= 2 {
-            return (parts[0], parts[1].to_string());
+fn wait_for_request(connection: &mut Connection, idle_timeout: Duration, pool: &PoolState, health: &Health, first: bool) -> bool {
+    // Una solicitud en pipeline ya puede estar en el buffer
+    if connection.has_buffered() {
+        return true;
+    }
+
+    let deadline = Instant::now() + idle_timeout;
+    if connection.stream().set_read_timeout(Some(IDLE_POLL_INTERVAL)).is_err() {
+        return false;
+    }
+
+    loop {
+        match connection.fill() {
+            Ok(read) => return read > 0,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if Instant::now() >= deadline || health.is_draining() || (!first && pool.queued() > 0) {
+                    return false;
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return false,
         }
     }
-    ("", "".to_string())
 }
 
 /*
 Router principal
-Determina el tipo de tarea a realizar
+Valida el metodo contra la tabla de rutas y despues ejecuta la tarea.
+OPTIONS se responde automaticamente para toda ruta conocida y HEAD para las rutas GET.
+*/
+pub fn route_request(request: &Request, config: &Config, health: &Health, metrics: &Metrics) -> Response {
+    let allowed = allowed_methods(&request.path, config.legacy_get_aliases);
+    let response = if allowed.is_empty() {
+        http_resonse_404("Ruta no encontrada")
+    } else {
+        match request.method.as_str() {
+            "OPTIONS" => http_response_204_allow(&allowed.join(", ")),
+            method if !allowed.contains(&method) => http_response_405(&allowed.join(", ")),
+            _ => handle_route(request, config, health, metrics),
+        }
+    };
+
+    // La respuesta a HEAD nunca lleva body, tampoco si es un error
+    if request.method == "HEAD" { response.head_only() } else { response }
+}
+
+// Ruta para las metricas: las que no existen se agrupan para no crear una serie por cada URL
+fn route_label(path: &str, legacy_get_aliases: bool) -> &str {
+    if allowed_methods(path, legacy_get_aliases).is_empty() { "other" } else { path }
+}
+
+/*
+Metodos aceptados por cada ruta, vacio si la ruta no existe.
+Con `legacy_get_aliases` las rutas que modifican archivos tambien aceptan GET, pero no HEAD:
+un HEAD ejecutaria la tarea completa y crearia o borraria el archivo.
+*/
+fn allowed_methods(path: &str, legacy_get_aliases: bool) -> Vec<&'static str> {
+    let mut methods = match path {
+        "/ping" | "/ready" | "/metrics" | MONTECARLO_PATH | "/fibonacci" | "/reverse" | "/hash" | "/timestamp" | "/sleep"
+        | "/random" | "/help" => vec!["GET", "HEAD"],
+        "/createfile" => vec!["POST"],
+        "/deletefile" => vec!["DELETE"],
+        _ => return Vec::new(),
+    };
+
+    if legacy_get_aliases && !methods.contains(&"GET") {
+        methods.insert(0, "GET");
+    }
+    methods.push("OPTIONS");
+    methods
+}
+
+/*
+Ejecuta la tarea de la ruta con los parametros de la solicitud
 */
-pub fn route_request(path: &str) -> String {
-    let (route, params) = parse_query(path);
+fn handle_route(request: &Request, config: &Config, health: &Health, metrics: &Metrics) -> Response {
+    let params = request.params();
 
-    match route.as_str() {
-        "ping" => {
-            return http_response_200("{\"status\":\"ok\"}");
+    //Si el dispatcher ya no espera la respuesta no tiene sentido empezar
+    let deadline = Deadline::from_request(request);
+    if deadline.is_expired() {
+        return http_response_504("La solicitud llego despues de su tiempo limite");
+    }
+
+    match request.path.as_str() {
+        //Liveness: si el worker puede responder esto, esta vivo
+        "/ping" => http_response_200(&health.liveness()),
+
+        //Readiness: 503 si no debe recibir tareas, el body dice por que
+        "/ready" => {
+            let readiness = health.readiness();
+            let status = if readiness.ready { 200 } else { 503 };
+            Response::json(status, &readiness)
         }
-        
-        "/internal/montecarlo" => {
-            if let Some(p_str) = params.get("points") {
-                if let Ok(p) = p_str.parse::<u64>() {
-                    let hits = calculate_monte_carlo(p);
-                    let body = format!("{{\"hits\":{}}}", hits);
-                    return format!(
-                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
-                        body.len(),
-                        body
-                    );
+
+        "/metrics" => http_response_metrics(metrics.render(&config.files_dir)),
+
+        MONTECARLO_PATH => match MontecarloTask::from_params(&params) {
+            Ok(task) => match timed(|| calculate_monte_carlo(task.points, &deadline)) {
+                (Ok(hits), elapsed) => {
+                    metrics.record_montecarlo(task.points, elapsed);
+                    http_response_200(&MontecarloResult { hits })
                 }
-            }
-            return http_resonse_400("Parametro 'points' invalido o faltante");
-        }
+                (Err(_), _) => http_response_504("Se detuvo el calculo de Montecarlo, vencio el tiempo limite"),
+            },
+            Err(error) => Response::error(&error),
+        },
 
         "/fibonacci" => {
-            if let Some(n_str) = params.get("num") {
-                if let Ok(n) = n_str.parse::<u64>() {
-                    let result = fibonacci(n);
-                    return http_response_200(&result.to_string());
-                }
+            if let Some(n_str) = params.get("num")
+                && let Ok(n) = n_str.parse::<u64>() {
+                return match fibonacci(n, &deadline) {
+                    Ok(result) => http_response_200(&FibonacciResult { num: n, result }),
+                    Err(_) => http_response_504("Se detuvo el calculo de fibonacci, vencio el tiempo limite"),
+                };
             }
-            return http_resonse_400("Parametro 'num' invalido");
+            http_resonse_400("Parametro 'num' invalido")
         }
 
         "/reverse" => {
             if let Some(text) = params.get("text") {
                 let result = rerverse_text(text);
-                return http_response_200(&result);
+                return http_response_200(&ReverseResult { text: text.to_string(), result });
             }
-            return http_resonse_400("Falta el parametro 'text'");
+            http_resonse_400("Falta el parametro 'text'")
         }
 
         "/hash" => {
             if let Some(text) = params.get("text") {
-                let result = sha256_hash(text);
-                return http_response_200(&result);
+                let hash = sha256_hash(text);
+                return http_response_200(&HashResult { text: text.to_string(), algorithm: "sha256", hash });
             }
-            return http_resonse_400("Falta el parametro 'text'");
+            http_resonse_400("Falta el parametro 'text'")
         }
 
         "/timestamp" => {
-            let result = timestamp_iso();
-            return http_response_200(&result);
+            let timestamp = timestamp_iso();
+            http_response_200(&TimestampResult { timestamp })
         }
 
         "/sleep" => {
-            if let Some(n_str) = params.get("seconds") {
-                if let Ok(n) = n_str.parse::<u64>() {
-                    sleep(Duration::from_secs(n));
-                    return http_response_200(&format!("Simulado retraso de {} segundos", n));
-                }
+            if let Some(n_str) = params.get("seconds")
+                && let Ok(n) = n_str.parse::<u64>() {
+                return match simulate_delay(n, &deadline) {
+                    Ok(()) => http_response_200(&SleepResult { seconds: n, message: format!("Simulado retraso de {} segundos", n) }),
+                    Err(_) => http_response_504("El retraso supera el tiempo limite de la solicitud"),
+                };
             }
-            return http_resonse_400("Parámetro 'seconds' inválido o faltante");
+            http_resonse_400("Parámetro 'seconds' inválido o faltante")
         }
 
         "/random" => {
@@ -119,74 +300,46 @@ pub fn route_request(path: &str) -> String {
                     return http_resonse_400("El parametro 'min' debe ser menor que 'max'");
                 }
                 let numbers = generate_random_numbers(c, mi, ma);
-                return http_response_200(&format!("{:?}", numbers));
+                return http_response_200(&RandomResult { count: c, min: mi, max: ma, numbers });
             }
-            return http_resonse_400("Faltan parametros (count, min, max) o son invalidos");
+            http_resonse_400("Faltan parametros (count, min, max) o son invalidos")
         }
 
         "/createfile" => {
             if let (Some(name), Some(content)) = (params.get("name"), params.get("content")) {
-                match create_file(name, content) {
-                    Ok(msg) => http_response_200(&format!("{}", msg)),
+                let result = create_file(&config.files_dir, name, content);
+                metrics.record_file_operation("create", result.is_ok());
+                match result {
+                    Ok(message) => http_response_200(&FileResult { name: name.to_string(), message }),
                     Err(e) => http_response_500(&e)
                 }
             } else {
-                return http_resonse_400("Faltan parametros 'name' o 'content'");
+                http_resonse_400("Faltan parametros 'name' o 'content'")
             }
         }
 
         "/deletefile" => {
             if let Some(name) = params.get("name") {
-                match delete_file(name) {
-                    Ok(msg) => http_response_200(&format!("{}", msg)),
+                let result = delete_file(&config.files_dir, name);
+                metrics.record_file_operation("delete", result.is_ok());
+                match result {
+                    Ok(message) => http_response_200(&FileResult { name: name.to_string(), message }),
                     Err(e) => http_response_500(&e),
                 }
             } else {
-                return http_resonse_400("Falta el parametro 'name'");
+                http_resonse_400("Falta el parametro 'name'")
             }
         }
 
-        "/help" => {
-            let help_text = format!("\"endpoints\" : [
-                {{\"path\" : \"reverse\", 
-                \"description\" : \"Invierte el texto recibido\", 
-                \"params\" : [\"text: texto que se desea invertir\"], 
-                \"example\" : \"/reverse?text=abc\"}},
-                {{\"path\" : \"toupper\", \"description\" : \"Convierte el texto a mayúsculas\", \"params\" : [\"text: texto a convertir\"], \"example\" : \"/toupper?text=hola\"}},
-                {{\"path\" : \"sha256\", \"description\" : \"Devuelve el hash SHA-256 del texto\", \"params\" : [\"text: texto a hashear\"], \"example\" : \"/sha256?text=hola\"}},
-                {{\"path\" : \"fibonacci\", \"description\" : \"Calcula el n-ésimo número de Fibonacci (recursivo)\", \"params\" : [\"num: número a calcular\"], \"example\" : \"/fibonacci?num=10\"}},
-                {{\"path\" : \"random\", \"description\" : \"Genera una lista de números aleatorios\", \"params\" : [\"count: cantidad\", \"min: mínimo\", \"max: máximo\"], \"example\" : \"/random?count=5&min=10&max=100\"}},
-                {{\"path\" : \"timestamp\", \"description\" : \"Devuelve la hora actual en formato ISO\", \"params\" : [], \"example\" : \"/timestamp\"}},
-                {{\"path\" : \"sleep\", \"description\" : \"Simula una espera bloqueante de N segundos\", \"params\" : [\"seconds: segundos a esperar\"], \"example\" : \"/sleep?seconds=3\"}},
-                {{\"path\" : \"createfile\", \"description\" : \"Crea un archivo con el contenido indicado\", \"params\" : [\"name: nombre del archivo\", \"content: contenido\"], \"example\" : \"/createfile?name=miarchivo&content=hola\"}},
-                {{\"path\" : \"deletefile\", \"description\" : \"Elimina un archivo existente\", \"params\" : [\"name: nombre del archivo\"], \"example\" : \"/deletefile?name=miarchivo\"}},
-                {{\"path\" : \"simulate\", \"description\" : \"Simula un endpoint como reverse, toupper, etc., con retardo\", \"params\" : [\"seconds: retardo\", \"task: nombre del endpoint interno\", \"otros: según la tarea\"], \"example\" : \"/simulate?seconds=2&task=reverse&text=hola\"}},
-                {{\"path\" : \"loadtest\", \"description\" : \"Encola múltiples tareas para medir carga del sistema\", \"params\" : [\"task: tipo de tarea\", \"count: cuántas tareas\", \"text: valor base si aplica\"], \"example\" : \"/loadtest?task=reverse&count=5&text=hola\"}},
-                {{\"path\" : \"help\", \"description\" : \"Devuelve este manual de uso de endpoints\", \"params\" : [], \"example\" : \"/help\"}},
-                ]");
-                return http_response_200(&help_text);
-        }
+        "/help" => http_response_200(&help()),
 
         _ => http_resonse_404("Ruta no encontrada")
     }
 }
 
-/*
-Separa la ruta principal de los parametros
-*/
-pub fn parse_query (path: &str) -> (String, HashMap<String, String>) {
-    let mut parts = path.split('?');
-    let route = parts.next().unwrap_or("").to_string();
-    let mut query_map = HashMap::new();
-
-    if let Some(query) = parts.next() {
-        for param in query.split('&') {
-            let mut kv = param.split('=');
-            let key = kv.next().unwrap_or("").to_string();
-            let value = kv.next().unwrap_or("").to_string();
-            query_map.insert(key, value);
-        }
-    }
-
-    (route, query_map)
-}
\ No newline at end of file
+// Ejecuta la tarea y devuelve tambien cuanto tardo
+fn timed<T>(task: impl FnOnce() -> T) -> (T, Duration) {
+    let started = Instant::now();
+    let result = task();
+    (result, started.elapsed())
+}
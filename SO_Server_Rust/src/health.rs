@@ -0,0 +1,72 @@
+// Estado de salud del worker, lo consulta el dispatcher en /ping y /ready
+use std::fs::{self, create_dir_all};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use http_common::protocol::{Liveness, Readiness, PROTOCOL_VERSION};
+
+/*
+Estado compartido entre todos los hilos del pool.
+`draining` indica que el worker se esta apagando y no debe recibir tareas nuevas.
+*/
+#[derive(Debug)]
+pub struct Health {
+    started: Instant,
+    draining: AtomicBool,
+    files_dir: PathBuf, // Carpeta que se revisa en /ready
+}
+
+impl Health {
+    pub fn new(files_dir: PathBuf) -> Health {
+        Health {
+            started: Instant::now(),
+            draining: AtomicBool::new(false),
+            files_dir,
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    // A partir de aqui /ready responde 503 y las conexiones keep-alive inactivas se cierran
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    pub fn liveness(&self) -> Liveness {
+        Liveness {
+            status: "ok".to_string(),
+            uptime_secs: self.started.elapsed().as_secs(),
+            protocol_version: PROTOCOL_VERSION,
+        }
+    }
+
+    pub fn readiness(&self) -> Readiness {
+        let draining = self.is_draining();
+        let storage_writable = storage_writable(&self.files_dir);
+        Readiness {
+            ready: !draining && storage_writable,
+            draining,
+            storage_writable,
+            protocol_version: PROTOCOL_VERSION,
+        }
+    }
+}
+
+/*
+Prueba que /createfile pueda funcionar: crea la carpeta si falta
+y escribe y borra un archivo temporal.
+*/
+fn storage_writable(files_dir: &Path) -> bool {
+    if create_dir_all(files_dir).is_err() {
+        return false;
+    }
+    // El nombre no es alfanumerico puro, asi no choca con archivos de /createfile
+    let probe = files_dir.join(format!(".ready-{}", process::id()));
+    let writable = fs::write(&probe, b"ok").is_ok();
+    let _ = fs::remove_file(&probe);
+    writable
+}
@@ -0,0 +1,17 @@
+// Worker: atiende las tareas que le reenvia el dispatcher
+
+pub mod config;
+pub mod deadline;
+pub mod endpoints;
+pub mod handle_connection;
+pub mod health;
+pub mod metrics;
+pub mod models;
+pub mod registration;
+pub mod request;
+pub mod responses;
+pub mod server;
+pub mod thread_pool;
+
+pub use config::Config;
+pub use server::{Server, ServerHandle};
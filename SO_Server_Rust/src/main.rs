@@ -1,31 +1,28 @@
-use std::net::TcpListener;
+use http_common::{error, info, log};
+use so_server_rust::{Config, Server};
 
-mod handle_connection;
-mod endpoints;
-mod responses;
-
-use crate::handle_connection::handle_connection;
 fn main() {
-    let listener = match TcpListener::bind("0.0.0.0:7878") {
-        Ok(listener) => {
-            println!("Servidor simple iniciado y escuchando en 0.0.0.0:7878");
-            listener
+    let config = Config::load();
+    log::init(config.log_level, config.log_format);
+    let listen_addr = config.listen_addr.clone();
+
+    let server = match Server::bind(&listen_addr, config) {
+        Ok(server) => {
+            info!("worker", address = listen_addr; "Servidor iniciado y escuchando");
+            server
         },
         Err(e) => {
-            eprintln!("ERROR CRÍTICO: No se pudo enlazar al puerto 7878. Error: {}", e);
+            error!("worker", address = listen_addr; "No se pudo enlazar: {}", e);
             std::process::exit(1);
         }
     };
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                println!("[Worker] Conexión entrante aceptada.");
-                handle_connection(stream);
-            }
-            Err(_e) => {
-                eprintln!("Error al aceptar la conexion.");
-            }
-        }
+    // Sin esto SIGTERM mataba el proceso a mitad de una solicitud
+    if let Err(e) = server.stop_on_signals() {
+        error!("worker", "No se pudieron registrar las senales de apagado: {}", e);
     }
-}
\ No newline at end of file
+
+    let finished = server.run();
+    info!("worker", "Apagado");
+    std::process::exit(if finished { 0 } else { 1 });
+}
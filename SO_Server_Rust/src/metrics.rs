@@ -0,0 +1,95 @@
+// Metricas del worker que se publican en /metrics, en el formato de Prometheus
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use http_common::metrics::{method_label, Counter, Gauge, Histogram, LATENCY_BUCKETS};
+
+#[derive(Debug)]
+pub struct Metrics {
+    requests: Counter,
+    request_duration: Histogram,
+    pub requests_in_flight: Gauge,
+    pub connections_active: Gauge,     // Conexiones que esta atendiendo algun hilo del pool
+    pub connections_rejected: Counter, // Rechazadas con 503 porque la cola del pool estaba llena
+    montecarlo_points: Counter,
+    montecarlo_rate: Gauge,
+    file_operations: Counter,
+    files_stored: Gauge,               // Se cuenta al leer /metrics
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            requests: Counter::new("http_requests_total", "Solicitudes atendidas por ruta, metodo y codigo", &["route", "method", "status"]),
+            request_duration: Histogram::new(
+                "http_request_duration_seconds",
+                "Tiempo para atender una solicitud por ruta y codigo",
+                &["route", "status"],
+                LATENCY_BUCKETS,
+            ),
+            requests_in_flight: Gauge::new("http_requests_in_flight", "Solicitudes que se estan atendiendo", &[]),
+            connections_active: Gauge::new("worker_connections_active", "Conexiones que atiende el pool de hilos", &[]),
+            connections_rejected: Counter::new("worker_connections_rejected_total", "Conexiones rechazadas con 503 por la cola llena", &[]),
+            montecarlo_points: Counter::new("worker_montecarlo_points_total", "Puntos de Montecarlo calculados", &[]),
+            montecarlo_rate: Gauge::new("worker_montecarlo_points_per_second", "Puntos por segundo del ultimo calculo de Montecarlo", &[]),
+            file_operations: Counter::new("worker_file_operations_total", "Archivos creados y borrados, por resultado", &["operation", "result"]),
+            files_stored: Gauge::new("worker_files_stored", "Archivos guardados en la carpeta de archivos", &[]),
+        }
+    }
+
+    // `route` ya viene acotada (ver handle_connection::route_label) para no crear una serie por cada URL
+    pub fn record_request(&self, route: &str, method: &str, status: u16, elapsed: Duration) {
+        let status = status.to_string();
+        self.requests.inc(&[route, method_label(method), &status]);
+        self.request_duration.observe(&[route, &status], elapsed.as_secs_f64());
+    }
+
+    pub fn record_montecarlo(&self, points: u64, elapsed: Duration) {
+        self.montecarlo_points.add(&[], points as f64);
+        if !elapsed.is_zero() {
+            self.montecarlo_rate.set(&[], points as f64 / elapsed.as_secs_f64());
+        }
+    }
+
+    pub fn record_file_operation(&self, operation: &str, ok: bool) {
+        self.file_operations.inc(&[operation, if ok { "ok" } else { "error" }]);
+    }
+
+    pub fn render(&self, files_dir: &Path) -> String {
+        self.files_stored.set(&[], count_files(files_dir) as f64);
+
+        let mut out = String::new();
+        self.requests.render(&mut out);
+        self.request_duration.render(&mut out);
+        self.requests_in_flight.render(&mut out);
+        self.connections_active.render(&mut out);
+        self.connections_rejected.render(&mut out);
+        self.montecarlo_points.render(&mut out);
+        self.montecarlo_rate.render(&mut out);
+        self.file_operations.render(&mut out);
+        self.files_stored.render(&mut out);
+        out
+    }
+}
+
+// Archivos de /createfile; los temporales y el de /ready empiezan con punto
+fn count_files(files_dir: &Path) -> usize {
+    let Ok(entries) = fs::read_dir(files_dir) else {
+        return 0;
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            !name.starts_with('.') && name.ends_with(".txt")
+        })
+        .count()
+}
@@ -0,0 +1,95 @@
+// Cuerpos de respuesta de los endpoints publicos, se serializan a JSON con serde
+// Los de rutas internas estan en http_common::protocol
+use serde::Serialize;
+
+// /fibonacci
+#[derive(Debug, Serialize)]
+pub struct FibonacciResult {
+    pub num: u64,
+    pub result: u64,
+}
+
+// /reverse
+#[derive(Debug, Serialize)]
+pub struct ReverseResult {
+    pub text: String,
+    pub result: String,
+}
+
+// /hash
+#[derive(Debug, Serialize)]
+pub struct HashResult {
+    pub text: String,
+    pub algorithm: &'static str,
+    pub hash: String,
+}
+
+// /timestamp
+#[derive(Debug, Serialize)]
+pub struct TimestampResult {
+    pub timestamp: String,
+}
+
+// /sleep
+#[derive(Debug, Serialize)]
+pub struct SleepResult {
+    pub seconds: u64,
+    pub message: String,
+}
+
+// /random
+#[derive(Debug, Serialize)]
+pub struct RandomResult {
+    pub count: usize,
+    pub min: i32,
+    pub max: i32,
+    pub numbers: Vec<i32>,
+}
+
+// /createfile y /deletefile
+#[derive(Debug, Serialize)]
+pub struct FileResult {
+    pub name: String,
+    pub message: String,
+}
+
+// /help
+#[derive(Debug, Serialize)]
+pub struct HelpResult {
+    pub endpoints: Vec<EndpointHelp>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EndpointHelp {
+    pub path: &'static str,
+    pub method: &'static str,
+    pub description: &'static str,
+    pub params: Vec<&'static str>,
+    pub example: &'static str,
+}
+
+impl EndpointHelp {
+    fn new(path: &'static str, method: &'static str, description: &'static str, params: &[&'static str], example: &'static str) -> EndpointHelp {
+        EndpointHelp { path, method, description, params: params.to_vec(), example }
+    }
+}
+
+// Manual de uso de los endpoints publicos
+pub fn help() -> HelpResult {
+    HelpResult {
+        endpoints: vec![
+            EndpointHelp::new("/reverse", "GET", "Invierte el texto recibido", &["text: texto que se desea invertir"], "/reverse?text=abc"),
+            EndpointHelp::new("/hash", "GET", "Devuelve el hash SHA-256 del texto", &["text: texto a hashear"], "/hash?text=hola"),
+            EndpointHelp::new("/fibonacci", "GET", "Calcula el n-ésimo número de Fibonacci (recursivo)", &["num: número a calcular"], "/fibonacci?num=10"),
+            EndpointHelp::new("/random", "GET", "Genera una lista de números aleatorios", &["count: cantidad", "min: mínimo", "max: máximo"], "/random?count=5&min=10&max=100"),
+            EndpointHelp::new("/timestamp", "GET", "Devuelve la hora actual en formato ISO", &[], "/timestamp"),
+            EndpointHelp::new("/sleep", "GET", "Simula una espera bloqueante de N segundos", &["seconds: segundos a esperar"], "/sleep?seconds=3"),
+            EndpointHelp::new("/createfile", "POST", "Crea un archivo con el contenido indicado", &["name: nombre del archivo", "content: contenido"], "/createfile?name=miarchivo&content=hola"),
+            EndpointHelp::new("/deletefile", "DELETE", "Elimina un archivo existente", &["name: nombre del archivo"], "/deletefile?name=miarchivo"),
+            EndpointHelp::new("/ping", "GET", "Indica que el worker esta vivo", &[], "/ping"),
+            EndpointHelp::new("/ready", "GET", "Indica si el worker puede recibir tareas", &[], "/ready"),
+            EndpointHelp::new("/metrics", "GET", "Metricas del worker en formato de Prometheus", &[], "/metrics"),
+            EndpointHelp::new("/help", "GET", "Devuelve este manual de uso de endpoints", &[], "/help"),
+        ],
+    }
+}
@@ -0,0 +1,166 @@
+/*
+Auto-registro en el dispatcher.
+Si REGISTER_URL esta definido el worker se da de alta en POST /admin/workers al iniciar
+y repite el alta cada HEARTBEAT_INTERVAL_SECS; si deja de hacerlo el dispatcher lo borra.
+Al detenerse se da de baja con DELETE /admin/workers/{id}.
+*/
+use std::env;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use http_common::protocol::{Registration, RegistrationAck, WORKERS_ADMIN_PATH};
+use http_common::{error, info, warn};
+
+use crate::config::Config;
+
+// Tiempo maximo para conectar y para recibir la respuesta del dispatcher
+const DISPATCHER_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Cada cuanto se revisa si hay que detenerse mientras se espera el proximo heartbeat
+const STOP_POLL: Duration = Duration::from_millis(200);
+
+/*
+Inicia el hilo de heartbeats. Devuelve None si el auto-registro esta desactivado.
+`local_addr` se usa para armar la direccion anunciada cuando no hay ADVERTISE_URL.
+*/
+pub fn spawn(config: &Config, local_addr: SocketAddr, stop: Arc<AtomicBool>) -> Option<JoinHandle<()>> {
+    let dispatcher = config.register_url.trim().trim_end_matches('/').to_string();
+    if dispatcher.is_empty() {
+        return None;
+    }
+
+    let registration = Registration {
+        address: advertise_url(config, local_addr),
+        weight: config.worker_weight.max(1),
+        heartbeat: true,
+    };
+    let token = config.admin_token.clone();
+    let interval = config.heartbeat_interval;
+    info!("registro", address = registration.address, dispatcher = dispatcher; "Anunciando el worker cada {:?}", interval);
+
+    let thread = thread::Builder::new()
+        .name("worker-heartbeat".to_string())
+        .spawn(move || heartbeat_loop(&dispatcher, &registration, &token, interval, &stop));
+    match thread {
+        Ok(thread) => Some(thread),
+        Err(e) => {
+            error!("registro", "No se pudo iniciar el hilo de heartbeats: {}", e);
+            None
+        }
+    }
+}
+
+fn heartbeat_loop(dispatcher: &str, registration: &Registration, token: &str, interval: Duration, stop: &AtomicBool) {
+    let body = serde_json::to_vec(registration).unwrap_or_default();
+    let mut registered: Option<String> = None; // Id que nos dio el dispatcher
+
+    while !stop.load(Ordering::SeqCst) {
+        let mut wait = interval;
+        match send(dispatcher, "POST", WORKERS_ADMIN_PATH, token, Some(&body)) {
+            Ok((status, body)) if status == 200 || status == 201 => match serde_json::from_slice::<RegistrationAck>(&body) {
+                Ok(ack) => {
+                    // Si el dispatcher se reinicio el id cambia
+                    if registered.as_deref() != Some(ack.id.as_str()) {
+                        info!("registro", dispatcher = dispatcher, id = ack.id; "Registrado en el dispatcher");
+                    }
+                    // Se renueva varias veces antes de que venza, aunque el intervalo configurado sea mayor
+                    if let Some(ttl) = ack.expires_in_secs {
+                        wait = wait.min(Duration::from_secs(ttl) / 3).max(STOP_POLL);
+                    }
+                    registered = Some(ack.id);
+                }
+                Err(e) => warn!("registro", dispatcher = dispatcher; "Respuesta invalida del dispatcher: {}", e),
+            },
+            Ok((status, body)) => {
+                warn!("registro", dispatcher = dispatcher, status = status; "El dispatcher rechazo el registro: {}", String::from_utf8_lossy(&body));
+            }
+            Err(e) => warn!("registro", dispatcher = dispatcher; "No se pudo contactar al dispatcher: {}", e),
+        }
+
+        let deadline = Instant::now() + wait;
+        while Instant::now() < deadline && !stop.load(Ordering::SeqCst) {
+            thread::sleep(STOP_POLL.min(deadline.saturating_duration_since(Instant::now())));
+        }
+    }
+
+    if let Some(id) = registered {
+        let path = format!("{}/{}", WORKERS_ADMIN_PATH, id);
+        match send(dispatcher, "DELETE", &path, token, None) {
+            Ok((status, _)) => info!("registro", id = id, status = status; "Baja en el dispatcher"),
+            Err(e) => warn!("registro", id = id; "No se pudo dar de baja: {}", e),
+        }
+    }
+}
+
+/*
+Direccion con la que el dispatcher llega a este worker.
+Sin ADVERTISE_URL se usa la IP en la que escucha o, si escucha en 0.0.0.0,
+el HOSTNAME del contenedor (docker lo resuelve en la red de compose).
+*/
+fn advertise_url(config: &Config, local_addr: SocketAddr) -> String {
+    let configured = config.advertise_url.trim().trim_end_matches('/');
+    if !configured.is_empty() {
+        return configured.to_string();
+    }
+    let host = if local_addr.ip().is_unspecified() {
+        env::var("HOSTNAME").ok().filter(|h| !h.is_empty()).unwrap_or_else(|| "127.0.0.1".to_string())
+    } else {
+        local_addr.ip().to_string()
+    };
+    format!("http://{}:{}", host, local_addr.port())
+}
+
+/*
+Cliente HTTP/1.1 minimo para hablar con el dispatcher: una solicitud por conexion.
+Solo se usa para el registro, el worker no tiene otro cliente HTTP.
+*/
+fn send(base_url: &str, method: &str, path: &str, token: &str, body: Option<&[u8]>) -> io::Result<(u16, Vec<u8>)> {
+    let host = base_url
+        .strip_prefix("http://")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "REGISTER_URL debe empezar con http://"))?;
+    let addr = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+    let socket_addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no se pudo resolver {}", host)))?;
+
+    let mut stream = TcpStream::connect_timeout(&socket_addr, DISPATCHER_TIMEOUT)?;
+    stream.set_read_timeout(Some(DISPATCHER_TIMEOUT))?;
+    stream.set_write_timeout(Some(DISPATCHER_TIMEOUT))?;
+
+    let body = body.unwrap_or_default();
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        host,
+        body.len()
+    );
+    if !body.is_empty() {
+        head.push_str("Content-Type: application/json\r\n");
+    }
+    if !token.is_empty() {
+        head.push_str(&format!("Authorization: Bearer {}\r\n", token));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+
+    // El dispatcher cierra la conexion despues de responder
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "respuesta HTTP invalida");
+    let header_end = response.windows(4).position(|w| w == b"\r\n\r\n").ok_or_else(invalid)?;
+    let status_line = String::from_utf8_lossy(&response[..header_end]);
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(invalid)?;
+    Ok((status, response[header_end + 4..].to_vec()))
+}
@@ -0,0 +1,98 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+
+use http_common::request::{parse_body, parse_head, Limits, ParseError, ReadError, Request};
+
+// Bytes que se piden al socket en cada lectura
+const READ_CHUNK: usize = 4096;
+
+/*
+Conexion con un cliente.
+Guarda los bytes recibidos que todavia no se han usado, asi una solicitud
+enviada en pipeline queda disponible para la siguiente lectura.
+*/
+pub struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> Connection {
+        Connection { stream, buffer: Vec::new() }
+    }
+
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    pub fn stream_mut(&mut self) -> &mut TcpStream {
+        &mut self.stream
+    }
+
+    // Hay bytes de una solicitud que ya llegaron y no se han procesado
+    pub fn has_buffered(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    /*
+    Lee lo que haya disponible en el socket y lo agrega al buffer.
+    Devuelve 0 si el cliente cerro la conexion.
+    */
+    pub fn fill(&mut self) -> io::Result<usize> {
+        let mut chunk = [0u8; READ_CHUNK];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(read) => {
+                    self.buffer.extend_from_slice(&chunk[..read]);
+                    return Ok(read);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /*
+    Lee una solicitud completa.
+    Primero se espera a tener la linea de solicitud y los headers ("\r\n\r\n"),
+    despues el body segun Content-Length o Transfer-Encoding: chunked.
+    */
+    pub fn read_request(&mut self, limits: &Limits) -> Result<Request, ReadError> {
+        let head = loop {
+            if let Some(head) = parse_head(&self.buffer, limits)? {
+                break head;
+            }
+            self.fill_or_fail()?;
+        };
+
+        let kind = head.body_kind(limits)?;
+        let mut continue_sent = false;
+        let (body, body_len) = loop {
+            if let Some(parsed) = parse_body(&self.buffer[head.len..], kind, limits)? {
+                break parsed;
+            }
+            // Si el cliente espera "100 Continue" se lo enviamos antes de leer el body,
+            // ya sabemos que el tamaño declarado esta dentro del limite
+            if head.expects_continue() && !continue_sent {
+                self.stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+                self.stream.flush()?;
+                continue_sent = true;
+            }
+            self.fill_or_fail()?;
+        };
+
+        self.buffer.drain(..head.len + body_len);
+        Ok(Request::from_parts(head, body)?)
+    }
+
+    // Lee mas bytes; si el cliente cierra a mitad de una solicitud es un error
+    fn fill_or_fail(&mut self) -> Result<(), ReadError> {
+        if self.fill()? == 0 {
+            if self.buffer.is_empty() {
+                return Err(ReadError::Closed);
+            }
+            return Err(ReadError::Parse(ParseError::BadRequest("Solicitud incompleta".to_string())));
+        }
+        Ok(())
+    }
+}
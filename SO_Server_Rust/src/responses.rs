@@ -1,39 +1,69 @@
+use http_common::api::ApiError;
+use http_common::metrics;
+use http_common::response::Response;
+use serde::Serialize;
+
+/*
+Respuestas del worker, todas con body JSON serializado con serde.
+Los errores usan el formato comun ApiError { status, code, message }.
+La version de HTTP, Date, Server y Content-Length los pone Response::to_bytes.
+*/
+
+// Valor del header Server
+pub const SERVER_NAME: &str = concat!("SO_Server_Rust/", env!("CARGO_PKG_VERSION"));
+
 //Formato de respuesta 200
-pub fn http_response_200(body : &str) -> String {
-    let json = format!("{{\"status\":200,\"message\":\"{}\"}}", body);
-    format!(
-        "HTTP/1.0 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
-        json.len(),
-        json
-    )
+pub fn http_response_200<T: Serialize>(body: &T) -> Response {
+    Response::json(200, body)
+}
+
+//Respuesta de /metrics, texto en el formato de Prometheus
+pub fn http_response_metrics(text: String) -> Response {
+    Response::new(200).body(metrics::CONTENT_TYPE, text.into_bytes())
 }
 
 //Formato de respuesta 404
-pub fn http_resonse_404(msg: &str) -> String {
-    let json = format!("{{\"status\" : 404, \"error\" : \"{}\"}}", msg);
-    format!(
-        "HTTP/1.0 404 Not Found\r\nContent-Length: {}\r\nContent-Type: text/plain\r\n\r\n{}",
-        json.len(),
-        json
-    )
+pub fn http_resonse_404(msg: &str) -> Response {
+    Response::error(&ApiError::not_found(msg))
+}
+
+//Respuesta a OPTIONS, sin body
+pub fn http_response_204_allow(allow: &str) -> Response {
+    Response::new(204).header("Allow", allow)
+}
+
+//Formato de respuesta 405, indica los metodos permitidos en el header Allow
+pub fn http_response_405(allow: &str) -> Response {
+    let error = ApiError::method_not_allowed(&format!("Metodo no permitido, use: {}", allow));
+    Response::error(&error).header("Allow", allow)
 }
 
 //Formato de respuesta 400
-pub fn http_resonse_400(msg: &str) -> String {
-    let json = format!("{{\"status\" : 400, \"error\" : \"{}\"}}", msg);
-    format!(
-        "HTTP/1.0 400 Bad Request\r\nContent-Length: {}\r\nContent-Type: text/plain\r\n\r\n{}",
-        json.len(),
-        json
-    )
+pub fn http_resonse_400(msg: &str) -> Response {
+    Response::error(&ApiError::bad_request(msg))
+}
+
+//Formato de respuesta 413
+pub fn http_response_413(msg: &str) -> Response {
+    Response::error(&ApiError::payload_too_large(msg))
+}
+
+//Formato de respuesta 431
+pub fn http_response_431(msg: &str) -> Response {
+    Response::error(&ApiError::headers_too_large(msg))
 }
 
 //Formato de respuesta 500
-pub fn http_response_500(msg: &str) -> String {
-    let json = format!("{{\"status\":500,\"message\":\"{}\"}}", msg);
-    format!(
-        "HTTP/1.0 500 Internal Server Error\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
-        json.len(),
-        json
-    )
-}
\ No newline at end of file
+pub fn http_response_500(msg: &str) -> Response {
+    Response::error(&ApiError::internal(msg))
+}
+
+//Formato de respuesta 503
+pub fn http_response_503(msg: &str) -> Response {
+    Response::error(&ApiError::unavailable(msg)).header("Retry-After", "1")
+}
+
+//Formato de respuesta 504, la tarea se detuvo porque vencio su tiempo limite
+pub fn http_response_504(msg: &str) -> Response {
+    Response::error(&ApiError::gateway_timeout(msg))
+}
@@ -0,0 +1,230 @@
+use std::io::{self, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::process;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::SystemTime;
+
+use http_common::access_log::AccessLog;
+use http_common::trace::Tracer;
+use http_common::{debug, info, warn};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+use crate::config::Config;
+use crate::handle_connection::{handle_connection, Accepted};
+use crate::health::Health;
+use crate::metrics::Metrics;
+use crate::registration;
+use crate::responses::{http_response_503, SERVER_NAME};
+use crate::thread_pool::ThreadPool;
+
+/*
+Worker listo para aceptar conexiones.
+`main` lo corre en el hilo principal; las pruebas lo levantan en un hilo
+aparte con `spawn` en un puerto libre y lo detienen con el ServerHandle.
+*/
+pub struct Server {
+    listener: TcpListener,
+    config: Arc<Config>,
+    health: Arc<Health>,
+    metrics: Arc<Metrics>,
+    tracer: Arc<Tracer>,
+    access_log: Arc<AccessLog>,
+    stop: Arc<AtomicBool>, // Pide al ciclo de accept que termine
+}
+
+// Worker corriendo en segundo plano
+pub struct ServerHandle {
+    trigger: StopTrigger,
+    thread: Option<JoinHandle<bool>>,
+}
+
+// Lo necesario para detener el worker desde otro hilo: el ServerHandle o el de las senales
+#[derive(Clone)]
+struct StopTrigger {
+    addr: SocketAddr,
+    health: Arc<Health>,
+    stop: Arc<AtomicBool>,
+}
+
+impl StopTrigger {
+    // Deja de aceptar conexiones; las que estan en curso terminan en `run`
+    fn begin(&self) {
+        // Drenar hace que las conexiones keep-alive inactivas se cierren
+        self.health.start_draining();
+        self.stop.store(true, Ordering::SeqCst);
+        // El hilo que acepta esta bloqueado en accept, una conexion propia lo despierta
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+impl Server {
+    pub fn bind(addr: impl ToSocketAddrs, config: Config) -> io::Result<Server> {
+        let listener = TcpListener::bind(addr)?;
+        let health = Arc::new(Health::new(config.files_dir.clone()));
+        Ok(Server {
+            listener,
+            tracer: Arc::new(Tracer::new("worker", &config.trace)),
+            access_log: Arc::new(AccessLog::new(&config.access_log)),
+            config: Arc::new(config),
+            health,
+            metrics: Arc::new(Metrics::new()),
+            stop: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    fn stop_trigger(&self) -> io::Result<StopTrigger> {
+        Ok(StopTrigger {
+            addr: self.local_addr()?,
+            health: self.health.clone(),
+            stop: self.stop.clone(),
+        })
+    }
+
+    /*
+    Apagado ordenado con SIGTERM o SIGINT (docker compose down, Ctrl+C):
+      1. /ready responde 503 (draining) y el dispatcher deja de enviar tareas.
+      2. Se siguen atendiendo conexiones durante DRAIN_DELAY_SECS, lo que tarda el dispatcher en notarlo.
+      3. Se deja de aceptar y `run` espera las solicitudes en curso hasta SHUTDOWN_TIMEOUT_SECS.
+    Una segunda senal termina el proceso sin esperar.
+    */
+    pub fn stop_on_signals(&self) -> io::Result<()> {
+        let trigger = self.stop_trigger()?;
+        let drain_delay = self.config.drain_delay;
+        let mut signals = Signals::new([SIGTERM, SIGINT])?;
+
+        thread::Builder::new().name("worker-signals".to_string()).spawn(move || {
+            let mut signals = signals.forever();
+            if let Some(signal) = signals.next() {
+                info!("worker", signal = signal; "Senal recibida, drenando por {:?} antes de dejar de aceptar", drain_delay);
+                trigger.health.start_draining();
+                thread::spawn(move || {
+                    thread::sleep(drain_delay);
+                    trigger.begin();
+                });
+            }
+            if signals.next().is_some() {
+                warn!("worker", "Segunda senal, se termina sin esperar las conexiones");
+                process::exit(1);
+            }
+        })?;
+        Ok(())
+    }
+
+    /*
+    Acepta conexiones y las pasa al pool hasta que se pide detener el worker.
+    Al salir espera a que los hilos del pool terminen sus conexiones, como maximo
+    SHUTDOWN_TIMEOUT_SECS. Devuelve false si quedaron conexiones sin terminar.
+    */
+    pub fn run(self) -> bool {
+        let config = self.config.clone();
+        let health = self.health.clone();
+        let metrics = self.metrics.clone();
+        let tracer = self.tracer.clone();
+        let access_log = self.access_log.clone();
+        // Cada conexion lleva la hora en que se acepto, para medir cuanto espero en la cola
+        let pool = ThreadPool::new(self.config.pool_threads, self.config.pool_queue_depth, move |accepted, pool| {
+            handle_connection(accepted, &config, &health, &metrics, &tracer, &access_log, pool)
+        });
+        info!(
+            "worker",
+            threads = self.config.pool_threads,
+            queue_depth = self.config.pool_queue_depth;
+            "Pool de hilos iniciado"
+        );
+
+        //Con REGISTER_URL el worker se anuncia solo al dispatcher
+        let heartbeat = match self.local_addr() {
+            Ok(addr) => registration::spawn(&self.config, addr, self.stop.clone()),
+            Err(_) => None,
+        };
+
+        for stream in self.listener.incoming() {
+            if self.stop.load(Ordering::SeqCst) {
+                break;
+            }
+            match stream {
+                Ok(stream) => {
+                    debug!("worker", "Conexion entrante aceptada");
+                    if let Err(rejected) = pool.submit(Accepted { stream, at: SystemTime::now() }) {
+                        self.metrics.connections_rejected.inc(&[]);
+                        reject_connection(rejected.stream);
+                    }
+                }
+                Err(e) => {
+                    warn!("worker", "Error al aceptar la conexion: {}", e);
+                }
+            }
+        }
+        info!("worker", "Se dejo de aceptar conexiones");
+
+        //El hilo de heartbeats ve `stop` y se da de baja en el dispatcher
+        if let Some(heartbeat) = heartbeat {
+            let _ = heartbeat.join();
+        }
+
+        let finished = pool.shutdown_timeout(self.config.shutdown_timeout);
+        if finished {
+            info!("worker", "Todas las conexiones terminaron");
+        } else {
+            warn!("worker", "Quedaron conexiones en curso despues de {:?}, se abandonan", self.config.shutdown_timeout);
+        }
+        finished
+    }
+
+    // Corre el worker en un hilo propio
+    pub fn spawn(self) -> io::Result<ServerHandle> {
+        let trigger = self.stop_trigger()?;
+        let thread = thread::Builder::new().name("worker-accept".to_string()).spawn(move || self.run())?;
+        Ok(ServerHandle { trigger, thread: Some(thread) })
+    }
+}
+
+impl ServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.trigger.addr
+    }
+
+    pub fn health(&self) -> &Health {
+        &self.trigger.health
+    }
+
+    /*
+    Detiene el worker y espera a que se cierren sus conexiones.
+    Devuelve false si alguna no termino antes de SHUTDOWN_TIMEOUT_SECS.
+    */
+    pub fn shutdown(mut self) -> bool {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> bool {
+        let Some(thread) = self.thread.take() else {
+            return true;
+        };
+        self.trigger.begin();
+        thread.join().unwrap_or(false)
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/*
+Responde 503 cuando la cola del pool esta llena.
+Se hace en el hilo que acepta para no bloquear mas conexiones.
+*/
+fn reject_connection(mut stream: TcpStream) {
+    warn!("worker", "Cola llena, se rechaza la conexion con 503");
+    let response = http_response_503("Servidor ocupado, intente de nuevo").connection(None);
+    let _ = stream.write_all(&response.to_bytes(SERVER_NAME));
+    let _ = stream.shutdown(Shutdown::Write);
+}
@@ -0,0 +1,131 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use http_common::error;
+
+/*
+    Pool de hilos acotado para atender conexiones.
+    Tiene un numero fijo de hilos y una cola de tamaño limitado; cuando la
+    cola esta llena `submit` devuelve el trabajo para que el llamador lo rechace.
+*/
+pub struct ThreadPool<T: Send + 'static> {
+    sender: Option<SyncSender<T>>,
+    threads: Vec<JoinHandle<()>>,
+    state: Arc<PoolState>,
+}
+
+// Estado del pool visible para los trabajos que se estan ejecutando
+#[derive(Debug, Default)]
+pub struct PoolState {
+    queued: AtomicUsize, // Trabajos aceptados que ningun hilo ha tomado todavia
+}
+
+impl PoolState {
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+}
+
+impl<T: Send + 'static> ThreadPool<T> {
+    /*
+    Crea el pool con `size` hilos y una cola de `queue_depth` trabajos pendientes.
+    Cada hilo toma trabajos de la cola y los procesa con `handler`.
+    */
+    pub fn new<F>(size: usize, queue_depth: usize, handler: F) -> ThreadPool<T>
+    where
+        F: Fn(T, &PoolState) + Send + Sync + 'static,
+    {
+        assert!(size > 0, "El pool necesita al menos un hilo");
+
+        let (sender, receiver) = mpsc::sync_channel::<T>(queue_depth);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handler = Arc::new(handler);
+        let state = Arc::new(PoolState::default());
+
+        let threads = (0..size)
+            .map(|id| {
+                let receiver = Arc::clone(&receiver);
+                let handler = Arc::clone(&handler);
+                let state = Arc::clone(&state);
+                thread::Builder::new()
+                    .name(format!("pool-{}", id))
+                    .spawn(move || worker_loop(receiver, handler, state))
+                    .expect("No se pudo crear el hilo del pool")
+            })
+            .collect();
+
+        ThreadPool { sender: Some(sender), threads, state }
+    }
+
+    /*
+    Encola un trabajo sin bloquear.
+    Si la cola esta llena (o el pool ya se cerro) se devuelve el trabajo en el Err.
+    */
+    pub fn submit(&self, job: T) -> Result<(), T> {
+        let Some(sender) = &self.sender else {
+            return Err(job);
+        };
+
+        // Se cuenta antes de enviar para que el hilo que lo reciba nunca vea el contador en negativo
+        self.state.queued.fetch_add(1, Ordering::SeqCst);
+        match sender.try_send(job) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(job)) | Err(TrySendError::Disconnected(job)) => {
+                self.state.queued.fetch_sub(1, Ordering::SeqCst);
+                Err(job)
+            }
+        }
+    }
+
+    /*
+    Cierra la cola y espera a que los hilos terminen lo pendiente, como al soltar el pool,
+    pero como maximo `timeout`. Devuelve false si quedaron hilos ocupados; esos hilos
+    se abandonan y terminan cuando termine el proceso.
+    */
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> bool {
+        drop(self.sender.take());
+        let deadline = Instant::now() + timeout;
+        while self.threads.iter().any(|t| !t.is_finished()) {
+            if Instant::now() >= deadline {
+                self.threads.clear();
+                return false;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        true
+    }
+}
+
+impl<T: Send + 'static> Drop for ThreadPool<T> {
+    // Cerramos la cola y esperamos a que los hilos terminen lo pendiente
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for handle in self.threads.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn worker_loop<T, F>(receiver: Arc<Mutex<Receiver<T>>>, handler: Arc<F>, state: Arc<PoolState>)
+where
+    F: Fn(T, &PoolState),
+{
+    loop {
+        // El lock se suelta al terminar la sentencia, antes de procesar el trabajo
+        let job = receiver.lock().unwrap().recv();
+        match job {
+            Ok(job) => {
+                state.queued.fetch_sub(1, Ordering::SeqCst);
+                // Un panic en una conexion no debe matar el hilo del pool
+                if panic::catch_unwind(AssertUnwindSafe(|| handler(job, &state))).is_err() {
+                    error!("pool", "Un trabajo termino con panic");
+                }
+            }
+            Err(_) => break, // El sender se cerro, no hay mas trabajo
+        }
+    }
+}
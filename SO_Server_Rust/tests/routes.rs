@@ -0,0 +1,337 @@
+// Pruebas del worker solo, con solicitudes HTTP escritas a mano sobre TCP
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde_json::Value;
+use so_server_rust::{Config, Server, ServerHandle};
+
+struct TestWorker {
+    handle: Option<ServerHandle>,
+    files_dir: PathBuf,
+}
+
+impl TestWorker {
+    fn start(name: &str) -> TestWorker {
+        let files_dir = std::env::temp_dir().join(format!("so_server_rust-test-{}-{}", std::process::id(), name));
+        let mut config = Config::from_env();
+        config.pool_threads = 2;
+        config.files_dir = files_dir.clone();
+        let handle = Server::bind("127.0.0.1:0", config).unwrap().spawn().unwrap();
+        TestWorker { handle: Some(handle), files_dir }
+    }
+
+    fn connect(&self) -> TcpStream {
+        let stream = TcpStream::connect(self.handle.as_ref().unwrap().local_addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream
+    }
+
+    // Envia una solicitud en una conexion nueva y devuelve la respuesta
+    fn send(&self, raw: &str) -> Reply {
+        let mut stream = self.connect();
+        stream.write_all(raw.as_bytes()).unwrap();
+        read_reply(&mut stream)
+    }
+}
+
+impl Drop for TestWorker {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.shutdown();
+        }
+        let _ = std::fs::remove_dir_all(&self.files_dir);
+    }
+}
+
+struct Reply {
+    status: u16,
+    head: String,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn header(&self, name: &str) -> Option<String> {
+        self.head.lines().skip(1).find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim().to_string())
+        })
+    }
+
+    fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap()
+    }
+}
+
+// Lee una respuesta completa usando Content-Length
+fn read_reply(stream: &mut TcpStream) -> Reply {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 1024];
+    let head_end = loop {
+        if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        let read = stream.read(&mut chunk).unwrap();
+        assert!(read > 0, "El worker cerro sin responder");
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = String::from_utf8(buffer[..head_end].to_vec()).unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    let mut reply = Reply { status, head, body: Vec::new() };
+    let length: usize = reply.header("content-length").map(|v| v.parse().unwrap()).unwrap_or(0);
+
+    while buffer.len() < head_end + length {
+        let read = stream.read(&mut chunk).unwrap();
+        assert!(read > 0, "El worker cerro a mitad del body");
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    reply.body = buffer[head_end..head_end + length].to_vec();
+    reply
+}
+
+#[test]
+fn ping_and_ready_return_json() {
+    let worker = TestWorker::start("ping");
+
+    let reply = worker.send("GET /ping HTTP/1.1\r\nHost: test\r\n\r\n");
+    assert_eq!(reply.status, 200);
+    assert_eq!(reply.json()["status"], "ok");
+    assert!(reply.header("date").is_some());
+    assert!(reply.header("server").unwrap().starts_with("SO_Server_Rust/"));
+
+    let reply = worker.send("GET /ready HTTP/1.1\r\nHost: test\r\n\r\n");
+    assert_eq!(reply.status, 200);
+    assert_eq!(reply.json()["ready"], true);
+    assert_eq!(reply.json()["storage_writable"], true);
+}
+
+#[test]
+fn unknown_method_gets_405_with_allow() {
+    let worker = TestWorker::start("405");
+
+    let reply = worker.send("PUT /reverse?text=a HTTP/1.1\r\nHost: test\r\nContent-Length: 0\r\n\r\n");
+    assert_eq!(reply.status, 405);
+    assert_eq!(reply.header("allow").unwrap(), "GET, HEAD, OPTIONS");
+    assert_eq!(reply.json()["code"], "method_not_allowed");
+
+    let reply = worker.send("OPTIONS /createfile HTTP/1.1\r\nHost: test\r\n\r\n");
+    assert_eq!(reply.status, 204);
+    assert!(reply.header("allow").unwrap().contains("POST"));
+}
+
+#[test]
+fn head_has_length_but_no_body() {
+    let worker = TestWorker::start("head");
+
+    let mut stream = worker.connect();
+    stream.write_all(b"HEAD /timestamp HTTP/1.1\r\nHost: test\r\n\r\nGET /reverse?text=ab HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+
+    let head = read_reply_head_only(&mut stream);
+    assert!(head.contains("Content-Length: "));
+    // La siguiente respuesta empieza justo despues de los headers del HEAD
+    let reply = read_reply(&mut stream);
+    assert_eq!(reply.json()["result"], "ba");
+}
+
+#[test]
+fn head_never_runs_the_legacy_get_aliases() {
+    let worker = TestWorker::start("head-legacy");
+    std::fs::create_dir_all(&worker.files_dir).unwrap();
+    std::fs::write(worker.files_dir.join("prueba.txt"), "hola").unwrap();
+
+    let mut stream = worker.connect();
+    stream.write_all(b"HEAD /deletefile?name=prueba HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+    let head = read_reply_head_only(&mut stream);
+    assert!(head.starts_with("HTTP/1.1 405"), "{}", head);
+    assert!(head.contains("Allow: GET, DELETE, OPTIONS"), "{}", head);
+    assert!(worker.files_dir.join("prueba.txt").exists());
+
+    stream.write_all(b"HEAD /createfile?name=otro&content=x HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+    let head = read_reply_head_only(&mut stream);
+    assert!(head.starts_with("HTTP/1.1 405"), "{}", head);
+    assert!(!worker.files_dir.join("otro.txt").exists());
+}
+
+#[test]
+fn keeps_the_connection_alive_between_requests() {
+    let worker = TestWorker::start("keepalive");
+
+    let mut stream = worker.connect();
+    for text in ["uno", "dos", "tres"] {
+        stream.write_all(format!("GET /reverse?text={} HTTP/1.1\r\nHost: test\r\n\r\n", text).as_bytes()).unwrap();
+        let reply = read_reply(&mut stream);
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.header("connection").unwrap(), "keep-alive");
+        assert_eq!(reply.json()["result"], text.chars().rev().collect::<String>());
+    }
+}
+
+#[test]
+fn creates_and_deletes_files_in_the_configured_dir() {
+    let worker = TestWorker::start("files");
+
+    let body = "name=prueba&content=hola%20mundo";
+    let reply = worker.send(&format!(
+        "POST /createfile HTTP/1.1\r\nHost: test\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    ));
+    assert_eq!(reply.status, 200, "{}", String::from_utf8_lossy(&reply.body));
+    assert_eq!(std::fs::read_to_string(worker.files_dir.join("prueba.txt")).unwrap(), "hola mundo");
+
+    let reply = worker.send("DELETE /deletefile?name=prueba HTTP/1.1\r\nHost: test\r\n\r\n");
+    assert_eq!(reply.status, 200);
+    assert!(!worker.files_dir.join("prueba.txt").exists());
+}
+
+#[test]
+fn rejects_oversized_headers_with_431() {
+    let worker = TestWorker::start("431");
+
+    let big = "a".repeat(16 * 1024);
+    let reply = worker.send(&format!("GET /ping HTTP/1.1\r\nHost: test\r\nX-Big: {}\r\n\r\n", big));
+    assert_eq!(reply.status, 431);
+    assert_eq!(reply.header("connection").unwrap(), "close");
+}
+
+// Lee solo los headers de una respuesta a HEAD
+fn read_reply_head_only(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+#[test]
+fn shutdown_waits_for_in_flight_requests() {
+    let mut worker = TestWorker::start("shutdown");
+    let mut stream = worker.connect();
+    stream.write_all(b"GET /sleep?seconds=1 HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+    // Se espera a que el pool tome la conexion antes de apagar
+    std::thread::sleep(Duration::from_millis(200));
+
+    let addr = worker.handle.as_ref().unwrap().local_addr();
+    let finished = worker.handle.take().unwrap().shutdown();
+    assert!(finished);
+
+    let reply = read_reply(&mut stream);
+    assert_eq!(reply.status, 200);
+    assert_eq!(reply.json()["seconds"], 1);
+    assert!(TcpStream::connect(addr).is_err(), "el worker sigue aceptando conexiones");
+}
+
+#[test]
+fn created_files_leave_no_temporary_files() {
+    let worker = TestWorker::start("atomic");
+    let reply = worker.send("POST /createfile HTTP/1.1\r\nHost: test\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 25\r\n\r\nname=atomico&content=hola");
+    assert_eq!(reply.status, 200, "{}", String::from_utf8_lossy(&reply.body));
+
+    let names: Vec<String> = std::fs::read_dir(&worker.files_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(names, ["atomico.txt"]);
+    assert_eq!(std::fs::read_to_string(worker.files_dir.join("atomico.txt")).unwrap(), "hola");
+}
+
+#[test]
+fn concurrent_creates_of_the_same_file_only_succeed_once() {
+    let worker = TestWorker::start("create-race");
+    let addr = worker.handle.as_ref().unwrap().local_addr();
+
+    for round in 0..10 {
+        let name = format!("carrera{}", round);
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(2));
+        let clients: Vec<_> = ["uno", "dos"]
+            .into_iter()
+            .map(|content| {
+                let (barrier, name) = (barrier.clone(), name.clone());
+                std::thread::spawn(move || {
+                    let mut stream = TcpStream::connect(addr).unwrap();
+                    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+                    let request = format!("GET /createfile?name={}&content={} HTTP/1.1\r\nHost: test\r\n\r\n", name, content);
+                    barrier.wait();
+                    stream.write_all(request.as_bytes()).unwrap();
+                    (content, read_reply(&mut stream).status)
+                })
+            })
+            .collect();
+        let results: Vec<(&str, u16)> = clients.into_iter().map(|client| client.join().unwrap()).collect();
+
+        // Exactamente uno gana y el archivo tiene su contenido; el otro recibe el error de que ya existe
+        let winners: Vec<&str> = results.iter().filter(|(_, status)| *status == 200).map(|(content, _)| *content).collect();
+        assert_eq!(winners.len(), 1, "{:?}", results);
+        assert!(results.iter().all(|(_, status)| *status == 200 || *status == 500), "{:?}", results);
+        assert_eq!(std::fs::read_to_string(worker.files_dir.join(format!("{}.txt", name))).unwrap(), winners[0]);
+    }
+
+    let temporaries = std::fs::read_dir(&worker.files_dir)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().starts_with('.'))
+        .count();
+    assert_eq!(temporaries, 0);
+}
+
+#[test]
+fn stops_cpu_bound_work_when_the_deadline_passes() {
+    let worker = TestWorker::start("deadline");
+
+    let started = std::time::Instant::now();
+    let reply = worker.send("GET /fibonacci?num=70 HTTP/1.1\r\nHost: test\r\nX-Deadline-Ms: 200\r\n\r\n");
+    assert_eq!(reply.status, 504);
+    assert_eq!(reply.json()["code"], "gateway_timeout");
+    assert!(started.elapsed() < Duration::from_secs(2), "tardo {:?}", started.elapsed());
+
+    // Con tiempo suficiente el resultado no cambia
+    let reply = worker.send("GET /fibonacci?num=20 HTTP/1.1\r\nHost: test\r\nX-Deadline-Ms: 5000\r\n\r\n");
+    assert_eq!(reply.status, 200);
+    assert_eq!(reply.json()["result"], 6765);
+}
+
+#[test]
+fn metrics_count_requests_and_stored_files() {
+    let worker = TestWorker::start("metrics");
+
+    worker.send("GET /ping HTTP/1.1\r\nHost: test\r\n\r\n");
+    worker.send("GET /no-existe/123 HTTP/1.1\r\nHost: test\r\n\r\n");
+    worker.send("BREW /ping HTTP/1.1\r\nHost: test\r\n\r\n");
+    let body = "name=metricas&content=hola";
+    worker.send(&format!(
+        "POST /createfile HTTP/1.1\r\nHost: test\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    ));
+
+    let reply = worker.send("GET /metrics HTTP/1.1\r\nHost: test\r\n\r\n");
+    assert_eq!(reply.status, 200);
+    assert!(reply.header("Content-Type").unwrap().starts_with("text/plain; version=0.0.4"));
+    let text = String::from_utf8(reply.body).unwrap();
+    assert!(text.contains("http_requests_total{route=\"/ping\",method=\"GET\",status=\"200\"} 1"), "{}", text);
+    // Las rutas desconocidas se agrupan
+    assert!(text.contains("http_requests_total{route=\"other\",method=\"GET\",status=\"404\"} 1"), "{}", text);
+    // Igual que los metodos que no son de la lista
+    assert!(text.contains("http_requests_total{route=\"/ping\",method=\"other\",status=\"405\"} 1"), "{}", text);
+    assert!(text.contains("http_request_duration_seconds_bucket{route=\"/ping\",status=\"200\",le=\"+Inf\"} 1"), "{}", text);
+    assert!(text.contains("worker_file_operations_total{operation=\"create\",result=\"ok\"} 1"), "{}", text);
+    assert!(text.contains("worker_files_stored 1"), "{}", text);
+}
+
+#[test]
+fn echoes_the_request_id_or_generates_one() {
+    let worker = TestWorker::start("request-id");
+
+    let reply = worker.send("GET /ping HTTP/1.1\r\nHost: test\r\nX-Request-Id: tarea-42\r\n\r\n");
+    assert_eq!(reply.header("x-request-id").as_deref(), Some("tarea-42"));
+
+    // Tambien las solicitudes que no se pudieron leer llevan un id
+    let reply = worker.send("GET /ping HTTP/1.1\r\nHost: test\r\nX-Request-Id: {mal}\r\n\r\n");
+    assert_eq!(reply.header("x-request-id").unwrap().len(), 32);
+    let reply = worker.send("BASURA\r\n\r\n");
+    assert_eq!(reply.status, 400);
+    assert!(reply.header("x-request-id").is_some());
+}
@@ -0,0 +1,314 @@
+/*
+Access log: una linea por solicitud respondida, para herramientas de analisis de logs que
+leen el formato de Apache/nginx. Es aparte de los logs de log.rs, que son para diagnostico.
+- ACCESS_LOG: vacio lo desactiva, stdout escribe en la salida estandar y cualquier otro valor
+  es la ruta de un archivo.
+- ACCESS_LOG_FORMAT: common, combined, extended o json.
+- ACCESS_LOG_MAX_BYTES: al pasar ese tamaño el archivo se rota a <archivo>.1, <archivo>.2, ...
+  guardando ACCESS_LOG_MAX_FILES archivos viejos; 0 no rota.
+Las lineas se escriben en un hilo aparte con una cola acotada, como las trazas: quien atiende
+la solicitud (un hilo del pool o una tarea de Tokio) nunca espera al disco.
+
+common:   10.0.0.5 - - [14/Nov/2023:22:13:20 +0000] "GET /reverse?text=abc HTTP/1.1" 200 27
+combined: 10.0.0.5 - - [14/Nov/2023:22:13:20 +0000] "GET /reverse?text=abc HTTP/1.1" 200 27 "-" "curl/8.5.0"
+extended: 10.0.0.5 - - [14/Nov/2023:22:13:20 +0000] "GET /reverse?text=abc HTTP/1.1" 200 27 "-" "curl/8.5.0" 3 "worker1" "ab12"
+common y combined son exactamente los de Apache, para los parsers estrictos. extended agrega a
+combined la duracion en milisegundos, el worker que atendio la tarea (- en los workers) y el
+request_id; json tiene todos los campos.
+*/
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+
+use crate::log::timestamp;
+use crate::request::Request;
+use crate::response::{civil_date, MONTHS};
+use crate::{info, warn};
+
+// Lineas que pueden esperar a ser escritas; si se llena se descartan y se avisa en el log
+const QUEUE_CAPACITY: usize = 8192;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessLogFormat {
+    Common,
+    Combined, // common mas Referer y User-Agent
+    Extended, // combined mas duracion, worker y request_id
+    Json,
+}
+
+// Configuracion del access log, ver Config de cada servicio
+#[derive(Debug, Clone)]
+pub struct AccessLogSettings {
+    pub target: String,   // Vacio, stdout o la ruta del archivo
+    pub format: AccessLogFormat,
+    pub max_bytes: u64,   // Tamaño del archivo que provoca la rotacion, 0 no rota
+    pub max_files: usize, // Archivos rotados que se guardan
+}
+
+// Lo que se registra de una solicitud
+#[derive(Debug)]
+pub struct AccessEntry<'a> {
+    pub client: Option<IpAddr>,
+    pub time: SystemTime,             // Cuando empezo a atenderse
+    pub request: Option<&'a Request>, // None si no se pudo leer
+    pub status: u16,
+    pub bytes: usize,                 // Body de la respuesta, sin headers
+    pub duration: Duration,
+    pub worker: Option<&'a str>,      // Solo en el dispatcher
+    pub request_id: &'a str,
+}
+
+/*
+Arma la linea de una solicitud, con el salto de linea al final.
+En los formatos de texto los valores entre comillas escapan comillas, barras y caracteres de control.
+*/
+pub fn format_entry(format: AccessLogFormat, entry: &AccessEntry) -> String {
+    let client = entry.client.map(|ip| ip.to_string()).unwrap_or_else(|| "-".to_string());
+    let header = |name: &str| entry.request.and_then(|r| r.header(name)).unwrap_or("-");
+    let duration_ms = entry.duration.as_millis();
+    let worker = entry.worker.unwrap_or("-");
+
+    if format == AccessLogFormat::Json {
+        let (method, target, version) = match entry.request {
+            Some(request) => (request.method.as_str(), request.target.as_str(), request.version.as_str()),
+            None => ("-", "-", "-"),
+        };
+        let line = json!({
+            "time": timestamp(entry.time),
+            "client": client,
+            "method": method,
+            "target": target,
+            "protocol": version,
+            "status": entry.status,
+            "bytes": entry.bytes,
+            "duration_ms": duration_ms as u64,
+            "referer": header("referer"),
+            "user_agent": header("user-agent"),
+            "worker": worker,
+            "request_id": entry.request_id,
+        });
+        return format!("{}\n", line);
+    }
+
+    // Una solicitud que no se pudo leer queda como "-", igual que en Apache
+    let request_line = match entry.request {
+        Some(request) => format!("{} {} {}", request.method, request.target, request.version),
+        None => "-".to_string(),
+    };
+    let bytes = if entry.bytes == 0 { "-".to_string() } else { entry.bytes.to_string() };
+    let mut line = format!("{} - - [{}] \"{}\" {} {}", client, clf_time(entry.time), escape(&request_line), entry.status, bytes);
+    if format != AccessLogFormat::Common {
+        line.push_str(&format!(" \"{}\" \"{}\"", escape(header("referer")), escape(header("user-agent"))));
+    }
+    if format == AccessLogFormat::Extended {
+        line.push_str(&format!(" {} \"{}\" \"{}\"", duration_ms, escape(worker), escape(entry.request_id)));
+    }
+    line.push('\n');
+    line
+}
+
+// 14/Nov/2023:22:13:20 +0000, siempre en UTC
+fn clf_time(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (year, month, day) = civil_date(secs / 86_400);
+    let rem = secs % 86_400;
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Escribe el access log de un servicio; se comparte entre todos los hilos o tareas
+pub struct AccessLog {
+    format: AccessLogFormat,
+    sender: Option<SyncSender<String>>, // None: access log desactivado
+    dropped: Arc<AtomicU64>,            // Lineas descartadas con la cola llena, las reporta el hilo
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+enum Output {
+    Stdout,
+    File(FileOutput),
+}
+
+struct FileOutput {
+    path: String,
+    max_bytes: u64,
+    max_files: usize,
+    file: Option<File>, // None si no se pudo abrir, se vuelve a intentar en la siguiente linea
+    size: u64,
+    failing: bool,      // Para avisar una vez cuando falla y otra cuando se recupera
+}
+
+impl AccessLog {
+    pub fn new(settings: &AccessLogSettings) -> AccessLog {
+        let mut output = match settings.target.as_str() {
+            "" => return AccessLog::disabled(settings.format),
+            "stdout" | "-" => Output::Stdout,
+            path => Output::File(FileOutput {
+                path: path.to_string(),
+                max_bytes: settings.max_bytes,
+                max_files: settings.max_files,
+                file: None,
+                size: 0,
+                failing: false,
+            }),
+        };
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let reported = dropped.clone();
+        let thread = thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || write_loop(&mut output, &reported, receiver));
+        match thread {
+            Ok(thread) => AccessLog { format: settings.format, sender: Some(sender), dropped, thread: Mutex::new(Some(thread)) },
+            Err(e) => {
+                warn!("access_log", "No se pudo iniciar el hilo del access log, queda desactivado: {}", e);
+                AccessLog::disabled(settings.format)
+            }
+        }
+    }
+
+    fn disabled(format: AccessLogFormat) -> AccessLog {
+        AccessLog { format, sender: None, dropped: Arc::new(AtomicU64::new(0)), thread: Mutex::new(None) }
+    }
+
+    // Arma la linea y la deja en la cola; no espera a que se escriba
+    pub fn write(&self, entry: &AccessEntry) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        if let Err(TrySendError::Full(_)) = sender.try_send(format_entry(self.format, entry)) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// Al soltarse escribe lo que quedaba en la cola
+impl Drop for AccessLog {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn write_loop(output: &mut Output, dropped: &AtomicU64, receiver: Receiver<String>) {
+    for line in receiver {
+        match output {
+            Output::Stdout => {
+                let _ = io::stdout().lock().write_all(line.as_bytes());
+            }
+            Output::File(file) => file.write(&line),
+        }
+        let lost = dropped.swap(0, Ordering::Relaxed);
+        if lost > 0 {
+            warn!("access_log", lines = lost; "Se descartaron lineas del access log, el disco no da abasto");
+        }
+    }
+}
+
+impl FileOutput {
+    fn write(&mut self, line: &str) {
+        match self.try_write(line) {
+            Ok(()) if self.failing => {
+                info!("access_log", file = self.path; "Se volvio a escribir el access log");
+                self.failing = false;
+            }
+            Ok(()) => {}
+            Err(e) => {
+                if !self.failing {
+                    warn!("access_log", file = self.path; "No se pudo escribir el access log: {}", e);
+                    self.failing = true;
+                }
+                self.file = None;
+            }
+        }
+    }
+
+    fn try_write(&mut self, line: &str) -> io::Result<()> {
+        if self.file.is_none() {
+            let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            self.size = file.metadata()?.len();
+            self.file = Some(file);
+        }
+        if self.max_bytes > 0 && self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        if let Some(file) = &mut self.file {
+            file.write_all(line.as_bytes())?;
+            self.size += line.len() as u64;
+        }
+        Ok(())
+    }
+
+    // <archivo>.N se borra, cada <archivo>.i pasa a .i+1 y el actual a .1
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(format!("{}.{}", self.path, self.max_files));
+            for i in (1..self.max_files).rev() {
+                let _ = fs::rename(format!("{}.{}", self.path, i), format!("{}.{}", self.path, i + 1));
+            }
+            fs::rename(&self.path, format!("{}.1", self.path))?;
+        }
+        self.file = Some(OpenOptions::new().create(true).append(true).open(&self.path)?);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AccessLogFormat, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "common" | "clf" => Ok(AccessLogFormat::Common),
+            "combined" => Ok(AccessLogFormat::Combined),
+            "extended" => Ok(AccessLogFormat::Extended),
+            "json" => Ok(AccessLogFormat::Json),
+            _ => Err(format!("formato de access log invalido '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for AccessLogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AccessLogFormat::Common => "common",
+            AccessLogFormat::Combined => "combined",
+            AccessLogFormat::Extended => "extended",
+            AccessLogFormat::Json => "json",
+        })
+    }
+}
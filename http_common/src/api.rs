@@ -0,0 +1,88 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/*
+Formato comun de los errores que devuelven el dispatcher y los workers.
+`status` repite el codigo HTTP, `code` es un identificador estable para
+programas y `message` es el texto para personas.
+*/
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiError {
+    pub status: u16,
+    pub code: String,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: u16, code: &str, message: &str) -> ApiError {
+        ApiError {
+            status,
+            code: code.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn bad_request(message: &str) -> ApiError {
+        ApiError::new(400, "bad_request", message)
+    }
+
+    pub fn unauthorized(message: &str) -> ApiError {
+        ApiError::new(401, "unauthorized", message)
+    }
+
+    pub fn forbidden(message: &str) -> ApiError {
+        ApiError::new(403, "forbidden", message)
+    }
+
+    pub fn not_found(message: &str) -> ApiError {
+        ApiError::new(404, "not_found", message)
+    }
+
+    pub fn method_not_allowed(message: &str) -> ApiError {
+        ApiError::new(405, "method_not_allowed", message)
+    }
+
+    pub fn conflict(message: &str) -> ApiError {
+        ApiError::new(409, "conflict", message)
+    }
+
+    pub fn payload_too_large(message: &str) -> ApiError {
+        ApiError::new(413, "payload_too_large", message)
+    }
+
+    pub fn unprocessable(message: &str) -> ApiError {
+        ApiError::new(422, "unprocessable_entity", message)
+    }
+
+    pub fn headers_too_large(message: &str) -> ApiError {
+        ApiError::new(431, "headers_too_large", message)
+    }
+
+    pub fn internal(message: &str) -> ApiError {
+        ApiError::new(500, "internal_error", message)
+    }
+
+    pub fn bad_gateway(message: &str) -> ApiError {
+        ApiError::new(502, "bad_gateway", message)
+    }
+
+    pub fn unavailable(message: &str) -> ApiError {
+        ApiError::new(503, "service_unavailable", message)
+    }
+
+    pub fn gateway_timeout(message: &str) -> ApiError {
+        ApiError::new(504, "gateway_timeout", message)
+    }
+
+    // Serializa el error; con estos campos serde_json no puede fallar
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.status, self.code, self.message)
+    }
+}
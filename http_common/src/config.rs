@@ -0,0 +1,209 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::process;
+use std::str::FromStr;
+
+/*
+Fuentes de configuracion de un servicio. Cada opcion tiene una clave, por ejemplo
+`listen_addr`, que se busca en este orden:
+  1. flag de linea de comandos   --listen-addr 127.0.0.1:9000  (o --listen-addr=...)
+  2. variable de entorno         LISTEN_ADDR
+  3. archivo TOML                listen_addr = "127.0.0.1:9000"
+  4. valor por defecto del servicio
+El archivo se indica con --config <ruta> o CONFIG_FILE y es opcional. Las claves
+de primer nivel valen para los dos servicios y las de la seccion del servicio
+([worker] o [dispatcher]) las reemplazan.
+*/
+#[derive(Debug, Default)]
+pub struct Settings {
+    cli: HashMap<String, String>,                       // Flags, con la clave ya en snake case
+    file: HashMap<String, String>,                      // Valores del archivo TOML
+    file_path: Option<String>,                          // Ruta del archivo, solo para los mensajes
+    help: bool,                                         // Se paso --help
+    used: RefCell<Vec<(String, String, &'static str)>>, // (clave, valor final, origen) de cada opcion leida
+}
+
+impl Settings {
+    // Solo variables de entorno y valores por defecto, como antes de que existieran los flags
+    pub fn from_env() -> Settings {
+        Settings::default()
+    }
+
+    /*
+    Lee los flags y, si se indico, el archivo TOML.
+    `section` es la tabla del archivo con los valores propios del servicio.
+    */
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I, section: &str) -> Result<Settings, String> {
+        let mut settings = Settings::default();
+        let mut config_file = env::var("CONFIG_FILE").ok().filter(|path| !path.trim().is_empty());
+
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                settings.help = true;
+                continue;
+            }
+            let Some(flag) = arg.strip_prefix("--") else {
+                return Err(format!("Argumento inesperado '{}'", arg));
+            };
+            let (name, value) = match flag.split_once('=') {
+                Some((name, value)) => (name.to_string(), value.to_string()),
+                // Un flag sin valor (por ejemplo --legacy-get-aliases) se toma como true
+                None => match args.next_if(|next| !next.starts_with("--")) {
+                    Some(value) => (flag.to_string(), value),
+                    None => (flag.to_string(), "true".to_string()),
+                },
+            };
+            if name == "config" {
+                config_file = Some(value);
+            } else {
+                settings.cli.insert(name.replace('-', "_"), value);
+            }
+        }
+
+        if let Some(path) = config_file {
+            settings.file = read_toml(&path, section)?;
+            settings.file_path = Some(path);
+        }
+        Ok(settings)
+    }
+
+    /*
+    Valor de una opcion segun la precedencia. Un valor que no se puede parsear
+    se ignora con un aviso y se pasa a la siguiente fuente.
+    */
+    pub fn get<T: FromStr + ToString>(&self, key: &str, default: T) -> T {
+        let env_name = key.to_ascii_uppercase();
+        let sources = [
+            ("flag", self.cli.get(key).cloned()),
+            ("env", env::var(&env_name).ok()),
+            ("archivo", self.file.get(key).cloned()),
+        ];
+
+        for (origin, value) in sources {
+            let Some(value) = value else { continue };
+            match value.trim().parse::<T>() {
+                Ok(parsed) => {
+                    self.record(key, parsed.to_string(), origin);
+                    return parsed;
+                }
+                Err(_) => crate::warn!("config", key = key, origin = origin; "Valor invalido '{}', se ignora", value),
+            }
+        }
+        self.record(key, default.to_string(), "defecto");
+        default
+    }
+
+    pub fn help_requested(&self) -> bool {
+        self.help
+    }
+
+    /*
+    Revisa que todos los flags correspondan a una opcion leida.
+    Un flag desconocido es un error; una clave desconocida en el archivo solo un aviso,
+    porque el mismo archivo puede tener opciones del otro servicio.
+    */
+    pub fn check_unused(&self) -> Result<(), String> {
+        let used = self.used.borrow();
+        let is_used = |key: &String| used.iter().any(|(k, _, _)| k == key);
+
+        for key in self.file.keys().filter(|k| !is_used(k)) {
+            crate::warn!("config", key = key, file = self.file_path.as_deref().unwrap_or("el archivo"); "La clave no se usa en este servicio");
+        }
+
+        let mut unknown: Vec<String> = self.cli.keys().filter(|k| !is_used(k)).map(|k| format!("--{}", k.replace('_', "-"))).collect();
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        unknown.sort();
+        Err(format!("Opciones desconocidas: {}", unknown.join(", ")))
+    }
+
+    // Texto de --help con cada opcion leida y el valor con que quedo
+    pub fn usage(&self, program: &str) -> String {
+        let mut text = format!(
+            "Uso: {} [--config <archivo.toml>] [--opcion valor]...\n\
+             Precedencia: flag > variable de entorno > archivo > valor por defecto\n\n",
+            program
+        );
+        for (key, value, origin) in self.used.borrow().iter() {
+            let flag = format!("--{}", key.replace('_', "-"));
+            text.push_str(&format!("  {:<34} {:<32} = {} ({})\n", flag, key.to_ascii_uppercase(), value, origin));
+        }
+        text
+    }
+
+    fn record(&self, key: &str, value: String, origin: &'static str) {
+        let mut used = self.used.borrow_mut();
+        used.retain(|(k, _, _)| k != key);
+        used.push((key.to_string(), value, origin));
+    }
+}
+
+/*
+Punto de entrada de los binarios: lee los argumentos del proceso, arma la configuracion
+con `build` y termina el proceso si hay un error o si se pidio --help.
+*/
+pub fn load<C>(program: &str, section: &str, build: impl FnOnce(&Settings) -> C) -> C {
+    let settings = match Settings::from_args(env::args().skip(1), section) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("[Config] {}", e);
+            process::exit(2);
+        }
+    };
+
+    let config = build(&settings);
+
+    if settings.help_requested() {
+        print!("{}", settings.usage(program));
+        process::exit(0);
+    }
+    if let Err(e) = settings.check_unused() {
+        eprintln!("[Config] {}. Use --help para ver las opciones.", e);
+        process::exit(2);
+    }
+    config
+}
+
+/*
+Lee el archivo TOML y lo aplana a texto, igual que llegan los flags y las variables.
+Los arreglos se unen con comas: worker_addresses = ["http://a:7878", "http://b:7878"].
+*/
+fn read_toml(path: &str, section: &str) -> Result<HashMap<String, String>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("No se pudo leer el archivo de configuracion {}: {}", path, e))?;
+    let table: toml::Table = text.parse().map_err(|e| format!("Archivo de configuracion {} invalido: {}", path, e))?;
+
+    let mut values = HashMap::new();
+    let mut section_values = HashMap::new();
+    for (key, value) in table {
+        match value {
+            toml::Value::Table(inner) if key == section => {
+                for (key, value) in inner {
+                    section_values.insert(key, toml_to_string(path, value)?);
+                }
+            }
+            // Secciones de otros servicios
+            toml::Value::Table(_) => {}
+            value => {
+                values.insert(key, toml_to_string(path, value)?);
+            }
+        }
+    }
+    values.extend(section_values);
+    Ok(values)
+}
+
+fn toml_to_string(path: &str, value: toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(s) => Ok(s),
+        toml::Value::Array(items) => {
+            let items: Result<Vec<String>, String> = items.into_iter().map(|item| toml_to_string(path, item)).collect();
+            Ok(items?.join(","))
+        }
+        toml::Value::Table(_) => Err(format!("Archivo de configuracion {}: no se admiten tablas anidadas", path)),
+        other => Ok(other.to_string()),
+    }
+}
@@ -0,0 +1,12 @@
+// Codigo compartido entre el dispatcher y los workers
+
+pub mod access_log;
+pub mod api;
+pub mod config;
+pub mod log;
+pub mod metrics;
+pub mod protocol;
+pub mod query;
+pub mod request;
+pub mod response;
+pub mod trace;
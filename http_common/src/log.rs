@@ -0,0 +1,209 @@
+/*
+Logs estructurados del dispatcher y los workers.
+Cada evento tiene hora, nivel, componente, mensaje y campos clave=valor; el campo request_id
+permite seguir una tarea en los logs de los dos servicios (ver protocol::REQUEST_ID_HEADER).
+- LOG_LEVEL: nivel minimo que se escribe (error, warn, info, debug).
+- LOG_FORMAT: text para leer en la terminal, json para una linea JSON por evento.
+Los errores y avisos van a stderr, el resto a stdout.
+
+Se usa con las macros, por ejemplo:
+    info!("forward", request_id = id, worker = worker_id; "Tarea reenviada en {:?}", elapsed);
+*/
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::response::civil_date;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+// Hasta que el binario llama a `init` (y en las pruebas) se escribe info en texto
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static FORMAT: AtomicU8 = AtomicU8::new(LogFormat::Text as u8);
+
+pub fn init(level: Level, format: LogFormat) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+    FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+// Las macros lo revisan antes de armar el mensaje
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+// Escribe un evento; lo llaman las macros
+pub fn write(level: Level, component: &str, message: &str, fields: &[(&str, &dyn fmt::Display)]) {
+    let format = if FORMAT.load(Ordering::Relaxed) == LogFormat::Json as u8 { LogFormat::Json } else { LogFormat::Text };
+    let fields: Vec<(&str, String)> = fields.iter().map(|(key, value)| (*key, value.to_string())).collect();
+    let line = format_event(format, SystemTime::now(), level, component, message, &fields);
+
+    // Una sola escritura por linea para que no se mezclen eventos de distintos hilos
+    let _ = if level <= Level::Warn {
+        io::stderr().lock().write_all(line.as_bytes())
+    } else {
+        io::stdout().lock().write_all(line.as_bytes())
+    };
+}
+
+/*
+Arma la linea de un evento, con el salto de linea al final.
+text: 2026-01-02T03:04:05.678Z INFO  [forward] Tarea reenviada request_id=ab12 worker=worker1
+json: {"time":"2026-01-02T03:04:05.678Z","level":"info","component":"forward","message":"Tarea reenviada","request_id":"ab12","worker":"worker1"}
+*/
+pub fn format_event(format: LogFormat, time: SystemTime, level: Level, component: &str, message: &str, fields: &[(&str, String)]) -> String {
+    let time = timestamp(time);
+    match format {
+        LogFormat::Text => {
+            let mut line = format!("{} {:<5} [{}] {}", time, level.to_string().to_ascii_uppercase(), component, message);
+            for (key, value) in fields {
+                line.push_str(&format!(" {}={}", key, text_value(value)));
+            }
+            line.push('\n');
+            line
+        }
+        LogFormat::Json => {
+            let mut line = format!(
+                "{{\"time\":\"{}\",\"level\":\"{}\",\"component\":{},\"message\":{}",
+                time,
+                level,
+                json_string(component),
+                json_string(message)
+            );
+            for (key, value) in fields {
+                line.push_str(&format!(",{}:{}", json_string(key), json_string(value)));
+            }
+            line.push_str("}\n");
+            line
+        }
+    }
+}
+
+// Los valores con espacios o comillas van entre comillas para que la linea se pueda separar
+fn text_value(value: &str) -> String {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+        format!("{:?}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+fn json_string(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| "\"\"".to_string())
+}
+
+// RFC 3339 en UTC con milisegundos
+pub(crate) fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_date(secs / 86_400);
+    let rem = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Level, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" | "warning" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            _ => Err(format!("nivel de log invalido '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogFormat, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("formato de log invalido '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+        })
+    }
+}
+
+/*
+Macros de log: componente, campos opcionales `clave = valor` separados por coma y terminados
+en `;`, y el mensaje con la sintaxis de format!. El mensaje solo se arma si el nivel esta activo.
+*/
+#[macro_export]
+macro_rules! log_event {
+    ($level:expr, $component:expr, $($key:ident = $value:expr),+ ; $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, $component, &format!($($arg)+), &[$((stringify!($key), &$value as &dyn ::std::fmt::Display)),+]);
+        }
+    };
+    ($level:expr, $component:expr, $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, $component, &format!($($arg)+), &[]);
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log_event!($crate::log::Level::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::log_event!($crate::log::Level::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log_event!($crate::log::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log_event!($crate::log::Level::Debug, $($arg)+) };
+}
@@ -0,0 +1,224 @@
+/*
+Metricas en el formato de texto de Prometheus, las publican el dispatcher y los workers en /metrics.
+Cada metrica guarda un valor por combinacion de etiquetas; los valores de las etiquetas
+se pasan en el mismo orden en que se declararon en `new`.
+*/
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+// Content-Type de /metrics
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/*
+Valor de la etiqueta `method`: el cliente puede enviar cualquier token como metodo,
+los que no son de esta lista se agrupan en "other" para no crear una serie por cada uno.
+*/
+pub fn method_label(method: &str) -> &'static str {
+    match method {
+        "GET" => "GET",
+        "POST" => "POST",
+        "DELETE" => "DELETE",
+        "HEAD" => "HEAD",
+        "OPTIONS" => "OPTIONS",
+        _ => "other",
+    }
+}
+
+// Limites de los histogramas de latencia en segundos; hay tareas de minutos (/sleep, Montecarlo)
+pub const LATENCY_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0];
+
+#[derive(Debug)]
+struct Family<T> {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, T>>, // Ordenado para que la salida sea estable
+}
+
+impl<T: Default> Family<T> {
+    fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Family<T> {
+        Family { name, help, labels, values: Mutex::new(BTreeMap::new()) }
+    }
+
+    fn with<R>(&self, labels: &[&str], update: impl FnOnce(&mut T) -> R) -> R {
+        debug_assert_eq!(labels.len(), self.labels.len(), "etiquetas de {}", self.name);
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        update(values.entry(labels.iter().map(|l| l.to_string()).collect()).or_default())
+    }
+
+    // Borra las series en las que la etiqueta `label` vale `value`
+    fn remove(&self, label: &str, value: &str) {
+        let Some(position) = self.labels.iter().position(|l| *l == label) else {
+            return;
+        };
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        values.retain(|labels, _| labels[position] != value);
+    }
+
+    fn header(&self, out: &mut String, kind: &str) {
+        let help = self.help.replace('\\', "\\\\").replace('\n', "\\n");
+        let _ = writeln!(out, "# HELP {} {}", self.name, help);
+        let _ = writeln!(out, "# TYPE {} {}", self.name, kind);
+    }
+}
+
+// Valor que solo sube: solicitudes, errores, puntos calculados
+#[derive(Debug)]
+pub struct Counter(Family<f64>);
+
+impl Counter {
+    pub fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Counter {
+        Counter(Family::new(name, help, labels))
+    }
+
+    pub fn inc(&self, labels: &[&str]) {
+        self.add(labels, 1.0);
+    }
+
+    pub fn add(&self, labels: &[&str], value: f64) {
+        self.0.with(labels, |total| *total += value);
+    }
+
+    // Para las etiquetas que dejan de existir, como un worker que se quito
+    pub fn remove(&self, label: &str, value: &str) {
+        self.0.remove(label, value);
+    }
+
+    pub fn render(&self, out: &mut String) {
+        render_values(&self.0, out, "counter");
+    }
+}
+
+// Valor que sube y baja: tareas en curso, archivos guardados
+#[derive(Debug)]
+pub struct Gauge(Family<f64>);
+
+impl Gauge {
+    pub fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Gauge {
+        Gauge(Family::new(name, help, labels))
+    }
+
+    pub fn set(&self, labels: &[&str], value: f64) {
+        self.0.with(labels, |current| *current = value);
+    }
+
+    pub fn add(&self, labels: &[&str], delta: f64) {
+        self.0.with(labels, |current| *current += delta);
+    }
+
+    // Suma 1 mientras exista el guard, aunque la tarea termine por error o se cancele
+    pub fn track(&self, labels: &[&str]) -> GaugeGuard<'_> {
+        self.add(labels, 1.0);
+        GaugeGuard { gauge: self, labels: labels.iter().map(|l| l.to_string()).collect() }
+    }
+
+    // Borra todos los valores; para los que se recalculan en cada lectura de /metrics
+    pub fn clear(&self) {
+        self.0.values.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    pub fn render(&self, out: &mut String) {
+        render_values(&self.0, out, "gauge");
+    }
+}
+
+pub struct GaugeGuard<'a> {
+    gauge: &'a Gauge,
+    labels: Vec<String>,
+}
+
+impl Drop for GaugeGuard<'_> {
+    fn drop(&mut self) {
+        let labels: Vec<&str> = self.labels.iter().map(String::as_str).collect();
+        self.gauge.add(&labels, -1.0);
+    }
+}
+
+// Distribucion de valores (latencias) en rangos acumulados
+#[derive(Debug)]
+pub struct Histogram {
+    family: Family<HistogramData>,
+    buckets: &'static [f64],
+}
+
+#[derive(Debug, Default)]
+struct HistogramData {
+    counts: Vec<u64>, // Observaciones <= cada limite
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new(name: &'static str, help: &'static str, labels: &'static [&'static str], buckets: &'static [f64]) -> Histogram {
+        Histogram { family: Family::new(name, help, labels), buckets }
+    }
+
+    pub fn observe(&self, labels: &[&str], value: f64) {
+        let buckets = self.buckets;
+        self.family.with(labels, |data| {
+            data.counts.resize(buckets.len(), 0);
+            for (count, limit) in data.counts.iter_mut().zip(buckets) {
+                if value <= *limit {
+                    *count += 1;
+                }
+            }
+            data.sum += value;
+            data.count += 1;
+        });
+    }
+
+    pub fn remove(&self, label: &str, value: &str) {
+        self.family.remove(label, value);
+    }
+
+    pub fn render(&self, out: &mut String) {
+        let family = &self.family;
+        family.header(out, "histogram");
+        let values = family.values.lock().unwrap_or_else(|e| e.into_inner());
+        for (labels, data) in values.iter() {
+            for (limit, count) in self.buckets.iter().zip(&data.counts) {
+                let le = format_value(*limit);
+                let _ = writeln!(out, "{}_bucket{} {}", family.name, label_set(family.labels, labels, Some(&le)), count);
+            }
+            let _ = writeln!(out, "{}_bucket{} {}", family.name, label_set(family.labels, labels, Some("+Inf")), data.count);
+            let _ = writeln!(out, "{}_sum{} {}", family.name, label_set(family.labels, labels, None), format_value(data.sum));
+            let _ = writeln!(out, "{}_count{} {}", family.name, label_set(family.labels, labels, None), data.count);
+        }
+    }
+}
+
+fn render_values(family: &Family<f64>, out: &mut String, kind: &str) {
+    family.header(out, kind);
+    let values = family.values.lock().unwrap_or_else(|e| e.into_inner());
+    for (labels, value) in values.iter() {
+        let _ = writeln!(out, "{}{} {}", family.name, label_set(family.labels, labels, None), format_value(*value));
+    }
+}
+
+// {nombre="valor",...}; `le` es el limite de un rango del histograma
+fn label_set(names: &[&str], values: &[String], le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = names
+        .iter()
+        .zip(values)
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label(value)))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+    if pairs.is_empty() { String::new() } else { format!("{{{}}}", pairs.join(",")) }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf".to_string() } else { "-Inf".to_string() }
+    } else {
+        value.to_string()
+    }
+}
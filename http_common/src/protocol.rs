@@ -0,0 +1,138 @@
+/*
+Protocolo interno entre el dispatcher y los workers.
+Los dos binarios usan estos mismos tipos, asi un cambio en un lado que no
+se refleja en el otro es un error de compilacion y no un JSON que no se puede leer.
+*/
+use serde::{Deserialize, Serialize};
+
+use crate::api::ApiError;
+use crate::query::QueryParams;
+
+/*
+Version del protocolo interno. Se sube cuando cambia algo de este archivo
+de forma incompatible; el dispatcher no envia tareas a workers con otra version.
+*/
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub fn is_compatible(version: u32) -> bool {
+    version == PROTOCOL_VERSION
+}
+
+/*
+Header con los milisegundos que le quedan a una tarea. Se envia tiempo relativo y no
+una hora para no depender de que los relojes del dispatcher y el worker coincidan.
+*/
+pub const DEADLINE_HEADER: &str = "X-Deadline-Ms";
+
+/*
+Id de una solicitud para seguirla en los logs. Lo pone el dispatcher (o lo respeta si el
+cliente envio uno valido), lo reenvia al worker y los dos lo devuelven en la respuesta.
+*/
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+// Largo maximo de un id que viene de afuera, para no llenar los logs
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+// Solo letras, numeros y - _ . : para que el id no rompa el formato de los logs
+pub fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b':'))
+}
+
+// 128 bits al azar en hexadecimal
+pub fn new_request_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+// Ruta interna donde el worker calcula una parte de Montecarlo
+pub const MONTECARLO_PATH: &str = "/internal/montecarlo";
+
+// Rutas de tareas del worker que el dispatcher reenvia tal cual
+pub const TASK_ROUTES: [&str; 11] = [
+    "/ping",
+    "/ready",
+    "/fibonacci",
+    "/reverse",
+    "/hash",
+    "/timestamp",
+    "/sleep",
+    "/random",
+    "/help",
+    "/createfile",
+    "/deletefile",
+];
+
+// Parte de una estimacion de pi que se le pide a un worker
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MontecarloTask {
+    pub points: u64,
+}
+
+impl MontecarloTask {
+    // Ruta con query para enviar la tarea
+    pub fn to_target(&self) -> String {
+        format!("{}?points={}", MONTECARLO_PATH, self.points)
+    }
+
+    pub fn from_params(params: &QueryParams) -> Result<MontecarloTask, ApiError> {
+        params
+            .get("points")
+            .and_then(|p| p.parse::<u64>().ok())
+            .map(|points| MontecarloTask { points })
+            .ok_or_else(|| ApiError::bad_request("Parametro 'points' invalido o faltante"))
+    }
+}
+
+// Resultado de una MontecarloTask: puntos que cayeron dentro del circulo
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MontecarloResult {
+    pub hits: u64,
+}
+
+// Respuesta de /ping: el proceso esta vivo y atendiendo conexiones
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Liveness {
+    pub status: String,
+    pub uptime_secs: u64,
+    pub protocol_version: u32,
+}
+
+// Respuesta de /ready: el worker puede recibir tareas
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub draining: bool,
+    pub storage_writable: bool, // Se puede escribir en la carpeta de archivos
+    pub protocol_version: u32,
+}
+
+// Ruta de la API de administracion del dispatcher donde se dan de alta los workers
+pub const WORKERS_ADMIN_PATH: &str = "/admin/workers";
+
+/*
+Alta de un worker en el dispatcher (POST /admin/workers).
+Con `heartbeat` el registro vence si el worker no lo renueva a tiempo;
+asi se registran los workers solos. Sin el, queda hasta que se borre.
+*/
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Registration {
+    pub address: String, // URL con la que el dispatcher llega al worker, por ejemplo http://worker5:7878
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    #[serde(default)]
+    pub heartbeat: bool,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+// Respuesta del alta, `id` es el que se usa en DELETE /admin/workers/{id}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegistrationAck {
+    pub id: String,
+    pub address: String,
+    pub weight: u32,
+    pub expires_in_secs: Option<u64>, // Tiempo para renovar el registro, None si no vence
+}
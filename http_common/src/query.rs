@@ -0,0 +1,185 @@
+use std::fmt;
+
+/*
+    Decodificacion de query strings (RFC 3986 + application/x-www-form-urlencoded).
+    Los valores se decodifican con %XX, '+' se toma como espacio y el resultado
+    tiene que ser UTF-8 valido. Una clave puede repetirse y se guardan todos sus valores.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryError {
+    InvalidPercentEncoding(String), // Secuencia %XX incompleta o con digitos no hexadecimales
+    InvalidUtf8(String),            // Los bytes decodificados no forman UTF-8
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::InvalidPercentEncoding(raw) => write!(f, "Codificacion '%' invalida en '{}'", raw),
+            QueryError::InvalidUtf8(raw) => write!(f, "El parametro '{}' no es UTF-8 valido", raw),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+// Parametros de un query string, en el orden en que llegaron
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryParams {
+    pairs: Vec<(String, String)>,
+}
+
+impl QueryParams {
+    /*
+    Parsea la parte despues del '?' (sin incluirlo).
+    "a=1&a=2&b" produce a -> [1, 2] y b -> [""]; los segmentos vacios se ignoran.
+    */
+    pub fn parse(query: &str) -> Result<QueryParams, QueryError> {
+        let mut pairs = Vec::new();
+
+        for segment in query.split('&') {
+            if segment.is_empty() {
+                continue;
+            }
+            // Solo el primer '=' separa clave y valor, "text=a=b" tiene valor "a=b"
+            let (key, value) = segment.split_once('=').unwrap_or((segment, ""));
+            pairs.push((decode_component(key)?, decode_component(value)?));
+        }
+
+        Ok(QueryParams { pairs })
+    }
+
+    // Primer valor de la clave
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    // Todos los valores de la clave, en orden
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.pairs.iter().filter(|(k, _)| k == key).map(|(_, v)| v.as_str()).collect()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.pairs.iter().any(|(k, _)| k == key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    // Agrega los pares de `other` despues de los actuales
+    pub fn extend(&mut self, other: QueryParams) {
+        self.pairs.extend(other.pairs);
+    }
+}
+
+/*
+Separa un request-target en ruta y query string.
+"/reverse?text=abc" -> ("/reverse", "text=abc"). El fragmento '#' se descarta.
+*/
+pub fn split_target(target: &str) -> (&str, &str) {
+    let target = target.split('#').next().unwrap_or("");
+    target.split_once('?').unwrap_or((target, ""))
+}
+
+// Separa la ruta y decodifica los parametros en un solo paso
+pub fn parse_target(target: &str) -> Result<(String, QueryParams), QueryError> {
+    let (path, query) = split_target(target);
+    Ok((path.to_string(), QueryParams::parse(query)?))
+}
+
+// Decodifica una clave o valor de un query: '+' es espacio y %XX un byte
+pub fn decode_component(raw: &str) -> Result<String, QueryError> {
+    percent_decode(raw, true)
+}
+
+/*
+Decodifica las secuencias %XX de `raw`.
+Con `plus_as_space` el '+' se convierte en espacio (formularios); en rutas se deja igual.
+*/
+pub fn percent_decode(raw: &str, plus_as_space: bool) -> Result<String, QueryError> {
+    let bytes = raw.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let high = bytes.get(i + 1).copied().and_then(hex_value);
+                let low = bytes.get(i + 2).copied().and_then(hex_value);
+                match (high, low) {
+                    (Some(h), Some(l)) => decoded.push(h << 4 | l),
+                    _ => return Err(QueryError::InvalidPercentEncoding(raw.to_string())),
+                }
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            b => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|_| QueryError::InvalidUtf8(raw.to_string()))
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_percent_escapes_and_plus() {
+        assert_eq!(decode_component("hola%20mundo").unwrap(), "hola mundo");
+        assert_eq!(decode_component("a+b%2Bc").unwrap(), "a b+c");
+        assert_eq!(decode_component("%C3%B1%c3%b1").unwrap(), "ññ");
+        assert_eq!(decode_component("100%25").unwrap(), "100%");
+        assert_eq!(percent_decode("/a+b%20c", false).unwrap(), "/a+b c");
+    }
+
+    #[test]
+    fn rejects_malformed_escapes_and_invalid_utf8() {
+        for raw in ["%", "%4", "abc%", "%zz", "%g0", "%%41"] {
+            assert_eq!(decode_component(raw), Err(QueryError::InvalidPercentEncoding(raw.to_string())), "{}", raw);
+        }
+        assert_eq!(decode_component("%FF"), Err(QueryError::InvalidUtf8("%FF".to_string())));
+        assert_eq!(decode_component("%C3"), Err(QueryError::InvalidUtf8("%C3".to_string())));
+    }
+
+    #[test]
+    fn parses_repeated_keys_in_order() {
+        let params = QueryParams::parse("a=1&&b&a=2&text=x=y").unwrap();
+        assert_eq!(params.get("a"), Some("1"));
+        assert_eq!(params.get_all("a"), vec!["1", "2"]);
+        assert_eq!(params.get("b"), Some(""));
+        assert_eq!(params.get("text"), Some("x=y"));
+        assert!(!params.contains_key("c"));
+        assert!(QueryParams::parse("").unwrap().is_empty());
+        assert!(QueryParams::parse("a=%").is_err());
+    }
+
+    #[test]
+    fn splits_path_query_and_fragment() {
+        assert_eq!(split_target("/reverse?text=abc#frag"), ("/reverse", "text=abc"));
+        assert_eq!(split_target("/status"), ("/status", ""));
+        let (path, params) = parse_target("/reverse?text=a%26b").unwrap();
+        assert_eq!(path, "/reverse");
+        assert_eq!(params.get("text"), Some("a&b"));
+    }
+}
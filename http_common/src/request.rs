@@ -0,0 +1,497 @@
+use std::collections::HashMap;
+use std::io;
+
+use crate::protocol::{is_valid_request_id, new_request_id, REQUEST_ID_HEADER};
+use crate::query::{parse_target, QueryParams};
+
+/*
+    Parser de solicitudes HTTP/1.1 sin I/O.
+    Trabaja sobre los bytes que se han recibido hasta el momento: si la solicitud
+    todavia no esta completa devuelve Ok(None) y el llamador lee mas del socket.
+    Lo usan el worker (sockets bloqueantes) y el dispatcher (Tokio).
+*/
+
+// Limites de tamaño para una solicitud
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_header_bytes: usize, // Linea de solicitud + headers
+    pub max_body_bytes: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    BadRequest(String), // 400
+    HeadersTooLarge,    // 431
+    PayloadTooLarge,    // 413
+}
+
+// Error al leer una solicitud desde un socket (sincrono o asincrono)
+#[derive(Debug)]
+pub enum ReadError {
+    Closed,            // El cliente cerro la conexion antes de enviar algo
+    Io(io::Error),
+    Parse(ParseError), // 400, 413 o 431
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+impl From<ParseError> for ReadError {
+    fn from(e: ParseError) -> Self {
+        ReadError::Parse(e)
+    }
+}
+
+// Linea de solicitud y headers, sin el body
+#[derive(Debug, Clone)]
+pub struct Head {
+    pub method: String,
+    pub target: String, // Ruta con el query sin decodificar, tal como llego
+    pub version: String,
+    pub headers: HashMap<String, String>, // Nombres en minusculas
+    pub len: usize,                       // Bytes que ocupa el head, incluyendo la linea vacia
+}
+
+// Solicitud HTTP ya parseada
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub target: String,
+    pub path: String,
+    pub query: QueryParams,
+    pub version: String,
+    pub headers: HashMap<String, String>, // Nombres en minusculas
+    pub body: Vec<u8>,
+    pub form: QueryParams, // Parametros del body si es application/x-www-form-urlencoded
+}
+
+// Como viene delimitado el body
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BodyKind {
+    Empty,
+    Length(usize),
+    Chunked,
+}
+
+impl Head {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).map(|v| v.as_str())
+    }
+
+    // El cliente espera "100 Continue" antes de mandar el body
+    pub fn expects_continue(&self) -> bool {
+        self.header("expect").is_some_and(|v| v.eq_ignore_ascii_case("100-continue"))
+    }
+
+    /*
+    Determina como leer el body segun Content-Length o Transfer-Encoding.
+    Un Content-Length mayor al limite se rechaza sin leer el body.
+    */
+    pub fn body_kind(&self, limits: &Limits) -> Result<BodyKind, ParseError> {
+        let transfer_encoding = self.header("transfer-encoding");
+        let content_length = self.header("content-length");
+
+        if transfer_encoding.is_some() && content_length.is_some() {
+            return Err(ParseError::BadRequest("No se permiten Content-Length y Transfer-Encoding juntos".to_string()));
+        }
+
+        if let Some(encoding) = transfer_encoding {
+            if !encoding.eq_ignore_ascii_case("chunked") {
+                return Err(ParseError::BadRequest(format!("Transfer-Encoding no soportado: {}", encoding)));
+            }
+            return Ok(BodyKind::Chunked);
+        }
+
+        if let Some(length) = content_length {
+            let length = length
+                .parse::<usize>()
+                .map_err(|_| ParseError::BadRequest("Content-Length invalido".to_string()))?;
+            if length > limits.max_body_bytes {
+                return Err(ParseError::PayloadTooLarge);
+            }
+            return Ok(BodyKind::Length(length));
+        }
+
+        Ok(BodyKind::Empty)
+    }
+}
+
+impl Request {
+    // Arma la solicitud final decodificando el query y el formulario
+    pub fn from_parts(head: Head, body: Vec<u8>) -> Result<Request, ParseError> {
+        let (path, query) = parse_target(&head.target).map_err(|e| ParseError::BadRequest(e.to_string()))?;
+
+        let mut request = Request {
+            method: head.method,
+            target: head.target,
+            path,
+            query,
+            version: head.version,
+            headers: head.headers,
+            body,
+            form: QueryParams::default(),
+        };
+        request.form = parse_form(&request)?;
+        Ok(request)
+    }
+
+    // Busca un header sin importar mayusculas/minusculas
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).map(|v| v.as_str())
+    }
+
+    /*
+    Id de la solicitud (header X-Request-Id). Si el cliente no envio uno valido se genera
+    y se guarda en los headers, asi todo lo que lee la solicitud despues ve el mismo.
+    */
+    pub fn ensure_request_id(&mut self) -> &str {
+        let key = REQUEST_ID_HEADER.to_ascii_lowercase();
+        if !self.headers.get(&key).is_some_and(|id| is_valid_request_id(id)) {
+            self.headers.insert(key.clone(), new_request_id());
+        }
+        &self.headers[&key]
+    }
+
+    // Id puesto por `ensure_request_id`, "-" si no se llamo
+    pub fn request_id(&self) -> &str {
+        self.header(REQUEST_ID_HEADER).unwrap_or("-")
+    }
+
+    /*
+    Indica si el cliente quiere mantener la conexion abierta.
+    En HTTP/1.1 es el comportamiento por defecto, en HTTP/1.0 hay que pedirlo.
+    */
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("connection").unwrap_or("").to_ascii_lowercase();
+        let has_token = |token: &str| connection.split(',').any(|t| t.trim() == token);
+
+        if self.version == "HTTP/1.0" {
+            has_token("keep-alive")
+        } else {
+            !has_token("close")
+        }
+    }
+
+    /*
+    Parametros de la solicitud: los del query string seguidos por los del body.
+    `get` devuelve el primer valor, asi que si una clave esta en ambos gana el query.
+    */
+    pub fn params(&self) -> QueryParams {
+        let mut params = self.query.clone();
+        params.extend(self.form.clone());
+        params
+    }
+}
+
+/*
+Intenta parsear una solicitud completa desde el inicio de `buf`.
+Devuelve la solicitud y los bytes consumidos; lo que sigue puede ser la siguiente solicitud.
+*/
+pub fn parse_request(buf: &[u8], limits: &Limits) -> Result<Option<(Request, usize)>, ParseError> {
+    let Some(head) = parse_head(buf, limits)? else {
+        return Ok(None);
+    };
+    let kind = head.body_kind(limits)?;
+    let Some((body, body_len)) = parse_body(&buf[head.len..], kind, limits)? else {
+        return Ok(None);
+    };
+
+    let consumed = head.len + body_len;
+    Ok(Some((Request::from_parts(head, body)?, consumed)))
+}
+
+/*
+Parsea la linea de solicitud y los headers hasta "\r\n\r\n".
+Se aceptan lineas terminadas solo en "\n" y se ignoran lineas vacias previas (RFC 9112 2.2).
+*/
+pub fn parse_head(buf: &[u8], limits: &Limits) -> Result<Option<Head>, ParseError> {
+    let mut lines = Vec::new();
+    let mut pos = 0;
+
+    let len = loop {
+        let Some(newline) = buf[pos..].iter().position(|&b| b == b'\n') else {
+            if buf.len() > limits.max_header_bytes {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            return Ok(None);
+        };
+        let end = pos + newline + 1;
+        if end > limits.max_header_bytes {
+            return Err(ParseError::HeadersTooLarge);
+        }
+
+        let line = trim_line_end(&buf[pos..end]);
+        pos = end;
+        if line.is_empty() {
+            if lines.is_empty() {
+                continue;
+            }
+            break pos;
+        }
+        lines.push(line);
+    };
+
+    let text = |line: &[u8]| {
+        std::str::from_utf8(line)
+            .map(str::to_string)
+            .map_err(|_| ParseError::BadRequest("Headers con bytes invalidos".to_string()))
+    };
+
+    let (method, target, version) = parse_request_line(&text(lines[0])?)?;
+
+    let mut headers: HashMap<String, String> = HashMap::new();
+    for line in &lines[1..] {
+        let (name, value) = parse_header_line(&text(line)?)?;
+        headers
+            .entry(name)
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(&value);
+            })
+            .or_insert(value);
+    }
+
+    if version == "HTTP/1.1" && !headers.contains_key("host") {
+        return Err(ParseError::BadRequest("Falta el header 'Host'".to_string()));
+    }
+
+    Ok(Some(Head { method, target, version, headers, len }))
+}
+
+/*
+Extrae el body del inicio de `buf` segun `kind`.
+Devuelve el body decodificado y los bytes consumidos, o None si falta informacion.
+*/
+pub fn parse_body(buf: &[u8], kind: BodyKind, limits: &Limits) -> Result<Option<(Vec<u8>, usize)>, ParseError> {
+    match kind {
+        BodyKind::Empty => Ok(Some((Vec::new(), 0))),
+        BodyKind::Length(length) if buf.len() >= length => Ok(Some((buf[..length].to_vec(), length))),
+        BodyKind::Length(_) => Ok(None),
+        BodyKind::Chunked => parse_chunked(buf, limits),
+    }
+}
+
+// Decodifica un body con Transfer-Encoding: chunked (RFC 9112 7.1)
+fn parse_chunked(buf: &[u8], limits: &Limits) -> Result<Option<(Vec<u8>, usize)>, ParseError> {
+    // Cada linea de control (tamaño o trailer) tiene su propio limite
+    let line_limit = limits.max_header_bytes;
+    let mut body = Vec::new();
+    let mut pos = 0;
+
+    loop {
+        let Some(line_end) = find_line(&buf[pos..], line_limit)? else {
+            return Ok(None);
+        };
+        let size_line = trim_line_end(&buf[pos..pos + line_end]);
+        pos += line_end;
+
+        // Se ignoran las extensiones ";nombre=valor"
+        let size_hex = std::str::from_utf8(size_line).unwrap_or("").split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size_hex, 16)
+            .map_err(|_| ParseError::BadRequest(format!("Tamaño de chunk invalido: {}", size_hex)))?;
+
+        if size == 0 {
+            break;
+        }
+        // Se compara contra lo que falta para el limite: con un tamaño como ffffffffffffffff
+        // la suma se desbordaria
+        if size > limits.max_body_bytes - body.len() {
+            return Err(ParseError::PayloadTooLarge);
+        }
+        let chunk_end = pos.checked_add(size).and_then(|end| end.checked_add(2)).ok_or(ParseError::PayloadTooLarge)?;
+        if buf.len() < chunk_end {
+            return Ok(None);
+        }
+
+        body.extend_from_slice(&buf[pos..pos + size]);
+        pos += size;
+        if &buf[pos..pos + 2] != b"\r\n" {
+            return Err(ParseError::BadRequest("Chunk sin CRLF final".to_string()));
+        }
+        pos += 2;
+    }
+
+    // Trailers: se leen y se descartan hasta la linea vacia
+    loop {
+        let Some(line_end) = find_line(&buf[pos..], line_limit)? else {
+            return Ok(None);
+        };
+        let line = trim_line_end(&buf[pos..pos + line_end]);
+        pos += line_end;
+        if line.is_empty() {
+            break;
+        }
+    }
+
+    Ok(Some((body, pos)))
+}
+
+// Posicion despues del siguiente "\n", respetando el limite de largo de linea
+fn find_line(buf: &[u8], limit: usize) -> Result<Option<usize>, ParseError> {
+    match buf.iter().position(|&b| b == b'\n') {
+        Some(newline) if newline < limit => Ok(Some(newline + 1)),
+        Some(_) => Err(ParseError::HeadersTooLarge),
+        None if buf.len() > limit => Err(ParseError::HeadersTooLarge),
+        None => Ok(None),
+    }
+}
+
+fn trim_line_end(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+fn parse_request_line(line: &str) -> Result<(String, String, String), ParseError> {
+    let parts: Vec<&str> = line.split(' ').collect();
+    if parts.len() != 3 || parts.iter().any(|p| p.is_empty()) {
+        return Err(ParseError::BadRequest("Linea de solicitud invalida".to_string()));
+    }
+
+    let method = parts[0];
+    if !method.bytes().all(is_token_byte) {
+        return Err(ParseError::BadRequest("Metodo invalido".to_string()));
+    }
+    let version = parts[2];
+    if version != "HTTP/1.1" && version != "HTTP/1.0" {
+        return Err(ParseError::BadRequest(format!("Version HTTP no soportada: {}", version)));
+    }
+
+    Ok((method.to_string(), parts[1].to_string(), version.to_string()))
+}
+
+fn parse_header_line(line: &str) -> Result<(String, String), ParseError> {
+    let (name, value) = line
+        .split_once(':')
+        .ok_or_else(|| ParseError::BadRequest(format!("Header invalido: {}", line)))?;
+
+    // No se permite espacio entre el nombre y los dos puntos (RFC 9112 5.1)
+    if name.is_empty() || !name.bytes().all(is_token_byte) {
+        return Err(ParseError::BadRequest(format!("Nombre de header invalido: {}", name)));
+    }
+
+    Ok((name.to_ascii_lowercase(), value.trim().to_string()))
+}
+
+// Caracteres permitidos en un token HTTP (RFC 9110 5.6.2)
+fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+// Decodifica el body como formulario si el Content-Type lo indica
+fn parse_form(request: &Request) -> Result<QueryParams, ParseError> {
+    let is_form = request
+        .header("content-type")
+        .is_some_and(|ct| ct.to_ascii_lowercase().starts_with("application/x-www-form-urlencoded"));
+    if !is_form {
+        return Ok(QueryParams::default());
+    }
+
+    let body = std::str::from_utf8(&request.body)
+        .map_err(|_| ParseError::BadRequest("El formulario no es UTF-8 valido".to_string()))?;
+    QueryParams::parse(body.trim_end()).map_err(|e| ParseError::BadRequest(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits { max_header_bytes: 256, max_body_bytes: 16 };
+
+    fn parse(raw: &[u8]) -> Result<Option<(Request, usize)>, ParseError> {
+        parse_request(raw, &LIMITS)
+    }
+
+    #[test]
+    fn parses_request_line_headers_and_query() {
+        let raw = b"\r\nGET /reverse?text=abc HTTP/1.1\r\nHost: x\r\nX-A: 1\r\nx-a: 2\r\n\r\n";
+        let (request, consumed) = parse(raw).unwrap().unwrap();
+        assert_eq!(consumed, raw.len());
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.target, "/reverse?text=abc");
+        assert_eq!(request.path, "/reverse");
+        assert_eq!(request.query.get("text"), Some("abc"));
+        assert_eq!(request.header("X-A"), Some("1, 2"));
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn parses_content_length_and_chunked_bodies() {
+        let (request, _) = parse(b"POST /x HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhola!").unwrap().unwrap();
+        assert_eq!(request.body, b"hola!");
+
+        let raw = b"POST /x HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n4;ext=1\r\nhola\r\n2\r\n!!\r\n0\r\nX-Trailer: 1\r\n\r\n";
+        let (request, consumed) = parse(raw).unwrap().unwrap();
+        assert_eq!(request.body, b"hola!!");
+        assert_eq!(consumed, raw.len());
+    }
+
+    #[test]
+    fn waits_for_incomplete_requests_and_leaves_the_next_one() {
+        let raw = b"POST /x HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nhola\r\n0\r\n\r\n";
+        for end in 0..raw.len() {
+            assert_eq!(parse(&raw[..end]).map(|r| r.is_none()), Ok(true), "{}", end);
+        }
+
+        let pipelined = b"GET /a HTTP/1.1\r\nHost: x\r\n\r\nGET /b HTTP/1.1\r\n";
+        let (request, consumed) = parse(pipelined).unwrap().unwrap();
+        assert_eq!(request.path, "/a");
+        assert_eq!(&pipelined[consumed..], b"GET /b HTTP/1.1\r\n");
+    }
+
+    #[test]
+    fn merges_form_body_after_query() {
+        let raw = b"POST /x?a=query HTTP/1.1\r\nHost: x\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 7\r\n\r\na=1&b=2";
+        let params = parse(raw).unwrap().unwrap().0.params();
+        assert_eq!(params.get_all("a"), vec!["query", "1"]);
+        assert_eq!(params.get("b"), Some("2"));
+    }
+
+    #[test]
+    fn keep_alive_depends_on_version_and_connection() {
+        let keep_alive = |raw: &[u8]| parse(raw).unwrap().unwrap().0.keep_alive();
+        assert!(keep_alive(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n"));
+        assert!(!keep_alive(b"GET / HTTP/1.1\r\nHost: x\r\nConnection: foo, close\r\n\r\n"));
+        assert!(!keep_alive(b"GET / HTTP/1.0\r\n\r\n"));
+        assert!(keep_alive(b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n"));
+    }
+
+    #[test]
+    fn rejects_invalid_requests() {
+        let bad_request = |raw: &[u8]| matches!(parse(raw), Err(ParseError::BadRequest(_)));
+        assert!(bad_request(b"GET / HTTP/1.1\r\n\r\n"));
+        assert!(bad_request(b"GET  / HTTP/1.1\r\nHost: x\r\n\r\n"));
+        assert!(bad_request(b"GET / HTTP/2\r\nHost: x\r\n\r\n"));
+        assert!(bad_request(b"G(T / HTTP/1.1\r\nHost: x\r\n\r\n"));
+        assert!(bad_request(b"GET / HTTP/1.1\r\nHost : x\r\n\r\n"));
+        assert!(bad_request(b"GET / HTTP/1.1\r\nHost: \xff\r\n\r\n"));
+        assert!(bad_request(b"GET /x?a=%zz HTTP/1.1\r\nHost: x\r\n\r\n"));
+        assert!(bad_request(b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 1\r\nTransfer-Encoding: chunked\r\n\r\n"));
+        assert!(bad_request(b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: gzip\r\n\r\n"));
+        assert!(bad_request(b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: -1\r\n\r\n"));
+        assert!(bad_request(b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n"));
+        assert!(bad_request(b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nabXX"));
+    }
+
+    #[test]
+    fn enforces_size_limits() {
+        let long_header = format!("GET / HTTP/1.1\r\nHost: x\r\nX-Big: {}\r\n\r\n", "a".repeat(300));
+        assert_eq!(parse(long_header.as_bytes()).err(), Some(ParseError::HeadersTooLarge));
+        assert_eq!(parse(&[b'a'; 300]).err(), Some(ParseError::HeadersTooLarge));
+
+        let big_body = b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 17\r\n\r\n";
+        assert_eq!(parse(big_body).err(), Some(ParseError::PayloadTooLarge));
+        let big_chunks = b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n8\r\n12345678\r\n9\r\n";
+        assert_eq!(parse(big_chunks).err(), Some(ParseError::PayloadTooLarge));
+
+        // Tamaños de chunk que desbordarian la suma con el body ya leido
+        for size in ["ffffffffffffffff", "fffffffffffffff0", "10"] {
+            let raw = format!("POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\n{}\r\n", size);
+            assert_eq!(parse(raw.as_bytes()).err(), Some(ParseError::PayloadTooLarge), "{}", size);
+        }
+        let overflow = b"POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n10000000000000000\r\n";
+        assert!(matches!(parse(overflow), Err(ParseError::BadRequest(_))));
+    }
+}
@@ -0,0 +1,235 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::api::ApiError;
+
+/*
+Respuesta HTTP/1.1 que arman el dispatcher y los workers.
+Se construye con metodos encadenados y se convierte a bytes al final con `to_bytes`,
+que agrega Content-Length, Date y Server si no se pusieron antes.
+*/
+#[derive(Debug, Clone)]
+pub struct Response {
+    status: u16,
+    headers: Vec<(String, String)>, // En el orden en que se agregaron, sin Content-Length
+    body: Vec<u8>,
+    head_only: bool,                // Respuesta a HEAD: se anuncia el largo pero no se envia el body
+}
+
+// Headers que describen la conexion y no el contenido, no se copian de una respuesta a otra
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "content-length",
+];
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+            head_only: false,
+        }
+    }
+
+    // Body JSON serializado con serde; si no se puede serializar se responde 500
+    pub fn json<T: Serialize>(status: u16, body: &T) -> Response {
+        match serde_json::to_vec(body) {
+            Ok(json) => Response::new(status).body("application/json", json),
+            Err(e) => Response::error(&ApiError::internal(&format!("No se pudo serializar la respuesta: {}", e))),
+        }
+    }
+
+    // Error con el formato comun, el codigo HTTP sale del propio error
+    pub fn error(error: &ApiError) -> Response {
+        Response::new(error.status).body("application/json", error.to_json().into_bytes())
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn body_bytes(&self) -> &[u8] {
+        &self.body
+    }
+
+    // Busca un header sin importar mayusculas/minusculas
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Pone un header, reemplazando el anterior con el mismo nombre
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    // Agrega un header aunque ya exista uno igual (por ejemplo Set-Cookie)
+    pub fn append_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(self, content_type: &str, body: Vec<u8>) -> Response {
+        let mut response = self.header("Content-Type", content_type);
+        response.body = body;
+        response
+    }
+
+    /*
+    Copia los headers de otra respuesta (la de un worker) excepto los de conexion,
+    que dependen de cada salto y los pone quien envia.
+    */
+    pub fn forwarded_headers<'a>(mut self, headers: impl IntoIterator<Item = (&'a str, &'a str)>) -> Response {
+        for (name, value) in headers {
+            if !HOP_BY_HOP.iter().any(|hop| hop.eq_ignore_ascii_case(name)) {
+                self = self.append_header(name, value);
+            }
+        }
+        self
+    }
+
+    /*
+    Header Connection: con keep-alive se anuncia el tiempo de inactividad
+    y las solicitudes restantes, con None se avisa que se cierra.
+    */
+    pub fn connection(self, keep_alive: Option<(u64, usize)>) -> Response {
+        match keep_alive {
+            Some((timeout, max)) => self
+                .header("Connection", "keep-alive")
+                .header("Keep-Alive", &format!("timeout={}, max={}", timeout, max)),
+            None => {
+                let mut response = self.header("Connection", "close");
+                response.headers.retain(|(key, _)| !key.eq_ignore_ascii_case("keep-alive"));
+                response
+            }
+        }
+    }
+
+    // Para HEAD se envian los mismos headers que en GET pero sin el body
+    pub fn head_only(mut self) -> Response {
+        self.head_only = true;
+        self
+    }
+
+    // Bytes del body que se envian, sin contar los headers (0 en una respuesta a HEAD)
+    pub fn sent_body_len(&self) -> usize {
+        if self.head_only { 0 } else { self.body.len() }
+    }
+
+    // Convierte la respuesta a bytes para escribirla en el socket
+    pub fn to_bytes(&self, server: &str) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason_phrase(self.status));
+
+        if self.get_header("date").is_none() {
+            head.push_str(&format!("Date: {}\r\n", http_date(SystemTime::now())));
+        }
+        if self.get_header("server").is_none() {
+            head.push_str(&format!("Server: {}\r\n", server));
+        }
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        // 1xx, 204 y 304 no llevan body ni Content-Length
+        let has_body = !matches!(self.status, 100..=199 | 204 | 304);
+        // En HEAD puede venir el largo ya puesto, por ejemplo cuando se reenvia un HEAD a un worker
+        let explicit_length = self.head_only && self.get_header("content-length").is_some();
+        if has_body && !explicit_length {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+
+        let mut bytes = head.into_bytes();
+        if has_body && !self.head_only {
+            bytes.extend_from_slice(&self.body);
+        }
+        bytes
+    }
+}
+
+// Frase estandar de cada codigo de estado
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
+pub(crate) const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/*
+Fecha en el formato del header Date (IMF-fixdate), por ejemplo
+"Sun, 06 Nov 1994 08:49:37 GMT". Se calcula a mano para no depender de chrono.
+*/
+pub fn http_date(time: SystemTime) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"]; // 1970-01-01 fue jueves
+
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let days = secs / 86_400;
+    let rem = secs % 86_400;
+    let (year, month, day) = civil_date(days);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+// Dias desde 1970 a (anio, mes, dia) (algoritmo de Howard Hinnant)
+pub(crate) fn civil_date(days: u64) -> (i64, i64, i64) {
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,450 @@
+/*
+Trazas distribuidas con W3C Trace Context.
+El dispatcher abre un span por solicitud (continua la traza si el cliente envio `traceparent`),
+uno por cada envio a un worker y lo pasa en el header; el worker cuelga sus spans de ese.
+Los spans terminados se exportan en segundo plano, en lotes, segun TRACE_EXPORTER:
+- none: no se guardan.
+- otlp: POST con JSON de OTLP/HTTP a TRACE_OTLP_ENDPOINT (un collector de OpenTelemetry).
+- file: el mismo JSON, un lote por linea, agregado a TRACE_FILE.
+*/
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::{info, warn};
+
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+// Spans terminados que pueden esperar a ser exportados; si se llena se descartan
+const QUEUE_CAPACITY: usize = 4096;
+// Spans por lote exportado
+const MAX_BATCH: usize = 512;
+// Tiempo maximo para conectar y para esperar la respuesta del collector
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceExporter {
+    None,
+    Otlp,
+    File,
+}
+
+// Configuracion de las trazas, ver Config de cada servicio
+#[derive(Debug, Clone)]
+pub struct TraceSettings {
+    pub exporter: TraceExporter,
+    pub otlp_endpoint: String,    // URL http:// del collector, por ejemplo http://localhost:4318/v1/traces
+    pub file: String,             // Archivo para el exportador file
+    pub flush_interval: Duration, // Cada cuanto se exporta lo acumulado
+}
+
+// Identidad de un span que viaja en el header traceparent
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceContext {
+    pub trace_id: String, // 32 digitos hexadecimales
+    pub span_id: String,  // 16 digitos hexadecimales
+    pub sampled: bool,    // Si es false el span se propaga pero no se exporta
+}
+
+impl TraceContext {
+    // Formato 00-<trace_id>-<span_id>-<flags>; None si el header no es valido
+    pub fn parse(header: &str) -> Option<TraceContext> {
+        let mut parts = header.trim().split('-');
+        let (version, trace_id, span_id, flags) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        // Versiones futuras pueden agregar campos al final, la 00 no
+        if version.len() != 2 || !is_hex(version) || version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+        if trace_id.len() != 32 || !is_hex(trace_id) || trace_id.bytes().all(|b| b == b'0') {
+            return None;
+        }
+        if span_id.len() != 16 || !is_hex(span_id) || span_id.bytes().all(|b| b == b'0') {
+            return None;
+        }
+        if flags.len() != 2 || !is_hex(flags) {
+            return None;
+        }
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        Some(TraceContext { trace_id: trace_id.to_string(), span_id: span_id.to_string(), sampled: flags & 1 == 1 })
+    }
+
+    pub fn to_header(&self) -> String {
+        format!("00-{}-{}-{:02x}", self.trace_id, self.span_id, self.sampled as u8)
+    }
+}
+
+// Solo hexadecimal en minusculas, como pide el estandar
+fn is_hex(value: &str) -> bool {
+    value.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpanKind {
+    Internal,
+    Server, // Atiende una solicitud que llego por la red
+    Client, // Envia una solicitud a otro servicio
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttrValue {
+    Str(String),
+    Int(i64),
+}
+
+impl From<&str> for AttrValue {
+    fn from(value: &str) -> AttrValue {
+        AttrValue::Str(value.to_string())
+    }
+}
+
+impl From<String> for AttrValue {
+    fn from(value: String) -> AttrValue {
+        AttrValue::Str(value)
+    }
+}
+
+impl From<u16> for AttrValue {
+    fn from(value: u16) -> AttrValue {
+        AttrValue::Int(value as i64)
+    }
+}
+
+impl From<u32> for AttrValue {
+    fn from(value: u32) -> AttrValue {
+        AttrValue::Int(value as i64)
+    }
+}
+
+impl From<u64> for AttrValue {
+    fn from(value: u64) -> AttrValue {
+        AttrValue::Int(value as i64)
+    }
+}
+
+impl From<usize> for AttrValue {
+    fn from(value: usize) -> AttrValue {
+        AttrValue::Int(value as i64)
+    }
+}
+
+// Un tramo de trabajo; se termina pasandolo a Tracer::end
+#[derive(Debug, Clone)]
+pub struct Span {
+    context: TraceContext,
+    parent_span_id: Option<String>,
+    name: String,
+    kind: SpanKind,
+    start: SystemTime,
+    attributes: Vec<(&'static str, AttrValue)>,
+    error: bool,
+}
+
+impl Span {
+    // Span de una solicitud recibida; continua la traza de `parent` o empieza una nueva
+    pub fn server(name: &str, parent: Option<TraceContext>) -> Span {
+        let (trace_id, parent_span_id, sampled) = match parent {
+            Some(parent) => (parent.trace_id, Some(parent.span_id), parent.sampled),
+            None => (format!("{:032x}", random_nonzero_u128()), None, true),
+        };
+        Span::new(name, SpanKind::Server, TraceContext { trace_id, span_id: new_span_id(), sampled }, parent_span_id)
+    }
+
+    pub fn child(&self, name: &str, kind: SpanKind) -> Span {
+        let context = TraceContext { span_id: new_span_id(), ..self.context.clone() };
+        Span::new(name, kind, context, Some(self.context.span_id.clone()))
+    }
+
+    fn new(name: &str, kind: SpanKind, context: TraceContext, parent_span_id: Option<String>) -> Span {
+        Span {
+            context,
+            parent_span_id,
+            name: name.to_string(),
+            kind,
+            start: SystemTime::now(),
+            attributes: Vec::new(),
+            error: false,
+        }
+    }
+
+    // Para spans que empezaron antes de poder crearlos, como la espera en la cola del pool
+    pub fn starting_at(mut self, start: SystemTime) -> Span {
+        self.start = start;
+        self
+    }
+
+    pub fn context(&self) -> &TraceContext {
+        &self.context
+    }
+
+    // Header para que el servicio llamado cuelgue sus spans de este
+    pub fn traceparent(&self) -> String {
+        self.context.to_header()
+    }
+
+    pub fn set(&mut self, key: &'static str, value: impl Into<AttrValue>) {
+        self.attributes.retain(|(k, _)| *k != key);
+        self.attributes.push((key, value.into()));
+    }
+
+    pub fn set_error(&mut self, message: &str) {
+        self.error = true;
+        self.set("error.message", message);
+    }
+
+    // Nombre y atributos del span de una solicitud ya respondida; un 5xx lo marca como error
+    pub fn record_response(&mut self, method: &str, path: &str, route: &str, status: u16, request_id: &str) {
+        self.name = format!("{} {}", method, route);
+        self.set("http.request.method", method);
+        self.set("url.path", path);
+        self.set("http.route", route);
+        self.set("http.response.status_code", status);
+        self.set("request_id", request_id);
+        if status >= 500 {
+            self.set_error(&format!("HTTP {}", status));
+        }
+    }
+}
+
+// Exporta los spans terminados de un servicio
+pub struct Tracer {
+    sender: Option<SyncSender<(Span, SystemTime)>>, // None: trazas desactivadas
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Tracer {
+    pub fn new(service: &str, settings: &TraceSettings) -> Tracer {
+        let sink = match settings.exporter {
+            TraceExporter::None => return Tracer::disabled(),
+            TraceExporter::Otlp => Sink::Otlp(settings.otlp_endpoint.clone()),
+            TraceExporter::File => Sink::File(settings.file.clone()),
+        };
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let service = service.to_string();
+        let flush_interval = settings.flush_interval;
+        let thread = thread::Builder::new()
+            .name("trace-export".to_string())
+            .spawn(move || export_loop(&service, &sink, flush_interval, receiver));
+        match thread {
+            Ok(thread) => Tracer { sender: Some(sender), thread: Mutex::new(Some(thread)) },
+            Err(e) => {
+                warn!("trace", "No se pudo iniciar el hilo de exportacion, trazas desactivadas: {}", e);
+                Tracer::disabled()
+            }
+        }
+    }
+
+    pub fn disabled() -> Tracer {
+        Tracer { sender: None, thread: Mutex::new(None) }
+    }
+
+    pub fn end(&self, span: Span) {
+        self.end_at(span, SystemTime::now());
+    }
+
+    pub fn end_at(&self, span: Span, end: SystemTime) {
+        // Si la cola esta llena se pierde el span; no se frena la solicitud por las trazas
+        match &self.sender {
+            Some(sender) if span.context.sampled => {
+                let _ = sender.try_send((span, end));
+            }
+            _ => {}
+        }
+    }
+}
+
+// Al soltarse exporta lo que quedaba pendiente
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+enum Sink {
+    Otlp(String),
+    File(String),
+}
+
+fn export_loop(service: &str, sink: &Sink, flush_interval: Duration, receiver: Receiver<(Span, SystemTime)>) {
+    let mut batch = Vec::new();
+    let mut last_flush = Instant::now();
+    let mut failing = false; // Para avisar una vez cuando falla y otra cuando se recupera
+
+    loop {
+        let closed = match receiver.recv_timeout(flush_interval.saturating_sub(last_flush.elapsed())) {
+            Ok(span) => {
+                batch.push(span);
+                if batch.len() < MAX_BATCH {
+                    continue;
+                }
+                false
+            }
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => true,
+        };
+
+        if !batch.is_empty() {
+            let body = encode_otlp(service, &batch).to_string();
+            match write_batch(sink, &body) {
+                Ok(()) if failing => {
+                    info!("trace", "La exportacion de trazas se recupero");
+                    failing = false;
+                }
+                Ok(()) => {}
+                Err(e) if !failing => {
+                    warn!("trace", spans = batch.len(); "No se pudieron exportar las trazas: {}", e);
+                    failing = true;
+                }
+                Err(_) => {}
+            }
+            batch.clear();
+        }
+        last_flush = Instant::now();
+        if closed {
+            return;
+        }
+    }
+}
+
+fn write_batch(sink: &Sink, body: &str) -> Result<(), String> {
+    match sink {
+        Sink::Otlp(endpoint) => post_json(endpoint, body),
+        Sink::File(path) => {
+            let mut file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| format!("{}: {}", path, e))?;
+            file.write_all(format!("{}\n", body).as_bytes()).map_err(|e| format!("{}: {}", path, e))
+        }
+    }
+}
+
+// ExportTraceServiceRequest de OTLP en JSON: los ids van en hexadecimal y los tiempos en nanosegundos como texto
+fn encode_otlp(service: &str, spans: &[(Span, SystemTime)]) -> Value {
+    let spans: Vec<Value> = spans
+        .iter()
+        .map(|(span, end)| {
+            let mut encoded = json!({
+                "traceId": span.context.trace_id,
+                "spanId": span.context.span_id,
+                "name": span.name,
+                "kind": match span.kind {
+                    SpanKind::Internal => 1,
+                    SpanKind::Server => 2,
+                    SpanKind::Client => 3,
+                },
+                "startTimeUnixNano": unix_nanos(span.start).to_string(),
+                "endTimeUnixNano": unix_nanos(*end).to_string(),
+                "attributes": span.attributes.iter().map(|(key, value)| attribute(key, value)).collect::<Vec<_>>(),
+                "status": { "code": if span.error { 2 } else { 0 } },
+            });
+            if let Some(parent) = &span.parent_span_id {
+                encoded["parentSpanId"] = json!(parent);
+            }
+            encoded
+        })
+        .collect();
+
+    json!({
+        "resourceSpans": [{
+            "resource": { "attributes": [attribute("service.name", &AttrValue::from(service))] },
+            "scopeSpans": [{ "scope": { "name": "http_common::trace" }, "spans": spans }],
+        }]
+    })
+}
+
+fn attribute(key: &str, value: &AttrValue) -> Value {
+    match value {
+        AttrValue::Str(text) => json!({ "key": key, "value": { "stringValue": text } }),
+        AttrValue::Int(number) => json!({ "key": key, "value": { "intValue": number.to_string() } }),
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0)
+}
+
+// POST a mano con HTTP/1.1, solo http://; alcanza para un collector en la misma red
+fn post_json(endpoint: &str, body: &str) -> Result<(), String> {
+    let rest = endpoint.strip_prefix("http://").ok_or_else(|| format!("solo se soporta http:// en '{}'", endpoint))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let host_port = if authority.contains(':') { authority.to_string() } else { format!("{}:80", authority) };
+    let addr = host_port
+        .to_socket_addrs()
+        .map_err(|e| format!("{}: {}", host_port, e))?
+        .next()
+        .ok_or_else(|| format!("{}: sin direcciones", host_port))?;
+
+    let mut stream = TcpStream::connect_timeout(&addr, EXPORT_TIMEOUT).map_err(|e| format!("{}: {}", endpoint, e))?;
+    stream.set_read_timeout(Some(EXPORT_TIMEOUT)).map_err(|e| e.to_string())?;
+    stream.set_write_timeout(Some(EXPORT_TIMEOUT)).map_err(|e| e.to_string())?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        authority,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).map_err(|e| format!("{}: {}", endpoint, e))?;
+
+    // Solo interesa el codigo de la linea de estado
+    let mut head = [0u8; 64];
+    let read = stream.read(&mut head).map_err(|e| format!("{}: {}", endpoint, e))?;
+    let status_line = String::from_utf8_lossy(&head[..read]);
+    match status_line.split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok()) {
+        Some(200..=299) => Ok(()),
+        Some(code) => Err(format!("{} respondio {}", endpoint, code)),
+        None => Err(format!("{} respondio algo que no es HTTP", endpoint)),
+    }
+}
+
+fn new_span_id() -> String {
+    loop {
+        let id = rand::random::<u64>();
+        if id != 0 {
+            return format!("{:016x}", id);
+        }
+    }
+}
+
+fn random_nonzero_u128() -> u128 {
+    loop {
+        let id = rand::random::<u128>();
+        if id != 0 {
+            return id;
+        }
+    }
+}
+
+impl FromStr for TraceExporter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TraceExporter, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" | "" => Ok(TraceExporter::None),
+            "otlp" => Ok(TraceExporter::Otlp),
+            "file" => Ok(TraceExporter::File),
+            _ => Err(format!("exportador de trazas invalido '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for TraceExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TraceExporter::None => "none",
+            TraceExporter::Otlp => "otlp",
+            TraceExporter::File => "file",
+        })
+    }
+}
@@ -0,0 +1,101 @@
+// Lineas del access log en cada formato y rotacion del archivo
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, UNIX_EPOCH};
+
+use http_common::access_log::{format_entry, AccessEntry, AccessLog, AccessLogFormat, AccessLogSettings};
+use http_common::request::{parse_request, Limits};
+use serde_json::Value;
+
+const LIMITS: Limits = Limits { max_header_bytes: 8 * 1024, max_body_bytes: 1024 };
+
+#[test]
+fn formats_common_combined_extended_and_json() {
+    let raw = b"GET /reverse?text=a%22b HTTP/1.1\r\nHost: x\r\nUser-Agent: prueba \"1.0\"\r\nReferer: http://ejemplo/\r\n\r\n";
+    let (request, _) = parse_request(raw, &LIMITS).unwrap().unwrap();
+    let entry = AccessEntry {
+        client: Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 5))),
+        time: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+        request: Some(&request),
+        status: 200,
+        bytes: 27,
+        duration: Duration::from_millis(3),
+        worker: Some("worker1"),
+        request_id: "ab12",
+    };
+
+    assert_eq!(
+        format_entry(AccessLogFormat::Common, &entry),
+        "10.0.0.5 - - [14/Nov/2023:22:13:20 +0000] \"GET /reverse?text=a%22b HTTP/1.1\" 200 27\n"
+    );
+    assert_eq!(
+        format_entry(AccessLogFormat::Combined, &entry),
+        "10.0.0.5 - - [14/Nov/2023:22:13:20 +0000] \"GET /reverse?text=a%22b HTTP/1.1\" 200 27 \"http://ejemplo/\" \"prueba \\\"1.0\\\"\"\n"
+    );
+    // Los campos propios solo van en extended
+    assert_eq!(
+        format_entry(AccessLogFormat::Extended, &entry),
+        "10.0.0.5 - - [14/Nov/2023:22:13:20 +0000] \"GET /reverse?text=a%22b HTTP/1.1\" 200 27 \"http://ejemplo/\" \"prueba \\\"1.0\\\"\" 3 \"worker1\" \"ab12\"\n"
+    );
+
+    let json: Value = serde_json::from_str(&format_entry(AccessLogFormat::Json, &entry)).unwrap();
+    assert_eq!(json["time"], "2023-11-14T22:13:20.123Z");
+    assert_eq!(json["target"], "/reverse?text=a%22b");
+    assert_eq!(json["bytes"], 27);
+    assert_eq!(json["user_agent"], "prueba \"1.0\"");
+    assert_eq!(json["worker"], "worker1");
+    assert_eq!(json["request_id"], "ab12");
+    assert_eq!(json["duration_ms"], 3);
+
+    // Una solicitud que no se pudo leer no tiene linea de solicitud, y sin body los bytes son "-"
+    let unreadable = AccessEntry { client: None, request: None, status: 400, bytes: 0, worker: None, ..entry };
+    assert_eq!(
+        format_entry(AccessLogFormat::Combined, &unreadable),
+        "- - - [14/Nov/2023:22:13:20 +0000] \"-\" 400 - \"-\" \"-\"\n"
+    );
+    assert_eq!(
+        format_entry(AccessLogFormat::Extended, &unreadable),
+        "- - - [14/Nov/2023:22:13:20 +0000] \"-\" 400 - \"-\" \"-\" 3 \"-\" \"ab12\"\n"
+    );
+    assert_eq!("CLF".parse::<AccessLogFormat>().unwrap(), AccessLogFormat::Common);
+    assert_eq!("Extended".parse::<AccessLogFormat>().unwrap(), AccessLogFormat::Extended);
+    assert!("apache".parse::<AccessLogFormat>().is_err());
+}
+
+#[test]
+fn rotates_the_file_when_it_gets_too_big() {
+    let dir = std::env::temp_dir().join(format!("http_distribuido-access-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("access.log");
+    let log = AccessLog::new(&AccessLogSettings {
+        target: path.to_string_lossy().into_owned(),
+        format: AccessLogFormat::Extended,
+        max_bytes: 140,
+        max_files: 2,
+    });
+
+    // Cada linea ocupa 66 bytes: entran dos por archivo
+    for i in 0..8 {
+        let request_id = format!("id{}", i);
+        log.write(&AccessEntry {
+            client: None,
+            time: UNIX_EPOCH,
+            request: None,
+            status: 200,
+            bytes: 10,
+            duration: Duration::ZERO,
+            worker: None,
+            request_id: &request_id,
+        });
+    }
+
+    // Soltar el log espera a que el hilo escriba todo lo que estaba en la cola
+    drop(log);
+    let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap_or_default();
+    let (current, first, second) = (read("access.log"), read("access.log.1"), read("access.log.2"));
+    let _ = std::fs::remove_dir_all(&dir);
+    assert!(current.contains("\"id7\"") && current.lines().count() == 2, "{}", current);
+    assert!(first.contains("\"id5\"") && first.lines().count() == 2, "{}", first);
+    assert!(second.contains("\"id3\""), "{}", second);
+    assert!(current.len() <= 140 && first.len() <= 140);
+}
@@ -0,0 +1,30 @@
+// Formato de los eventos de log en texto y en JSON
+use std::time::{Duration, UNIX_EPOCH};
+
+use http_common::log::{format_event, Level, LogFormat};
+use serde_json::Value;
+
+#[test]
+fn formats_events_as_text_and_json() {
+    let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+    let fields = [("request_id", "ab12".to_string()), ("path", "/reverse con espacio".to_string())];
+
+    let text = format_event(LogFormat::Text, time, Level::Warn, "forward", "Fallo al reenviar", &fields);
+    assert_eq!(text, "2023-11-14T22:13:20.123Z WARN  [forward] Fallo al reenviar request_id=ab12 path=\"/reverse con espacio\"\n");
+
+    let json = format_event(LogFormat::Json, time, Level::Info, "forward", "Dice \"hola\"", &fields);
+    assert!(json.ends_with('\n') && json.lines().count() == 1);
+    let event: Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(event["time"], "2023-11-14T22:13:20.123Z");
+    assert_eq!(event["level"], "info");
+    assert_eq!(event["message"], "Dice \"hola\"");
+    assert_eq!(event["request_id"], "ab12");
+}
+
+#[test]
+fn parses_levels_and_formats() {
+    assert_eq!("WARNING".parse::<Level>().unwrap(), Level::Warn);
+    assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+    assert!("verbose".parse::<Level>().is_err());
+    assert!(Level::Error < Level::Debug);
+}
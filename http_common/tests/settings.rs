@@ -0,0 +1,54 @@
+// Precedencia de las fuentes de configuracion. Las claves son inventadas para no chocar con variables de entorno reales.
+use std::fs;
+use std::path::PathBuf;
+
+use http_common::config::Settings;
+
+fn write_config(name: &str, text: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("settings-{}-{}.toml", name, std::process::id()));
+    fs::write(&path, text).unwrap();
+    path
+}
+
+fn args(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn flags_win_over_file_and_file_over_defaults() {
+    let path = write_config("precedence", "test_port = 9000\ntest_name = \"archivo\"\n");
+    let settings = Settings::from_args(args(&["--config", path.to_str().unwrap(), "--test-port=9100"]), "worker").unwrap();
+
+    assert_eq!(settings.get("test_port", 1u16), 9100);
+    assert_eq!(settings.get("test_name", "defecto".to_string()), "archivo");
+    assert_eq!(settings.get("test_missing", 7u32), 7);
+    // Un valor invalido se ignora y se usa la siguiente fuente
+    let settings = Settings::from_args(args(&["--config", path.to_str().unwrap(), "--test-port", "abc"]), "worker").unwrap();
+    assert_eq!(settings.get("test_port", 1u16), 9000);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn service_section_overrides_shared_keys() {
+    let path = write_config(
+        "sections",
+        "test_addr = \"0.0.0.0:1\"\ntest_list = [\"a\", \"b\"]\n\n[worker]\ntest_addr = \"127.0.0.1:7878\"\n\n[dispatcher]\ntest_addr = \"127.0.0.1:8080\"\n",
+    );
+    let worker = Settings::from_args(args(&["--config", path.to_str().unwrap()]), "worker").unwrap();
+    let dispatcher = Settings::from_args(args(&["--config", path.to_str().unwrap()]), "dispatcher").unwrap();
+
+    assert_eq!(worker.get("test_addr", String::new()), "127.0.0.1:7878");
+    assert_eq!(dispatcher.get("test_addr", String::new()), "127.0.0.1:8080");
+    assert_eq!(worker.get("test_list", String::new()), "a,b");
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn bare_flags_are_true_and_unknown_flags_are_rejected() {
+    let settings = Settings::from_args(args(&["--test-enabled", "--test-typo", "3"]), "worker").unwrap();
+
+    assert!(settings.get("test_enabled", false));
+    let err = settings.check_unused().unwrap_err();
+    assert!(err.contains("--test-typo"), "{}", err);
+    assert!(Settings::from_args(args(&["positional"]), "worker").is_err());
+}
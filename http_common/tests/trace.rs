@@ -0,0 +1,69 @@
+// Header traceparent y exportacion de spans a un archivo
+use std::time::Duration;
+
+use http_common::trace::{Span, SpanKind, TraceContext, TraceExporter, TraceSettings, Tracer};
+use serde_json::Value;
+
+#[test]
+fn parses_and_writes_traceparent() {
+    let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+    let context = TraceContext::parse(header).unwrap();
+    assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_eq!(context.span_id, "00f067aa0ba902b7");
+    assert!(context.sampled);
+    assert_eq!(context.to_header(), header);
+
+    for invalid in [
+        "",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+        "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+        "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+    ] {
+        assert_eq!(TraceContext::parse(invalid), None, "{}", invalid);
+    }
+
+    // Un hijo sigue la misma traza con otro span
+    let span = Span::server("GET", Some(context.clone()));
+    let child = span.child("forward", SpanKind::Client);
+    assert_eq!(child.context().trace_id, context.trace_id);
+    assert_ne!(child.context().span_id, span.context().span_id);
+    assert_ne!(span.context().span_id, context.span_id);
+}
+
+#[test]
+fn file_exporter_writes_sampled_spans() {
+    let path = std::env::temp_dir().join(format!("http_distribuido-trazas-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let settings = TraceSettings {
+        exporter: TraceExporter::File,
+        otlp_endpoint: String::new(),
+        file: path.to_string_lossy().into_owned(),
+        flush_interval: Duration::from_secs(60),
+    };
+    let tracer = Tracer::new("prueba", &settings);
+
+    let mut span = Span::server("GET", None);
+    span.record_response("GET", "/fibonacci", "/fibonacci", 500, "ab12");
+    tracer.end(span.child("execute", SpanKind::Internal));
+    tracer.end(span);
+    let unsampled = TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00");
+    tracer.end(Span::server("GET", unsampled));
+    // Al soltarse exporta lo pendiente aunque no haya pasado el intervalo
+    drop(tracer);
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let batch: Value = serde_json::from_str(text.lines().next().unwrap()).unwrap();
+    let resource = &batch["resourceSpans"][0];
+    assert_eq!(resource["resource"]["attributes"][0]["value"]["stringValue"], "prueba");
+    let spans = resource["scopeSpans"][0]["spans"].as_array().unwrap();
+    assert_eq!(spans.len(), 2, "{}", text);
+    let server = &spans[1];
+    assert_eq!(server["name"], "GET /fibonacci");
+    assert_eq!(server["status"]["code"], 2);
+    assert_eq!(spans[0]["parentSpanId"], server["spanId"]);
+    assert!(server.get("parentSpanId").is_none());
+}
@@ -0,0 +1,238 @@
+// API de administracion: alta y baja de workers sin reiniciar el dispatcher
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use http_common::protocol::{Registration, RegistrationAck, WORKERS_ADMIN_PATH};
+use http_common::request::Request;
+use http_common::response::Response;
+use http_common::info;
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::auxiliares::{handle_workers_status_request, AppContext, DispatcherState, Worker, WorkerSource};
+use crate::metrics::Metrics;
+use crate::responses::{
+    http_resonse_400, http_response_200, http_response_201, http_response_401, http_response_403, http_response_404,
+    http_response_405,
+};
+
+// Pausa minima entre revisiones de registros vencidos
+const MIN_EXPIRY_TICK: Duration = Duration::from_millis(50);
+
+//Respuesta de DELETE /admin/workers/{id}
+#[derive(Serialize)]
+struct RemovedWorker {
+    id: String,
+    address: String,
+    message: String,
+}
+
+/*
+Rutas bajo /admin:
+  GET    /admin/workers       lista de workers, igual que /workers
+  POST   /admin/workers       alta (o renovacion) de un worker, body JSON `Registration`
+  DELETE /admin/workers/{id}  baja de un worker
+Si ADMIN_TOKEN esta definido se exige el header `Authorization: Bearer <token>`;
+si no, solo se atienden conexiones desde localhost.
+*/
+pub async fn handle_admin_request(request: &Request, ctx: &AppContext, client: Option<IpAddr>) -> Response {
+    if let Err(response) = check_access(request, &ctx.config.admin_token, client) {
+        return response;
+    }
+
+    let path = request.path.as_str();
+    if path == WORKERS_ADMIN_PATH {
+        return match request.method.as_str() {
+            "GET" => handle_workers_status_request(ctx).await,
+            "POST" => register_worker(request, ctx).await,
+            _ => http_response_405("GET, POST"),
+        };
+    }
+
+    match path.strip_prefix(WORKERS_ADMIN_PATH).and_then(|rest| rest.strip_prefix('/')) {
+        Some(id) if !id.is_empty() && !id.contains('/') => match request.method.as_str() {
+            "DELETE" => remove_worker(id, &ctx.state, &ctx.metrics).await,
+            _ => http_response_405("DELETE"),
+        },
+        _ => http_response_404("Ruta de administracion no encontrada"),
+    }
+}
+
+/*
+Sin token la API no queda abierta: el puerto del dispatcher suele estar publicado,
+asi que solo se aceptan clientes locales. `to_canonical` cubre ::ffff:127.0.0.1.
+*/
+fn check_access(request: &Request, token: &str, client: Option<IpAddr>) -> Result<(), Response> {
+    if token.is_empty() {
+        return match client {
+            Some(ip) if ip.to_canonical().is_loopback() => Ok(()),
+            _ => Err(http_response_403("Sin ADMIN_TOKEN la API de administracion solo acepta conexiones locales")),
+        };
+    }
+
+    let authorized = request
+        .header("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.trim().as_bytes(), token.as_bytes()));
+    if !authorized {
+        return Err(http_response_401("Falta el token de administracion o no es valido"));
+    }
+    Ok(())
+}
+
+// Compara sin cortar en el primer byte distinto, asi el tiempo de respuesta no revela el token
+fn constant_time_eq(given: &[u8], expected: &[u8]) -> bool {
+    let mut diff = given.len() ^ expected.len();
+    for i in 0..given.len().max(expected.len()) {
+        diff |= (given.get(i).copied().unwrap_or(0) ^ expected.get(i).copied().unwrap_or(0)) as usize;
+    }
+    diff == 0
+}
+
+/*
+Da de alta un worker o renueva su registro si la direccion ya existe.
+Los workers nuevos empiezan con el circuito abierto y reciben tareas cuando pasan el healthcheck.
+*/
+async fn register_worker(request: &Request, ctx: &AppContext) -> Response {
+    let registration: Registration = match serde_json::from_slice(&request.body) {
+        Ok(registration) => registration,
+        Err(e) => return http_resonse_400(&format!("Body invalido, se esperaba JSON con 'address': {}", e)),
+    };
+
+    let address = registration.address.trim().trim_end_matches('/').to_string();
+    if !address.starts_with("http://") && !address.starts_with("https://") {
+        return http_resonse_400("'address' debe empezar con http:// o https://");
+    }
+    if registration.weight == 0 {
+        return http_resonse_400("'weight' debe ser mayor que 0");
+    }
+
+    let ttl = ctx.config.registration_ttl;
+    let expires_at = registration.heartbeat.then(|| Instant::now() + ttl);
+    let source = if registration.heartbeat { WorkerSource::Heartbeat } else { WorkerSource::Admin };
+
+    let mut state = ctx.state.lock().await;
+    if let Some(worker) = state.workers.iter_mut().find(|w| w.address == address) {
+        worker.weight = registration.weight;
+        // Un heartbeat no le pone vencimiento a un worker que se agrego a mano o por configuracion
+        if worker.expires_at.is_some() || !registration.heartbeat {
+            worker.expires_at = expires_at;
+            if worker.source != WorkerSource::Config {
+                worker.source = source;
+            }
+        }
+        return http_response_200(&ack(worker, ttl));
+    }
+
+    let id = format!("worker{}", state.next_worker_id);
+    state.next_worker_id += 1;
+
+    let mut worker = Worker::new(&id, &address, registration.weight);
+    worker.source = source;
+    worker.expires_at = expires_at;
+    info!("admin", worker = id, address = address, weight = worker.weight; "Worker registrado ({:?})", source);
+
+    let response = http_response_201(&ack(&worker, ttl));
+    state.workers.push(worker);
+    response
+}
+
+async fn remove_worker(id: &str, state_dispatcher: &Arc<Mutex<DispatcherState>>, metrics: &Metrics) -> Response {
+    let mut state = state_dispatcher.lock().await;
+    let Some(index) = state.workers.iter().position(|w| w.id == id) else {
+        return http_response_404(&format!("No existe el worker '{}'", id));
+    };
+
+    //Las tareas que ya estaban en curso terminan normalmente, solo dejan de llegar nuevas
+    let worker = state.workers.remove(index);
+    metrics.forget_worker(&worker.id);
+    info!("admin", worker = worker.id, address = worker.address; "Worker eliminado");
+    http_response_200(&RemovedWorker {
+        id: worker.id,
+        address: worker.address,
+        message: "Worker eliminado".to_string(),
+    })
+}
+
+fn ack(worker: &Worker, ttl: Duration) -> RegistrationAck {
+    RegistrationAck {
+        id: worker.id.clone(),
+        address: worker.address.clone(),
+        weight: worker.weight,
+        expires_in_secs: worker.expires_at.map(|_| ttl.as_secs()),
+    }
+}
+
+/*
+Borra los workers auto-registrados que no renovaron el registro a tiempo.
+Se revisa varias veces por cada REGISTRATION_TTL_SECS para no pasarse mucho del plazo.
+*/
+pub async fn expire_registrations(state_dispatcher: Arc<Mutex<DispatcherState>>, ttl: Duration, metrics: Arc<Metrics>) {
+    let tick = (ttl / 4).max(MIN_EXPIRY_TICK);
+    loop {
+        tokio::time::sleep(tick).await;
+
+        let now = Instant::now();
+        let mut state = state_dispatcher.lock().await;
+        state.workers.retain(|w| {
+            let expired = w.expires_at.is_some_and(|at| at <= now);
+            if expired {
+                info!("admin", worker = w.id, address = w.address; "El registro vencio sin heartbeat, se elimina");
+                metrics.forget_worker(&w.id);
+            }
+            !expired
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use http_common::request::{parse_request, Limits};
+
+    use super::*;
+
+    fn request(authorization: &str) -> Request {
+        let raw = format!("GET /admin/workers HTTP/1.1\r\nHost: x\r\n{}\r\n", authorization);
+        let limits = Limits { max_header_bytes: 1024, max_body_bytes: 0 };
+        parse_request(raw.as_bytes(), &limits).unwrap().unwrap().0
+    }
+
+    fn status(result: Result<(), Response>) -> u16 {
+        result.map_or_else(|response| response.status(), |_| 200)
+    }
+
+    #[test]
+    fn without_token_only_local_clients_are_allowed() {
+        let local = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let mapped = Some(IpAddr::V6(Ipv4Addr::LOCALHOST.to_ipv6_mapped()));
+        let remote = Some(IpAddr::V4(Ipv4Addr::new(172, 18, 0, 5)));
+        assert_eq!(status(check_access(&request(""), "", local)), 200);
+        assert_eq!(status(check_access(&request(""), "", mapped)), 200);
+        assert_eq!(status(check_access(&request(""), "", Some(IpAddr::V6(Ipv6Addr::LOCALHOST)))), 200);
+        assert_eq!(status(check_access(&request(""), "", remote)), 403);
+        assert_eq!(status(check_access(&request(""), "", None)), 403);
+    }
+
+    #[test]
+    fn with_token_every_client_needs_it() {
+        let local = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let remote = Some(IpAddr::V4(Ipv4Addr::new(172, 18, 0, 5)));
+        assert_eq!(status(check_access(&request("Authorization: Bearer secreto\r\n"), "secreto", remote)), 200);
+        assert_eq!(status(check_access(&request(""), "secreto", local)), 401);
+        assert_eq!(status(check_access(&request("Authorization: Bearer otro\r\n"), "secreto", local)), 401);
+        assert_eq!(status(check_access(&request("Authorization: secreto\r\n"), "secreto", local)), 401);
+        assert_eq!(status(check_access(&request("Authorization: Bearer secret\r\n"), "secreto", local)), 401);
+        assert_eq!(status(check_access(&request("Authorization: Bearer secretos\r\n"), "secreto", local)), 401);
+    }
+
+    #[test]
+    fn token_comparison_checks_every_byte_and_the_length() {
+        assert!(constant_time_eq(b"secreto", b"secreto"));
+        assert!(!constant_time_eq(b"secretO", b"secreto"));
+        assert!(!constant_time_eq(b"secreto\0", b"secreto"));
+        assert!(!constant_time_eq(b"", b"secreto"));
+    }
+}
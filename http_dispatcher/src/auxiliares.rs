@@ -1,21 +1,38 @@
-use std::collections::HashMap;
 // Funciones que necesita el dispatcher para funcionar
-use std::sync::{Arc, Mutex, MutexGuard};
-use std::net::{TcpStream};
-use std::time::Duration;
-use std::io::{Read, Write};
-use std::env;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 use futures::future::join_all;
-use serde::Deserialize;
-
-use crate::responses::{http_resonse_400, http_response_200, http_response_500_json};
-
-//Estructura que define el estado de un Worker
-#[derive(Debug, Clone, PartialEq)]
-pub enum WorkerStatus {
-    Active,
-    Inactive,
+use http_common::access_log::{AccessEntry, AccessLog};
+use http_common::protocol::{new_request_id, MontecarloResult, MontecarloTask, DEADLINE_HEADER, REQUEST_ID_HEADER, TASK_ROUTES};
+use http_common::request::{parse_body, parse_head, Limits, ParseError, ReadError, Request};
+use http_common::response::Response;
+use http_common::trace::{Span, SpanKind, TraceContext, Tracer, TRACEPARENT_HEADER};
+use http_common::{debug, info, warn};
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use crate::admin::handle_admin_request;
+use crate::circuit_breaker::{BreakerSettings, CircuitBreaker, CircuitReport, CircuitState, Outcome};
+use crate::config::Config;
+use crate::idempotency::{Claim, IdempotencyStore, IDEMPOTENCY_KEY_HEADER};
+use crate::load_balancer::{request_key, LoadBalancer};
+use crate::metrics::Metrics;
+use crate::reload::read_worker_list;
+use crate::retry::RetryPolicy;
+use crate::responses::{SERVER_NAME, http_resonse_400, http_response_200, http_response_409, http_response_413, http_response_422, http_response_431, http_response_500_json, http_response_502, http_response_503, http_response_504, http_response_metrics};
+
+//De donde salio el worker, se muestra en /workers
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkerSource {
+    Config,    //WORKER_ADDRESSES al iniciar
+    Admin,     //POST /admin/workers
+    Heartbeat, //El worker se registro solo y tiene que renovar el registro
 }
 
 //Worker en el sistema
@@ -23,291 +40,666 @@ pub enum WorkerStatus {
 pub struct Worker {
     pub id: String,
     pub address: String,
-    pub status: WorkerStatus,
+    pub circuit: CircuitBreaker,     //Decide si recibe tareas segun los errores, la lentitud y el healthcheck
+    pub draining: bool,              //Se quito de WORKERS_FILE, termina sus tareas y se elimina
     pub task_completed: u64,
     pub tasks_failed: u64,
+    pub weight: u32,                 //Peso para round robin ponderado
+    pub in_flight: Arc<AtomicU64>,   //Tareas enviadas que todavia no terminan
+    pub source: WorkerSource,        //Configuracion, API de administracion o auto-registro
+    pub expires_at: Option<Instant>, //Cuando se borra si no renueva el registro
 }
 
-//Tiene todo el estado del dispatcher
-#[derive(Debug)]
-pub struct DispatcherState {
-    pub workers: Vec<Worker>,
-    pub next_worker_index: usize, //Index para estrategia de RR
-}
+impl Worker {
+    //Worker nuevo, su circuito empieza abierto hasta que pase el healthcheck
+    pub fn new(id: &str, address: &str, weight: u32) -> Worker {
+        Worker {
+            id: id.to_string(),
+            address: address.trim().to_string(),
+            circuit: CircuitBreaker::new(),
+            draining: false,
+            task_completed: 0,
+            tasks_failed: 0,
+            weight,
+            in_flight: Arc::new(AtomicU64::new(0)),
+            source: WorkerSource::Config,
+            expires_at: None,
+        }
+    }
 
-#[derive(Deserialize)]
-struct WorkerResponse {
-    hits: u64,
-}
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
 
-pub fn initialize_workers() -> Vec<Worker> {
-    println!("Buscando variable de entorno WORKER_ADDRESSE...");
-    // Hay quie implementar toda la logica para leer el .env
-    // La variable tendra las direcciones de los workers
-    let addresses = env::var("WORKER_ADDRESSES").expect("Variable de entorno no esta definida");
+    //Puede recibir una tarea: no se esta drenando y el circuito lo deja pasar
+    pub fn is_available(&self) -> bool {
+        !self.draining && self.circuit.allows(self.in_flight())
+    }
 
-    let workers: Vec<Worker> = addresses.split(',')
-    .enumerate().map(|(i, address)| {
-        let worker_id = format!("worker{}", i + 1);
-        println!("[Init] Configurando worker: {} con direccion {}", worker_id, address);
-        Worker{
-            id: worker_id,
-            address: address.trim().to_string(),
-            status: WorkerStatus::Inactive,
-            task_completed:0,
-            tasks_failed:0
+    //Registra como termino una tarea y avisa en el log si el circuito cambio de estado
+    pub fn record_outcome(&mut self, outcome: Outcome, settings: &BreakerSettings) {
+        if let Some(state) = self.circuit.record(outcome, settings) {
+            self.log_circuit(state);
         }
-    }).collect();
+    }
 
-    if workers.is_empty(){
-        panic!("La variable WORKER_ADDRESSES esta vacia. No se pueden configurar los workers.")
+    pub fn log_circuit(&self, state: CircuitState) {
+        match self.circuit.reason() {
+            Some(reason) => warn!("circuit", worker = self.id, address = self.address; "El circuito paso a {:?} ({:?})", state, reason),
+            None => info!("circuit", worker = self.id, address = self.address; "El circuito paso a {:?}", state),
+        }
     }
+}
 
-    workers
+/*
+Cuenta una tarea en curso en un worker mientras exista.
+Al soltarse se descuenta sola, aunque la tarea termine por error o se cancele,
+asi el contador no se queda inflado.
+*/
+pub struct InFlightGuard {
+    counter: Arc<AtomicU64>,
 }
 
-pub async fn health_check(state_dispatcher: Arc<Mutex<DispatcherState>>) {
-    let client = reqwest::Client::new();
-    loop {
-        //Bloquear el mutex, iterar sobre los workers y hacerles ping
-        //Vamos actualizando el status segun la respuesta
-        println!("(Healthcheck) Verificando estado de workers...");
+impl InFlightGuard {
+    pub fn start(worker: &Worker) -> InFlightGuard {
+        worker.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard { counter: worker.in_flight.clone() }
+    }
+}
 
-        let workers_to_check = {
-            let state = state_dispatcher.lock().unwrap();
-            state.workers.iter().map(|w| (w.id.clone(), w.address.clone())).collect::<Vec<_>>()
-        };
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::Relaxed);
+    }
+}
 
-        for (id, address) in workers_to_check {
-            let ping_url = format!("{}/ping", address);
-            let mut new_status = WorkerStatus::Inactive; // Vamos a asumir que esta inactivo
+//Tiene todo el estado del dispatcher
+#[derive(Debug)]
+pub struct DispatcherState {
+    pub workers: Vec<Worker>,
+    pub balancer: Box<dyn LoadBalancer>, //Estrategia para elegir el worker de cada tarea
+    pub next_worker_id: usize,           //Numero para el id del proximo worker registrado, los ids no se reutilizan
+}
 
-            // Hacemos la peticion de ping con un timeout
-            let request = client.get(&ping_url).timeout(Duration::from_secs(2));
+//Lo que comparten todas las conexiones de clientes
+#[derive(Clone)]
+pub struct AppContext {
+    pub state: Arc<Mutex<DispatcherState>>,
+    pub client: reqwest::Client,            //Cliente con pool de conexiones hacia los workers
+    pub config: Arc<Config>,
+    pub retry: Arc<RetryPolicy>,            //Reintentos y su presupuesto, compartido por todas las tareas
+    pub idempotency: Arc<IdempotencyStore>, //Respuestas guardadas por Idempotency-Key
+    pub metrics: Arc<Metrics>,
+    pub tracer: Arc<Tracer>,                //Exporta los spans de las solicitudes
+    pub access_log: Arc<AccessLog>,
+}
 
-            match request.send().await {
-                Ok(response) if response.status().is_success() => {
-                    println!("(Healthcheck) Worker {} en {} respondio correctamente.", id, address);
-                    new_status = WorkerStatus::Active;
-                }
-                Ok(response) => { // Worker respondio, con codigo de error
-                    println!("(Healthcheck) Worker {} en {} respondio con error: {}", id, address, response.status());
-                }
-                Err(e) => { //La peticion fallo (timeout, no se pudo conectar)
-                    println!("(Healthcheck) Fallo al contactar worker {} en {}: {}", id, address, e);
-                }
-            }
+//Respuesta de /workers
+#[derive(Serialize)]
+struct WorkersReport<'a> {
+    strategy: &'static str,
+    workers: Vec<WorkerReport<'a>>,
+}
 
-            //Actualizamos el estado del worker
-            {
-                let mut state = state_dispatcher.lock().unwrap();
-                if let Some(worker) = state.workers.iter_mut().find(|w| w.id == id) {
-                    worker.status = new_status;
-                }
-            }
-        }
+#[derive(Serialize)]
+struct WorkerReport<'a> {
+    id: &'a str,
+    address: &'a str,
+    circuit: CircuitReport,
+    draining: bool,
+    weight: u32,
+    in_flight: u64,
+    tasks_completed: u64,
+    tasks_failed: u64,
+    source: WorkerSource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_in_secs: Option<u64>,
+}
 
-        // Dormimos para la proxima verificacion
-        tokio::time::sleep(Duration::from_secs(100)).await;
-    }
+//Respuesta de /montecarlo
+#[derive(Serialize)]
+struct MontecarloEstimate {
+    pi_estimate: f64,
+    total_points_simulated: u64,
+    total_hits: u64,
 }
 
-//Funcio que va a parsear la linea de la peticion
-fn parse_request_line(request: &str) -> (&str, &str) {
-    if let Some(request_line) = request.lines().next() {
-        let parts: Vec<&str> = request_line.split_whitespace().collect();
-        if parts.len() >= 2 {
-            return (parts[0], parts[1]);
+pub fn initialize_workers(config: &Config) -> Vec<Worker> {
+    //Con WORKERS_FILE la lista sale de ese archivo y se puede recargar despues
+    if !config.workers_file.is_empty() {
+        if !config.worker_addresses.trim().is_empty() {
+            warn!("init", "WORKERS_FILE esta definido, se ignora WORKER_ADDRESSES");
         }
+        let entries = read_worker_list(&config.workers_file).unwrap_or_else(|e| panic!("{}", e));
+        return entries.iter().enumerate().map(|(i, entry)| {
+            let worker_id = format!("worker{}", i + 1);
+            info!("init", worker = worker_id, address = entry.address, weight = entry.weight; "Worker configurado");
+            Worker::new(&worker_id, &entry.address, entry.weight)
+        }).collect();
+    }
+
+    // Las direcciones vienen de --worker-addresses, WORKER_ADDRESSES o del archivo de configuracion
+    let addresses = config.worker_addresses.trim();
+    if addresses.is_empty() {
+        panic!("No hay workers configurados (WORKER_ADDRESSES esta vacia). No se pueden configurar los workers.")
     }
+    //Pesos opcionales en el mismo orden que las direcciones, por defecto 1
+    let weights: Vec<&str> = config.worker_weights.split(',').collect();
 
-    ("", "")
+    addresses.split(',')
+    .enumerate().map(|(i, address)| {
+        let address = address.trim();
+        let worker_id = format!("worker{}", i + 1);
+        let weight = match weights.get(i).map(|w| w.trim()).filter(|w| !w.is_empty()) {
+            Some(w) => w.parse::<u32>().ok().filter(|w| *w > 0).unwrap_or_else(|| {
+                warn!("init", worker = worker_id; "Peso invalido '{}', se usa 1", w);
+                1
+            }),
+            None => 1,
+        };
+        info!("init", worker = worker_id, address = address, weight = weight; "Worker configurado");
+        Worker::new(&worker_id, address, weight)
+    }).collect()
 }
 
-//Separa la ruta de los parametros
-fn parse_query(path_query: &str) -> (String, HashMap<String, String>) {
-    let mut parts = path_query.splitn(2, '?');
-    let route = parts.next().unwrap_or("").to_string();
-    let mut query_map = HashMap::new();
-
-    if let Some(query) = parts.next() {
-        for param in query.split('&') {
-            let mut kv = param.splitn(2, '=');
-            if let (Some(key), Some(value)) = (kv.next(), kv.next()) {
-                query_map.insert(key.to_string(), value.to_string());
-            }
+/*
+Cliente HTTP compartido para hablar con los workers.
+Mantiene un pool de conexiones keep-alive que se reutilizan entre tareas.
+*/
+pub fn build_http_client(config: &Config) -> reqwest::Client {
+    reqwest::Client::builder()
+        .pool_idle_timeout(config.upstream_idle_timeout)
+        .pool_max_idle_per_host(config.upstream_max_idle_per_host)
+        .build()
+        .expect("No se pudo crear el cliente HTTP")
+}
+
+/*
+Atiende la conexion de un cliente: lee la solicitud, la resuelve y responde.
+Corre como una tarea de Tokio, no ocupa un hilo mientras espera a los workers.
+*/
+pub async fn handle_cliente(mut stream: TcpStream, ctx: AppContext) {
+    let read = tokio::time::timeout(ctx.config.client_read_timeout, read_request(&mut stream, &ctx.config.limits)).await;
+
+    let started = Instant::now();
+    let received = SystemTime::now();
+    let client = stream.peer_addr().ok().map(|addr| addr.ip());
+    let mut served_request = None;
+    let mut served_by = None; //Worker que atendio la tarea, para el access log
+    let respose = match read {
+        Ok(Ok(mut request)) => {
+            //Si el cliente no envio un X-Request-Id valido se genera uno
+            let request_id = request.ensure_request_id().to_string();
+            debug!("http", request_id = request_id, method = request.method, path = request.path; "Solicitud recibida");
+            //Si el cliente envio traceparent la solicitud sigue su traza, si no empieza una nueva
+            let parent = request.header(TRACEPARENT_HEADER).and_then(TraceContext::parse);
+            let mut span = Span::server(&request.method, parent);
+            let response = {
+                let _in_flight = ctx.metrics.requests_in_flight.track(&[]);
+                route_request(&request, &ctx, client, &span, &mut served_by).await
+            };
+            let route = route_label(&request.path).to_string();
+            span.record_response(&request.method, &request.path, &route, response.status(), &request_id);
+            ctx.tracer.end(span);
+            served_request = Some((route, request));
+            response
+        }
+        Ok(Err(ReadError::Closed)) => return,
+        Ok(Err(ReadError::Io(e))) => {
+            warn!("http", "Error al leer la solicitud: {}", e);
+            return;
         }
+        Ok(Err(ReadError::Parse(ParseError::BadRequest(msg)))) => http_resonse_400(&msg),
+        Ok(Err(ReadError::Parse(ParseError::HeadersTooLarge))) => http_response_431("Los headers de la solicitud son demasiado grandes"),
+        Ok(Err(ReadError::Parse(ParseError::PayloadTooLarge))) => http_response_413("El body de la solicitud es demasiado grande"),
+        Err(_) => {
+            warn!("http", "Tiempo de espera agotado leyendo la solicitud del cliente");
+            return;
+        }
+    };
+
+    // Una solicitud que no se pudo leer no tiene ruta ni metodo, se le da un id para el log
+    let elapsed = started.elapsed();
+    let (route, request, request_id) = match &served_request {
+        Some((route, request)) => (route.as_str(), Some(request), request.request_id().to_string()),
+        None => ("other", None, new_request_id()),
+    };
+    let (method, path) = request.map_or(("unknown", "-"), |r| (r.method.as_str(), r.path.as_str()));
+    ctx.metrics.record_request(route, method, respose.status(), elapsed);
+    info!(
+        "http",
+        request_id = request_id,
+        method = method,
+        path = path,
+        status = respose.status(),
+        duration_ms = elapsed.as_millis();
+        "Solicitud atendida"
+    );
+
+    ctx.access_log.write(&AccessEntry {
+        client,
+        time: received,
+        request,
+        status: respose.status(),
+        bytes: respose.sent_body_len(),
+        duration: elapsed,
+        worker: served_by.as_deref(),
+        request_id: &request_id,
+    });
+
+    //El front end atiende una solicitud por conexion; el id reemplaza al que devolvio el worker, que es el mismo
+    let respose = respose.header(REQUEST_ID_HEADER, &request_id).connection(None);
+    if let Err(e) = stream.write_all(&respose.to_bytes(SERVER_NAME)).await {
+        warn!("http", request_id = request_id; "Error al escribir respuesta: {}", e);
     }
+    stream.flush().await.unwrap_or_default();
+}
 
-    (route, query_map)
+// Decide que hacer con la solicitud segun la ruta; `served_by` recibe el worker que atendio la tarea
+async fn route_request(
+    request: &Request,
+    ctx: &AppContext,
+    client: Option<IpAddr>,
+    span: &Span,
+    served_by: &mut Option<String>,
+) -> Response {
+    match request.path.as_str() {
+        "/workers" => handle_workers_status_request(ctx).await,
+        "/metrics" => http_response_metrics(ctx.metrics.render(&ctx.state.lock().await.workers)),
+        path if path.starts_with("/admin/") => handle_admin_request(request, ctx, client).await,
+        "/montecarlo" => handle_montecarlo_request(request, ctx, span, served_by).await,
+        _ => handle_task_forwarding(request, ctx, span, served_by).await //Cualquier otra ruta se considera para reenvio
+    }
 }
 
-pub fn handle_cliente(mut stream: TcpStream, state_dispatcher: Arc<Mutex<DispatcherState>>) {
-    let mut buffer = [0; 1024];
-    if let Err(e) = stream.read(&mut buffer) {
-        eprintln!("Error al leer: {}", e);
-        return;
+/*
+Ruta para las metricas y las trazas. Las de /admin llevan el id del worker y las reenviadas
+pueden ser cualquier cosa: solo las rutas conocidas del worker tienen serie propia.
+*/
+fn route_label(path: &str) -> &str {
+    match path {
+        "/workers" | "/metrics" | "/montecarlo" => path,
+        path if path.starts_with("/admin/") => "/admin",
+        path if TASK_ROUTES.contains(&path) => path,
+        _ => "other",
     }
+}
 
-    let request_str = String::from_utf8_lossy(&buffer[..]);
+/*
+Lee una solicitud completa del cliente usando el parser compartido.
+Se va leyendo del socket hasta que el parser tiene los headers y el body.
+*/
+async fn read_request(stream: &mut TcpStream, limits: &Limits) -> Result<Request, ReadError> {
+    let mut buffer = Vec::with_capacity(1024);
 
-    let (_method, path_query) = parse_request_line(&request_str);
+    let head = loop {
+        if let Some(head) = parse_head(&buffer, limits)? {
+            break head;
+        }
+        fill_or_fail(stream, &mut buffer).await?;
+    };
 
-    let (path, params) = parse_query(path_query);
+    let kind = head.body_kind(limits)?;
+    let mut continue_sent = false;
+    let (body, _) = loop {
+        if let Some(parsed) = parse_body(&buffer[head.len..], kind, limits)? {
+            break parsed;
+        }
+        if head.expects_continue() && !continue_sent {
+            stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+            continue_sent = true;
+        }
+        fill_or_fail(stream, &mut buffer).await?;
+    };
 
-    let respose = match path.as_str() {
-        "/workers" => handle_workers_status_request(state_dispatcher),
-        "/montecarlo" => {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            let client = reqwest::Client::new();
+    Ok(Request::from_parts(head, body)?)
+}
 
-            rt.block_on(handle_montecarlo_request(&params, &state_dispatcher, &client))
+// Lee mas bytes; si el cliente cierra a mitad de una solicitud es un error
+async fn fill_or_fail(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> Result<(), ReadError> {
+    if stream.read_buf(buffer).await? == 0 {
+        if buffer.is_empty() {
+            return Err(ReadError::Closed);
         }
-        _ => handle_task_forwarding(path_query, state_dispatcher) //Cualquier otra ruta se considera para reenvio
+        return Err(ReadError::Parse(ParseError::BadRequest("Solicitud incompleta".to_string())));
+    }
+    Ok(())
+}
+
+pub(crate) async fn handle_workers_status_request(ctx: &AppContext) -> Response {
+    let state = ctx.state.lock().await;
+
+    let report = WorkersReport {
+        strategy: state.balancer.name(),
+        workers: state.workers.iter().map(|w| WorkerReport {
+            id: &w.id,
+            address: &w.address,
+            circuit: w.circuit.report(&ctx.config.breaker),
+            draining: w.draining,
+            weight: w.weight,
+            in_flight: w.in_flight(),
+            tasks_completed: w.task_completed,
+            tasks_failed: w.tasks_failed,
+            source: w.source,
+            expires_in_secs: w.expires_at.map(|at| at.saturating_duration_since(Instant::now()).as_secs()),
+        }).collect(),
     };
 
-    if let Err(e) = stream.write_all(respose.as_bytes()) {
-        eprintln!("Error al escribir respuesta: {}", e);
-    }
-    stream.flush().unwrap_or_default();
+    http_response_200(&report)
 }
 
-fn handle_workers_status_request(state_dispatcher: Arc<Mutex<DispatcherState>>) -> String {
-    println!("Generando reporte de estado de workers ...");
-    let state = state_dispatcher.lock().unwrap();
-
-    let workers_json: Vec<String> = state.workers.iter().map(|w| {
-        format!(
-            "{{\"id\":\"{}\",\"address\":\"{}\",\"status\":\"{:?}\",\"tasks_completed\":{},\"tasks_failed\":{}}}",
-            w.id, w.address, w.status, w.task_completed, w.tasks_failed
-        )
-    }).collect();
-
-    let body = format!("[{}]", workers_json.join(","));
-    format!(
-        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
-        body.len(),
-        body
-    )
+//Elige el siguiente worker ACTIVO con la estrategia configurada
+pub fn select_next_worker(state: &DispatcherState, key: &str) -> Option<usize>{
+    state.balancer.select(&state.workers, key)
 }
 
-//Se encargar de elegier el siguiente worker ACTIVO con RR
-pub fn select_next_worker(state: &mut MutexGuard<DispatcherState>) -> Option<usize>{
-    println!("Next worker");
-    let num_workers = state.workers.len();
-    if num_workers == 0 {
-        return None;
+/*
+Momento en que vence la tarea: el tiempo de su ruta (o REQUEST_TIMEOUT_MS), o menos
+si el cliente envio su propio X-Deadline-Ms. El bool indica que manda el del cliente.
+*/
+fn task_deadline(request: &Request, config: &Config) -> (Instant, bool) {
+    let route_timeout = config.timeout_for(&request.path);
+    let client_timeout = request.header(DEADLINE_HEADER).and_then(|ms| ms.trim().parse::<u64>().ok()).map(Duration::from_millis);
+    match client_timeout {
+        Some(timeout) if timeout < route_timeout => (Instant::now() + timeout, true),
+        _ => (Instant::now() + route_timeout, false),
     }
-    let mut index = state.next_worker_index;
-    for i in 0..num_workers {
-        if index ==  num_workers - 1 {
-            state.next_worker_index = 0;
-        } else {
-            state.next_worker_index += 1;
+}
+
+/*
+Si manda el limite del cliente, que la tarea venza no dice nada del worker: con un
+X-Deadline-Ms corto cualquiera podria abrir el circuito de un worker sano.
+*/
+fn counts_for_breaker(outcome: Outcome, client_deadline: bool) -> bool {
+    !(client_deadline && outcome == Outcome::TimedOut)
+}
+
+// Que fallos de un intento se reintentan en otro worker
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RetryScope {
+    Unreached, // Solo si la tarea no llego al worker (fallo la conexion)
+    Rejected,  // Ademas las respuestas de RETRY_ON_STATUS, el worker no ejecuto la tarea
+    Any,       // Tambien los errores a mitad de la respuesta, la tarea se puede repetir
+}
+
+/*
+Reenvia la tarea a un worker. Con Idempotency-Key primero se busca la clave:
+una repeticion recibe la respuesta guardada sin volver a ejecutar la tarea.
+La clave no habilita reintentar un error a mitad de la respuesta: el worker pudo
+haber ejecutado la tarea y repetirla en otro la ejecutaria dos veces.
+*/
+pub async fn handle_task_forwarding(request: &Request, ctx: &AppContext, span: &Span, served_by: &mut Option<String>) -> Response {
+    let key = match IdempotencyStore::key_of(request) {
+        Ok(key) => key,
+        Err(msg) => return http_resonse_400(&msg),
+    };
+    let idempotent = ctx.retry.is_idempotent(request);
+    let mut may_have_run = false;
+    let Some(key) = key else {
+        let scope = if idempotent { RetryScope::Any } else { RetryScope::Unreached };
+        return forward_with_retries(request, ctx, span, served_by, scope, &mut may_have_run).await;
+    };
+
+    match ctx.idempotency.claim(key, request) {
+        Claim::New(reservation) => {
+            let scope = if idempotent { RetryScope::Any } else { RetryScope::Rejected };
+            let response = forward_with_retries(request, ctx, span, served_by, scope, &mut may_have_run).await;
+            reservation.complete(&response, may_have_run);
+            response
         }
-        println!("Worker que se va a evaluar: {}", index);
-        println!("{}", state.next_worker_index);
-        println!("Numero total de worker: {}", num_workers);
-        println!("{:?}", state.workers[index].status);
-        if state.workers[index].status == WorkerStatus::Active {
-            println!("Entra para retornar el index");
-            return Some(index);
+        Claim::Replay(response) => {
+            info!("forward", request_id = request.request_id(), idempotency_key = key; "Respuesta repetida por {}", IDEMPOTENCY_KEY_HEADER);
+            response
         }
-        index += 1;
+        Claim::InProgress => http_response_409(&format!("Ya hay una solicitud en curso con esta {}", IDEMPOTENCY_KEY_HEADER)),
+        Claim::Mismatch => http_response_422(&format!("La {} ya se uso con otra solicitud", IDEMPOTENCY_KEY_HEADER)),
     }
-    None
 }
 
-pub fn handle_task_forwarding(path_and_query: &str, state_dispatcher: Arc<Mutex<DispatcherState>>) -> String{
-    let client = reqwest::Client::new();
-
-    
-    let mut worker_info: Option<(String, String)> = Some(("".to_string(),"".to_string()));
-    let max_retries = {state_dispatcher.lock().unwrap().workers.len()}; //Numero maximo de reintentos
-    
-    if max_retries == 0 {
-        return "HTTP/1.1 503 Service Unavailable\r\n\r\nNo workers configured".to_string()
+/*
+Envia la tarea y la reintenta en otro worker segun RetryPolicy, dentro de `scope`.
+Cada seleccion de worker y cada envio tienen su span, hijo de `span`.
+`served_by` queda con el worker del ultimo intento y `may_have_run` en true si algun
+worker pudo haber ejecutado la tarea (respondio, vencio el tiempo o se corto la conexion).
+*/
+async fn forward_with_retries(
+    request: &Request,
+    ctx: &AppContext,
+    span: &Span,
+    served_by: &mut Option<String>,
+    scope: RetryScope,
+    may_have_run: &mut bool,
+) -> Response {
+    let (state_dispatcher, client, retry) = (&ctx.state, &ctx.client, &ctx.retry);
+    let path_and_query = request.target.as_str();
+    let key = request_key(request, &ctx.config.lb_hash_key);
+    let (deadline, client_deadline) = task_deadline(request, &ctx.config);
+    let mut tried: Vec<String> = Vec::new(); //Workers que ya fallaron con esta tarea
+
+    if state_dispatcher.lock().await.workers.is_empty() {
+        return http_response_503("No hay workers configurados")
     }
-    
-    for attempt in 0..max_retries {
+    retry.record_request();
+
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        //Los reintentos comparten el mismo tiempo limite
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return http_response_504("Se agoto el tiempo limite de la tarea");
+        }
 
-        worker_info = {
-            let mut state = state_dispatcher.lock().unwrap();
+        let mut select_span = span.child("select_worker", SpanKind::Internal);
+        let worker_info = {
+            let state = state_dispatcher.lock().await;
 
-            select_next_worker(&mut state).map(|index| {
+            //El contador se incrementa con el lock tomado, asi la siguiente seleccion ya lo ve
+            select_untried_worker(&state, &key, &tried).map(|index| {
                 let worker = &state.workers[index];
-                (worker.id.clone(), worker.address.clone())
+                (worker.id.clone(), worker.address.clone(), InFlightGuard::start(worker))
             })
         };
 
-        // Si el worker fue seleccionado procedemos
-        if let Some((worker_id, worker_address)) =  worker_info{
-            //Enviamos la tarea
-            let target_url = format!("{}{}", worker_address, path_and_query);
-            println!("Reenviado tarea '{}' al worker '{}' en '{}'", path_and_query, worker_id, target_url);
-    
-            // Reenviar la peticion y esperar respuesta
-            // Usamos un runtime de Tokio
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            let response_result = rt.block_on(client.get(&target_url).send());
-    
-            // Procesamos respuesta o el fallo
-            match response_result {
-                Ok(response) => {
-                    let status = response.status();
-                    let body = rt.block_on(response.text()).unwrap_or_else(|_| "".to_string());
-        
-                    //Incrementos el contador de tareas completadas para el worker
-                    let mut state = state_dispatcher.lock().unwrap();
-                    if let Some(worker) = state.workers.iter_mut().find(|w| w.id == worker_id) {
+        //Si entra aqui es que no hay workers como tal o no hay activos
+        let Some((worker_id, worker_address, in_flight)) = worker_info else {
+            select_span.set_error("No hay workers activos");
+            ctx.tracer.end(select_span);
+            warn!("forward", request_id = request.request_id(), attempt = attempt; "No hay mas workers activos, se aborta la tarea");
+            return http_response_503("No hay workers activos disponibles");
+        };
+
+        select_span.set("worker.id", worker_id.as_str());
+        ctx.tracer.end(select_span);
+        *served_by = Some(worker_id.clone());
+
+        //Enviamos la tarea; el span del envio incluye el tiempo de red y lo que tarda el worker
+        let mut call = span.child("forward", SpanKind::Client);
+        call.set("worker.id", worker_id.as_str());
+        call.set("attempt", attempt);
+        let target_url = format!("{}{}", worker_address, path_and_query);
+        debug!("forward", request_id = request.request_id(), worker = worker_id, url = target_url, attempt = attempt; "Reenviando tarea");
+
+        // Reenviar la peticion y esperar respuesta, el fallo decide si se puede reintentar
+        let breaker = &ctx.config.breaker;
+        let started = Instant::now();
+        let failure = match forward_request(client, request, &target_url, &call.traceparent(), remaining).await {
+            //El worker termino cuando llega el body completo
+            Ok((status, headers, body)) => {
+                call.set("http.response.status_code", status.as_u16());
+                drop(in_flight);
+                if status.is_server_error() {
+                    call.set_error(&format!("HTTP {}", status.as_u16()));
+                }
+                ctx.tracer.end(call);
+
+                let retryable = retry.retries_status(status.as_u16());
+                *may_have_run |= !retryable;
+                //Las metricas se registran con el lock tomado: si el worker ya se quito no se le crean series de nuevo
+                let mut state = state_dispatcher.lock().await;
+                if let Some(worker) = state.workers.iter_mut().find(|w| w.id == worker_id) {
+                    ctx.metrics.record_forward(&worker_id, started.elapsed());
+                    if status.is_server_error() {
+                        ctx.metrics.record_forward_error(&worker_id, "status");
+                    }
+                    if retryable {
+                        worker.tasks_failed += 1;
+                    } else {
                         worker.task_completed += 1;
                     }
-        
-                    println!("Respuesta recibida del worker '{}'. Status: '{}'", worker_id, status);
-                    //Falta la funcion que le da formato a la respuesta
-                    return format_forwarded_response(status, &body)
+                    let outcome = Outcome::from_status(status.as_u16(), started.elapsed());
+                    if counts_for_breaker(outcome, client_deadline) {
+                        worker.record_outcome(outcome, breaker);
+                    }
                 }
-                Err(e) => {
-                    eprintln!("Fallo al reenviar la tarea al worker '{}': '{}'", worker_id, e);
-        
-                    //Falla el worker, entonces lo marcamos como inactivo y registramos fallo
-                    let mut state = state_dispatcher.lock().unwrap();
-                    if let Some(worker) = state.workers.iter_mut().find(|w| w.id == worker_id) {
-                        worker.status = WorkerStatus::Inactive;
-                        worker.tasks_failed += 1;
+
+                debug!("forward", request_id = request.request_id(), worker = worker_id, status = status.as_u16(); "Respuesta recibida del worker");
+                let response = format_forwarded_response(request, status, &headers, body);
+                if !retryable {
+                    return response;
+                }
+                //Si no se reintenta, el cliente recibe lo que respondio el ultimo worker
+                (scope != RetryScope::Unreached, response)
+            }
+            //El worker puede estar sano pero lento: cuenta como tarea lenta para el circuito
+            //(si el limite era el de la ruta) y no se reintenta, el tiempo de la tarea ya se gasto
+            Err(e) if e.is_timeout() => {
+                drop(in_flight);
+                *may_have_run = true;
+                call.set_error("timeout");
+                ctx.tracer.end(call);
+                warn!("forward", request_id = request.request_id(), worker = worker_id; "El worker no respondio en {:?}", remaining);
+                let mut state = state_dispatcher.lock().await;
+                if let Some(worker) = state.workers.iter_mut().find(|w| w.id == worker_id) {
+                    ctx.metrics.record_forward_error(&worker_id, "timeout");
+                    worker.tasks_failed += 1;
+                    if counts_for_breaker(Outcome::TimedOut, client_deadline) {
+                        worker.record_outcome(Outcome::TimedOut, breaker);
                     }
-    
                 }
+                return http_response_504("El worker no respondio antes del tiempo limite");
             }
-    
-        } else {
-            //Si entra aqui es que el select_next_worker devolvio None
-            //Significa que, no hay workers como tal o no hay activos
-            println!("No se encontraron más workers activos. Abortando tarea.");
-            return "HTTP/1.1 503 Service Unavailable\r\n\r\nNo active workers available".to_string();
+            Err(e) => {
+                drop(in_flight);
+                warn!("forward", request_id = request.request_id(), worker = worker_id, attempt = attempt; "Fallo al reenviar la tarea: {}", e);
+
+                //Si no se pudo conectar el circuito se abre de inmediato, los demas errores cuentan en la ventana
+                let outcome = if e.is_connect() { Outcome::Unreachable } else { Outcome::Failure };
+                call.set_error(&e.to_string());
+                ctx.tracer.end(call);
+                let mut state = state_dispatcher.lock().await;
+                if let Some(worker) = state.workers.iter_mut().find(|w| w.id == worker_id) {
+                    ctx.metrics.record_forward_error(&worker_id, if e.is_connect() { "unreachable" } else { "transport" });
+                    worker.tasks_failed += 1;
+                    worker.record_outcome(outcome, breaker);
+                }
+                //Si no se pudo conectar la tarea no llego al worker y se puede repetir
+                *may_have_run |= !e.is_connect();
+                (scope == RetryScope::Any || e.is_connect(), http_response_502("No se pudo completar la tarea, fallaron los workers"))
+            }
+        };
+
+        let (may_retry, response) = failure;
+        if !may_retry || attempt >= retry.max_attempts || !retry.try_retry() {
+            return response;
         }
+        tried.push(worker_id);
+        ctx.metrics.retries.inc(&[]);
+
+        //Backoff antes del siguiente intento, sin pasarse del tiempo limite
+        let wait = retry.backoff(attempt).min(deadline.saturating_duration_since(Instant::now()));
+        let backoff_span = span.child("retry_backoff", SpanKind::Internal);
+        tokio::time::sleep(wait).await;
+        ctx.tracer.end(backoff_span);
+    }
+}
+
+/*
+En un reintento se prefiere un worker que todavia no fallo con esta tarea.
+Los ya probados se pasan al balanceador marcados como drenando (solo en la copia)
+para que no los elija; si no queda otro se repite uno.
+*/
+fn select_untried_worker(state: &DispatcherState, key: &str, tried: &[String]) -> Option<usize> {
+    if tried.is_empty() {
+        return select_next_worker(state, key);
+    }
+    let candidates: Vec<Worker> = state
+        .workers
+        .iter()
+        .map(|w| {
+            let mut candidate = w.clone();
+            candidate.draining |= tried.contains(&w.id);
+            candidate
+        })
+        .collect();
+    state.balancer.select(&candidates, key).or_else(|| select_next_worker(state, key))
+}
+
+/*
+Envia la solicitud del cliente al worker con el mismo metodo y body.
+Si el metodo no es valido para reqwest se usa GET como antes.
+Tambien lee el body de la respuesta: si el worker se cae o se vence el tiempo a mitad
+del body es un error como cualquier otro, no una respuesta vacia con el codigo original.
+`timeout` es lo que le queda a la tarea; tambien se le avisa al worker para que se detenga.
+`traceparent` es el span del envio, el worker cuelga los suyos de ese.
+*/
+async fn forward_request(
+    client: &reqwest::Client,
+    request: &Request,
+    target_url: &str,
+    traceparent: &str,
+    timeout: Duration,
+) -> reqwest::Result<(reqwest::StatusCode, reqwest::header::HeaderMap, Vec<u8>)> {
+    let method = reqwest::Method::from_bytes(request.method.as_bytes()).unwrap_or(reqwest::Method::GET);
+    let mut builder = client
+        .request(method, target_url)
+        .timeout(timeout)
+        .header(DEADLINE_HEADER, timeout.as_millis().to_string())
+        .header(REQUEST_ID_HEADER, request.request_id())
+        .header(TRACEPARENT_HEADER, traceparent);
+
+    if let Some(content_type) = request.header("content-type") {
+        builder = builder.header(reqwest::header::CONTENT_TYPE, content_type);
+    }
+    if !request.body.is_empty() {
+        builder = builder.body(request.body.clone());
     }
 
-        "HTTP/1.1 502 Bad Gateway\r\n\r\nCould not complete the task after all workers failed".to_string()
+    let response = builder.send().await?;
+    let (status, headers) = (response.status(), response.headers().clone());
+    let body = response.bytes().await?;
+    Ok((status, headers, body.to_vec()))
 }
 
-// Formata la respuesta recibida del worker
-fn format_forwarded_response(status: reqwest::StatusCode, body:&str) -> String {
-    format!(
-        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
-        status,
-        body.len(),
-        body
-    )
-}   
+/*
+Arma la respuesta para el cliente con lo que devolvio el worker.
+Se copian el codigo y los headers tal cual, menos los de conexion.
+*/
+fn format_forwarded_response(request: &Request, status: reqwest::StatusCode, headers: &reqwest::header::HeaderMap, body: Vec<u8>) -> Response {
+    let forwarded = headers.iter().filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)));
+    let mut response = Response::new(status.as_u16()).forwarded_headers(forwarded);
+    response = match headers.get(reqwest::header::CONTENT_TYPE).and_then(|v| v.to_str().ok()) {
+        Some(content_type) => response.body(content_type, body),
+        None if body.is_empty() => response,
+        None => response.body("application/octet-stream", body),
+    };
+
+    //A un HEAD el worker responde sin body, se mantiene el largo que anuncio
+    if request.method == "HEAD" {
+        response = response.head_only();
+        if let Some(length) = headers.get(reqwest::header::CONTENT_LENGTH).and_then(|v| v.to_str().ok()) {
+            response = response.header("Content-Length", length);
+        }
+    }
+    response
+}
 
 //Funcion que maneja el calculo de pi
-async fn handle_montecarlo_request(
-    params: &HashMap<String, String>,
-    state_dispatcher: &Arc<Mutex<DispatcherState>>,
-    client: &reqwest::Client
-) -> String {
+async fn handle_montecarlo_request(request: &Request, ctx: &AppContext, span: &Span, served_by: &mut Option<String>) -> Response {
+    let (state_dispatcher, client, config) = (&ctx.state, &ctx.client, &ctx.config);
     //Parseamos el request
+    let params = request.params();
+    let (deadline, client_deadline) = task_deadline(request, config);
 
     let total_points = match params.get("points").and_then(|s| s.parse::<u64>().ok()) {
         Some(p) if p > 0 => p,
@@ -315,66 +707,122 @@ async fn handle_montecarlo_request(
     };
 
     //Obtenemos los workers activos
+    let mut select_span = span.child("select_worker", SpanKind::Internal);
     let active_workers = {
-        state_dispatcher.lock().unwrap().workers.iter()
-        .filter(|w| w.status == WorkerStatus::Active)
-        .map(|w| (w.id.clone(), w.address.clone()))
+        state_dispatcher.lock().await.workers.iter()
+        .filter(|w| w.is_available())
+        .map(|w| (w.id.clone(), w.address.clone(), InFlightGuard::start(w)))
         .collect::<Vec<_>>()
     };
+    select_span.set("workers", active_workers.len());
+    ctx.tracer.end(select_span);
 
     if active_workers.is_empty() {
         return http_response_500_json("No hay workers disponibles");
     }
+    //Todos los workers reciben una parte, el access log los lista separados por coma
+    *served_by = Some(active_workers.iter().map(|(id, _, _)| id.as_str()).collect::<Vec<_>>().join(","));
 
     //Dividmos el trabajo
     let points_per_worker = total_points / active_workers.len() as u64;
     let mut futures = vec![];
 
-    println!("[Dispatcher] Dividiendo {} puntos entre {} workers ({} c/u)", total_points, active_workers.len(), points_per_worker);
+    info!(
+        "montecarlo",
+        request_id = request.request_id(),
+        points = total_points,
+        workers = active_workers.len();
+        "Dividiendo {} puntos por worker",
+        points_per_worker
+    );
 
     //Generamos las tareas para la peticion concurrente
-    for (workerId, address) in active_workers {
-        let url = format!("{}/internal/montecarlo?points={}", address, points_per_worker);
+    //Todas las subtareas vencen juntas, cada worker se detiene solo al llegar el limite
+    let started = Instant::now();
+    let timeout = deadline.saturating_duration_since(started);
+    for (worker_id, address, in_flight) in active_workers {
+        let url = format!("{}{}", address, MontecarloTask { points: points_per_worker }.to_target());
         let client_clone = client.clone();
+        let request_id = request.request_id().to_string();
+        let tracer = ctx.tracer.clone();
+        let mut call = span.child("forward", SpanKind::Client);
+        call.set("worker.id", worker_id.as_str());
+        call.set("montecarlo.points", points_per_worker);
 
         futures.push(tokio::spawn(async move {
-            (workerId, client_clone.get(&url).send().await)
+            //La subtarea cuenta como en curso hasta leer la respuesta completa
+            let _in_flight = in_flight;
+            let request = client_clone
+                .get(&url)
+                .timeout(timeout)
+                .header(DEADLINE_HEADER, timeout.as_millis().to_string())
+                .header(REQUEST_ID_HEADER, request_id)
+                .header(TRACEPARENT_HEADER, call.traceparent());
+            let result = match request.send().await.and_then(|response| response.error_for_status()) {
+                Ok(response) => response.json::<MontecarloResult>().await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = &result {
+                call.set_error(&e.to_string());
+            }
+            tracer.end(call);
+            (worker_id, result)
         }));
     }
         //Ejecutamos las peticiones en paralelo y esperamos los resultados
         let results = join_all(futures).await;
         let mut total_hits = 0;
         let mut succesful_workers = 0;
+        let mut timed_out = false;
 
         for result in results {
-            println!("Entra en la parte de resultados");
-
-            if let Ok((workerId, Ok(response))) = result {
-                if let Ok(worker_response) = response.json::<WorkerResponse>().await {
+            let Ok((worker_id, result)) = result else {
+                continue;
+            };
+            //Montecarlo tarda lo que piden los puntos, una parte que termina no cuenta como lenta
+            let outcome = match result {
+                Ok(worker_response) => {
                     total_hits += worker_response.hits;
                     succesful_workers += 1;
-
-                    let mut state = state_dispatcher.lock().unwrap();
-                    if let Some(w) = state.workers.iter_mut().find(|w| w.id == workerId) {
-                        w.task_completed += 1;
-                    } 
+                    Outcome::Success(Duration::ZERO)
+                }
+                //El worker responde 504 cuando se detiene por el limite
+                Err(e) if e.is_timeout() || e.status() == Some(reqwest::StatusCode::GATEWAY_TIMEOUT) => {
+                    warn!("montecarlo", request_id = request.request_id(), worker = worker_id; "La parte de Montecarlo no termino a tiempo");
+                    timed_out = true;
+                    Outcome::TimedOut
+                }
+                Err(e) if e.is_connect() => Outcome::Unreachable,
+                Err(_) => Outcome::Failure,
+            };
+
+            let mut state = state_dispatcher.lock().await;
+            if let Some(w) = state.workers.iter_mut().find(|w| w.id == worker_id) {
+                if matches!(outcome, Outcome::Success(_)) {
+                    w.task_completed += 1;
+                }
+                if counts_for_breaker(outcome, client_deadline) {
+                    w.record_outcome(outcome, &config.breaker);
                 }
             }
         }
+        if succesful_workers == 0 && timed_out {
+            return http_response_504("Ningun worker termino su parte de Montecarlo antes del tiempo limite");
+        }
         if succesful_workers == 0 {
             return http_response_500_json("Ningun worker pudo completar la tarea de Montecarlo");
         }
 
         let pi_estimate = 4.0 * (total_hits as f64) / (points_per_worker * succesful_workers) as f64;
 
-        let response_body = format!(
-            "{{\"pi_estimate\":{}, \"total_points_simulated\":{}, \"total_hits\":{}}}",
+        let estimate = MontecarloEstimate {
             pi_estimate,
-            points_per_worker * succesful_workers,
-            total_hits
-        );
+            total_points_simulated: points_per_worker * succesful_workers,
+            total_hits,
+        };
+        ctx.metrics.record_montecarlo(estimate.total_points_simulated, started.elapsed());
 
-        println!("Response body: {}", response_body);
+        info!("montecarlo", request_id = request.request_id(), pi = estimate.pi_estimate; "Estimacion de pi lista");
 
-        return http_response_200(&response_body);
+        http_response_200(&estimate)
 }
\ No newline at end of file
@@ -0,0 +1,393 @@
+/*
+Circuit breaker de cada worker, decide si puede recibir tareas.
+- closed: recibe tareas. Se abre si en los ultimos BREAKER_WINDOW_SECS hubo al menos
+  BREAKER_MIN_REQUESTS tareas y la proporcion de errores (5xx) llega a BREAKER_ERROR_RATE,
+  o la de tareas lentas (mas de BREAKER_SLOW_CALL_MS, o que vencieron) llega a BREAKER_SLOW_RATE.
+  Las que vencen por un X-Deadline-Ms del cliente mas corto que el de la ruta no se cuentan.
+  Si no se puede conectar con el worker se abre de inmediato.
+- open: no recibe tareas. Si se abrio por el trafico, despues de BREAKER_OPEN_SECS pasa a half_open;
+  si se abrio por el healthcheck (o el worker es nuevo) vuelve a closed cuando el healthcheck pasa.
+- half_open: recibe hasta BREAKER_HALF_OPEN_REQUESTS tareas de prueba a la vez. Si esas tantas
+  salen bien se cierra, si una falla se vuelve a abrir.
+*/
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+// La ventana se divide en estos intervalos; al avanzar se descarta el mas viejo entero
+const WINDOW_BUCKETS: u32 = 10;
+const MIN_BUCKET: Duration = Duration::from_millis(10);
+
+// Umbrales del circuit breaker, ver Config
+#[derive(Debug, Clone, Copy)]
+pub struct BreakerSettings {
+    pub window: Duration,        // Ventana en la que se miden errores y lentitud
+    pub min_requests: u32,       // Tareas minimas en la ventana para poder abrir el circuito
+    pub error_rate: f64,         // Proporcion de errores que abre el circuito, mas de 1 lo desactiva
+    pub slow_call: Duration,     // Desde cuanto una tarea cuenta como lenta
+    pub slow_rate: f64,          // Proporcion de tareas lentas que abre el circuito, mas de 1 lo desactiva
+    pub open_for: Duration,      // Tiempo abierto antes de probar de nuevo
+    pub half_open_requests: u32, // Tareas de prueba en half_open
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+// Por que se abrio el circuito, se muestra en /workers
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TripReason {
+    Unchecked,   // Worker nuevo, todavia no paso el healthcheck
+    Healthcheck, // Fallaron UNHEALTHY_THRESHOLD pings seguidos
+    Unreachable, // No se pudo conectar al reenviar una tarea
+    ErrorRate,
+    SlowCalls,
+}
+
+// Como termino una tarea enviada al worker
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Success(Duration), // Con lo que tardo
+    Failure,
+    TimedOut,
+    Unreachable,
+}
+
+impl Outcome {
+    // Un 504 del worker es una tarea que se detuvo por el tiempo limite, cuenta como lenta
+    pub fn from_status(status: u16, latency: Duration) -> Outcome {
+        match status {
+            504 => Outcome::TimedOut,
+            500..=599 => Outcome::Failure,
+            _ => Outcome::Success(latency),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    start: Instant,
+    requests: u32,
+    failures: u32,
+    slow: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    state: CircuitState,
+    reason: Option<TripReason>,
+    half_open_at: Option<Instant>, // Cuando pasa de open a half_open; None espera al healthcheck
+    trial_limit: u32,              // Tareas de prueba a la vez en half_open
+    trial_successes: u32,
+    window: VecDeque<Bucket>,
+}
+
+// Estado del circuito en /workers
+#[derive(Debug, Serialize)]
+pub struct CircuitReport {
+    pub state: CircuitState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<TripReason>,
+    pub requests: u32, // Tareas en la ventana
+    pub error_rate: f64,
+    pub slow_rate: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub half_open_in_secs: Option<u64>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> CircuitBreaker {
+        CircuitBreaker::new()
+    }
+}
+
+impl CircuitBreaker {
+    // Los workers nuevos empiezan abiertos y se cierran cuando pasan el healthcheck
+    pub fn new() -> CircuitBreaker {
+        CircuitBreaker {
+            state: CircuitState::Open,
+            reason: Some(TripReason::Unchecked),
+            half_open_at: None,
+            trial_limit: 1,
+            trial_successes: 0,
+            window: VecDeque::new(),
+        }
+    }
+
+    // Un circuito abierto cuyo plazo ya vencio esta en half_open aunque nadie lo haya cambiado
+    pub fn state(&self) -> CircuitState {
+        match self.state {
+            CircuitState::Open if self.half_open_at.is_some_and(|at| Instant::now() >= at) => CircuitState::HalfOpen,
+            state => state,
+        }
+    }
+
+    pub fn reason(&self) -> Option<TripReason> {
+        self.reason
+    }
+
+    // Si puede recibir una tarea mas teniendo `in_flight` en curso
+    pub fn allows(&self, in_flight: u64) -> bool {
+        match self.state() {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => in_flight < self.trial_limit as u64,
+        }
+    }
+
+    // Registra el resultado de una tarea; devuelve el estado nuevo si cambio
+    pub fn record(&mut self, outcome: Outcome, settings: &BreakerSettings) -> Option<CircuitState> {
+        let before = self.state();
+        match before {
+            // Respuestas de tareas que se enviaron antes de abrirse
+            CircuitState::Open => return None,
+            CircuitState::HalfOpen => {
+                self.state = CircuitState::HalfOpen;
+                match classify(outcome, settings) {
+                    None => {
+                        self.trial_successes += 1;
+                        if self.trial_successes >= self.trial_limit {
+                            self.close();
+                        }
+                    }
+                    Some(reason) => self.trip(reason, settings),
+                }
+            }
+            CircuitState::Closed => {
+                if outcome == Outcome::Unreachable {
+                    self.trip(TripReason::Unreachable, settings);
+                } else {
+                    let reason = classify(outcome, settings);
+                    self.add(reason == Some(TripReason::ErrorRate), reason == Some(TripReason::SlowCalls), settings);
+                    if let Some(reason) = self.exceeded(settings) {
+                        self.trip(reason, settings);
+                    }
+                }
+            }
+        }
+        let after = self.state();
+        (after != before).then_some(after)
+    }
+
+    // El healthcheck vio UNHEALTHY_THRESHOLD fallos seguidos: queda abierto hasta que vuelva a pasar
+    pub fn health_failed(&mut self, settings: &BreakerSettings) -> Option<CircuitState> {
+        if self.reason == Some(TripReason::Healthcheck) {
+            return None;
+        }
+        let before = self.state();
+        self.trip(TripReason::Healthcheck, settings);
+        (before != CircuitState::Open).then_some(CircuitState::Open)
+    }
+
+    /*
+    El healthcheck vio HEALTHY_THRESHOLD pings buenos seguidos.
+    Cierra el circuito si se habia abierto porque el worker no respondia; si se abrio
+    por errores o lentitud el worker puede estar vivo y fallar igual, eso lo decide half_open.
+    */
+    pub fn health_passed(&mut self) -> Option<CircuitState> {
+        let down = matches!(self.reason, Some(TripReason::Unchecked | TripReason::Healthcheck | TripReason::Unreachable));
+        if self.state() == CircuitState::Closed || !down {
+            return None;
+        }
+        self.close();
+        Some(CircuitState::Closed)
+    }
+
+    pub fn report(&self, settings: &BreakerSettings) -> CircuitReport {
+        let now = Instant::now();
+        let (requests, failures, slow) = self.totals(settings.window, now);
+        let rate = |count: u32| if requests == 0 { 0.0 } else { count as f64 / requests as f64 };
+        let state = self.state();
+        CircuitReport {
+            state,
+            reason: self.reason,
+            requests,
+            error_rate: rate(failures),
+            slow_rate: rate(slow),
+            half_open_in_secs: self
+                .half_open_at
+                .filter(|_| state == CircuitState::Open)
+                .map(|at| at.saturating_duration_since(now).as_secs()),
+        }
+    }
+
+    fn trip(&mut self, reason: TripReason, settings: &BreakerSettings) {
+        let by_traffic = !matches!(reason, TripReason::Unchecked | TripReason::Healthcheck);
+        self.state = CircuitState::Open;
+        self.reason = Some(reason);
+        self.half_open_at = by_traffic.then(|| Instant::now() + settings.open_for);
+        self.trial_limit = settings.half_open_requests.max(1);
+        self.trial_successes = 0;
+        self.window.clear();
+    }
+
+    fn close(&mut self) {
+        self.state = CircuitState::Closed;
+        self.reason = None;
+        self.half_open_at = None;
+        self.trial_successes = 0;
+        self.window.clear();
+    }
+
+    fn add(&mut self, failure: bool, slow: bool, settings: &BreakerSettings) {
+        let now = Instant::now();
+        self.window.retain(|bucket| now.duration_since(bucket.start) < settings.window);
+
+        let bucket_len = (settings.window / WINDOW_BUCKETS).max(MIN_BUCKET);
+        if self.window.back().is_none_or(|bucket| now.duration_since(bucket.start) >= bucket_len) {
+            self.window.push_back(Bucket { start: now, requests: 0, failures: 0, slow: 0 });
+        }
+        if let Some(bucket) = self.window.back_mut() {
+            bucket.requests += 1;
+            bucket.failures += failure as u32;
+            bucket.slow += slow as u32;
+        }
+    }
+
+    fn totals(&self, window: Duration, now: Instant) -> (u32, u32, u32) {
+        self.window
+            .iter()
+            .filter(|bucket| now.duration_since(bucket.start) < window)
+            .fold((0, 0, 0), |(requests, failures, slow), bucket| {
+                (requests + bucket.requests, failures + bucket.failures, slow + bucket.slow)
+            })
+    }
+
+    fn exceeded(&self, settings: &BreakerSettings) -> Option<TripReason> {
+        let (requests, failures, slow) = self.totals(settings.window, Instant::now());
+        if requests == 0 || requests < settings.min_requests {
+            return None;
+        }
+        if failures as f64 / requests as f64 >= settings.error_rate {
+            Some(TripReason::ErrorRate)
+        } else if slow as f64 / requests as f64 >= settings.slow_rate {
+            Some(TripReason::SlowCalls)
+        } else {
+            None
+        }
+    }
+}
+
+// Que umbral cuenta el resultado: None es una tarea buena
+fn classify(outcome: Outcome, settings: &BreakerSettings) -> Option<TripReason> {
+    match outcome {
+        Outcome::Success(latency) if latency < settings.slow_call => None,
+        Outcome::Success(_) | Outcome::TimedOut => Some(TripReason::SlowCalls),
+        Outcome::Failure => Some(TripReason::ErrorRate),
+        Outcome::Unreachable => Some(TripReason::Unreachable),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: BreakerSettings = BreakerSettings {
+        window: Duration::from_secs(60),
+        min_requests: 4,
+        error_rate: 0.5,
+        slow_call: Duration::from_millis(100),
+        slow_rate: 0.75,
+        open_for: Duration::from_secs(60),
+        half_open_requests: 3,
+    };
+
+    // Los circuitos empiezan abiertos hasta el primer healthcheck
+    fn closed() -> CircuitBreaker {
+        let mut breaker = CircuitBreaker::new();
+        assert_eq!(breaker.health_passed(), Some(CircuitState::Closed));
+        breaker
+    }
+
+    #[test]
+    fn error_rate_needs_min_requests() {
+        let mut breaker = closed();
+        for _ in 0..3 {
+            assert_eq!(breaker.record(Outcome::Failure, &SETTINGS), None);
+        }
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_eq!(breaker.record(Outcome::Failure, &SETTINGS), Some(CircuitState::Open));
+        assert_eq!(breaker.reason(), Some(TripReason::ErrorRate));
+
+        // Con la mitad de errores tambien se abre, pero no antes de llegar al minimo
+        let mut breaker = closed();
+        breaker.record(Outcome::Success(Duration::ZERO), &SETTINGS);
+        breaker.record(Outcome::Failure, &SETTINGS);
+        breaker.record(Outcome::Success(Duration::ZERO), &SETTINGS);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_eq!(breaker.record(Outcome::Failure, &SETTINGS), Some(CircuitState::Open));
+    }
+
+    #[test]
+    fn slow_and_timed_out_calls_count_toward_slow_rate() {
+        let mut breaker = closed();
+        breaker.record(Outcome::Success(Duration::from_millis(10)), &SETTINGS);
+        breaker.record(Outcome::Success(Duration::from_millis(150)), &SETTINGS);
+        breaker.record(Outcome::TimedOut, &SETTINGS);
+        breaker.record(Outcome::Success(Duration::from_millis(100)), &SETTINGS);
+        // 3 de 4 lentas llega a 0.75
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(breaker.reason(), Some(TripReason::SlowCalls));
+
+        let mut breaker = closed();
+        for _ in 0..3 {
+            breaker.record(Outcome::Success(Duration::from_millis(10)), &SETTINGS);
+        }
+        breaker.record(Outcome::TimedOut, &SETTINGS);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_eq!(breaker.report(&SETTINGS).slow_rate, 0.25);
+    }
+
+    #[test]
+    fn half_open_limits_trials_and_closes_after_enough_successes() {
+        let settings = BreakerSettings { open_for: Duration::ZERO, ..SETTINGS };
+        let mut breaker = closed();
+        breaker.record(Outcome::Unreachable, &settings);
+        assert_eq!(breaker.reason(), Some(TripReason::Unreachable));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        assert!(breaker.allows(2));
+        assert!(!breaker.allows(3));
+        assert_eq!(breaker.record(Outcome::Success(Duration::ZERO), &settings), None);
+        assert_eq!(breaker.record(Outcome::Success(Duration::ZERO), &settings), None);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert_eq!(breaker.record(Outcome::Success(Duration::ZERO), &settings), Some(CircuitState::Closed));
+        assert!(breaker.allows(100));
+
+        // Una prueba que falla lo vuelve a abrir
+        let mut breaker = closed();
+        breaker.record(Outcome::Unreachable, &settings);
+        breaker.record(Outcome::Success(Duration::ZERO), &settings);
+        breaker.record(Outcome::Failure, &settings);
+        assert_eq!(breaker.reason(), Some(TripReason::ErrorRate));
+        assert_ne!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn health_passed_only_closes_circuits_opened_by_health() {
+        let mut breaker = closed();
+        for _ in 0..4 {
+            breaker.record(Outcome::Failure, &SETTINGS);
+        }
+        assert_eq!(breaker.reason(), Some(TripReason::ErrorRate));
+        assert_eq!(breaker.health_passed(), None);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(breaker.report(&SETTINGS).half_open_in_secs.is_some());
+
+        let mut breaker = closed();
+        assert_eq!(breaker.health_failed(&SETTINGS), Some(CircuitState::Open));
+        assert_eq!(breaker.health_failed(&SETTINGS), None);
+        assert!(breaker.report(&SETTINGS).half_open_in_secs.is_none());
+        assert_eq!(breaker.health_passed(), Some(CircuitState::Closed));
+
+        let mut breaker = closed();
+        breaker.record(Outcome::Unreachable, &SETTINGS);
+        assert_eq!(breaker.health_passed(), Some(CircuitState::Closed));
+    }
+}
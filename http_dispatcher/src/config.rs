@@ -0,0 +1,201 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use http_common::access_log::{AccessLogFormat, AccessLogSettings};
+use http_common::config::{self, Settings};
+use http_common::log::{Level, LogFormat};
+use http_common::request::Limits;
+use http_common::trace::{TraceExporter, TraceSettings};
+
+use crate::circuit_breaker::BreakerSettings;
+
+// Configuracion del dispatcher, ver http_common::config::Settings para las fuentes
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub listen_addr: String,                // Direccion y puerto donde escucha a los clientes
+    pub worker_addresses: String,           // URLs de los workers separadas por coma
+    pub worker_weights: String,             // Pesos opcionales en el mismo orden, por defecto 1
+    pub workers_file: String,               // Archivo con la lista de workers que se recarga en caliente, vacio lo desactiva
+    pub workers_file_poll: Duration,        // Cada cuanto se revisa si cambio, 0 solo recarga con SIGHUP
+    pub upstream_idle_timeout: Duration,    // Tiempo que una conexion a un worker queda abierta sin uso
+    pub upstream_max_idle_per_host: usize,  // Conexiones inactivas que se guardan por worker
+    pub limits: Limits,                     // Tamaño maximo de las solicitudes de los clientes
+    pub client_read_timeout: Duration,      // Tiempo maximo para recibir una solicitud completa
+    pub lb_strategy: String,                // Estrategia de balanceo, ver load_balancer::STRATEGIES
+    pub lb_hash_key: String,                // De donde sale la clave para consistent_hash
+    pub health_interval: Duration,          // Cada cuanto se revisa un worker activo
+    pub health_inactive_interval: Duration, // Cada cuanto se revisa un worker con el circuito abierto
+    pub health_timeout: Duration,           // Tiempo maximo de espera de un ping
+    pub healthy_threshold: u32,             // Pings buenos seguidos para volver a Active
+    pub unhealthy_threshold: u32,           // Pings fallidos seguidos para pasar a Inactive
+    pub health_max_concurrent: usize,       // Pings que se hacen al mismo tiempo
+    pub admin_token: String,                // Token para /admin, vacio solo la atiende desde localhost
+    pub registration_ttl: Duration,         // Tiempo que dura un auto-registro sin heartbeat
+    pub shutdown_timeout: Duration,         // Maximo que se espera a las solicitudes en curso al apagarse
+    pub request_timeout: Duration,          // Tiempo maximo de una tarea, incluidos los reintentos
+    pub route_timeouts: RouteTimeouts,      // Tiempos propios de algunas rutas
+    pub retry_max_attempts: u32,            // Intentos por tarea contando el primero, 1 desactiva los reintentos
+    pub retry_backoff_base: Duration,       // Espera antes del primer reintento, se duplica en cada uno
+    pub retry_backoff_max: Duration,        // Tope de la espera entre reintentos
+    pub retry_on_status: StatusCodes,       // Respuestas de un worker que se reintentan en otro
+    pub retry_budget_ratio: f64,            // Reintentos permitidos por cada solicitud recibida
+    pub retry_budget_min_per_sec: f64,      // Reintentos por segundo que se permiten aunque haya poco trafico
+    pub non_idempotent_routes: String,      // Rutas que solo se reintentan con Idempotency-Key, separadas por coma
+    pub idempotency_ttl: Duration,          // Tiempo que se guarda la respuesta de una Idempotency-Key
+    pub breaker: BreakerSettings,           // Umbrales del circuit breaker de cada worker
+    pub log_level: Level,                   // Nivel minimo de los logs
+    pub log_format: LogFormat,              // text o json
+    pub trace: TraceSettings,               // A donde se exportan las trazas
+    pub access_log: AccessLogSettings,      // Una linea por solicitud en formato common, combined, extended o json
+}
+
+impl Config {
+    // Flags, variables de entorno y archivo de configuracion; termina el proceso si hay un error
+    pub fn load() -> Config {
+        config::load("http_dispatcher", "dispatcher", Config::from_settings)
+    }
+
+    pub fn from_env() -> Config {
+        Config::from_settings(&Settings::from_env())
+    }
+
+    pub fn from_settings(settings: &Settings) -> Config {
+        Config {
+            listen_addr: settings.get("listen_addr", "0.0.0.0:8080".to_string()),
+            worker_addresses: settings.get("worker_addresses", String::new()),
+            worker_weights: settings.get("worker_weights", String::new()),
+            workers_file: settings.get("workers_file", String::new()),
+            workers_file_poll: Duration::from_secs(settings.get("workers_file_poll_secs", 2)),
+            // Menor que el KEEPALIVE_IDLE_SECS del worker para no reusar una conexion que el worker ya cerro
+            upstream_idle_timeout: Duration::from_secs(settings.get("upstream_idle_timeout_secs", 4)),
+            upstream_max_idle_per_host: settings.get("upstream_max_idle_per_host", 8),
+            limits: Limits {
+                max_header_bytes: settings.get("max_header_bytes", 8 * 1024),
+                max_body_bytes: settings.get("max_body_bytes", 1024 * 1024),
+            },
+            client_read_timeout: Duration::from_secs(settings.get("client_read_timeout_secs", 30)),
+            lb_strategy: settings.get("lb_strategy", "round_robin".to_string()),
+            lb_hash_key: settings.get("lb_hash_key", "target".to_string()),
+            health_interval: Duration::from_secs(settings.get("health_interval_secs", 10)),
+            health_inactive_interval: Duration::from_secs(settings.get("health_inactive_interval_secs", 2)),
+            health_timeout: Duration::from_millis(settings.get("health_timeout_ms", 2000)),
+            healthy_threshold: settings.get("healthy_threshold", 2),
+            unhealthy_threshold: settings.get("unhealthy_threshold", 3),
+            health_max_concurrent: settings.get("health_max_concurrent", 16),
+            admin_token: settings.get("admin_token", String::new()),
+            registration_ttl: Duration::from_secs(settings.get("registration_ttl_secs", 30)),
+            shutdown_timeout: Duration::from_secs(settings.get("shutdown_timeout_secs", 8)),
+            request_timeout: Duration::from_millis(settings.get("request_timeout_ms", 30_000)),
+            route_timeouts: settings.get("route_timeouts", RouteTimeouts::default()),
+            retry_max_attempts: settings.get("retry_max_attempts", 3),
+            retry_backoff_base: Duration::from_millis(settings.get("retry_backoff_base_ms", 50)),
+            retry_backoff_max: Duration::from_millis(settings.get("retry_backoff_max_ms", 1000)),
+            retry_on_status: settings.get("retry_on_status", StatusCodes(vec![502, 503])),
+            retry_budget_ratio: settings.get("retry_budget_ratio", 0.2),
+            retry_budget_min_per_sec: settings.get("retry_budget_min_per_sec", 10.0),
+            // Las versiones GET de /createfile y /deletefile tambien cambian archivos
+            non_idempotent_routes: settings.get("non_idempotent_routes", "/createfile,/deletefile".to_string()),
+            idempotency_ttl: Duration::from_secs(settings.get("idempotency_ttl_secs", 600)),
+            breaker: BreakerSettings {
+                window: Duration::from_secs(settings.get("breaker_window_secs", 10)),
+                min_requests: settings.get("breaker_min_requests", 5),
+                error_rate: settings.get("breaker_error_rate", 0.5),
+                slow_call: Duration::from_millis(settings.get("breaker_slow_call_ms", 10_000)),
+                slow_rate: settings.get("breaker_slow_rate", 0.8),
+                open_for: Duration::from_secs(settings.get("breaker_open_secs", 5)),
+                half_open_requests: settings.get("breaker_half_open_requests", 3),
+            },
+            log_level: settings.get("log_level", Level::Info),
+            log_format: settings.get("log_format", LogFormat::Text),
+            trace: TraceSettings {
+                exporter: settings.get("trace_exporter", TraceExporter::None),
+                otlp_endpoint: settings.get("trace_otlp_endpoint", "http://localhost:4318/v1/traces".to_string()),
+                file: settings.get("trace_file", "trazas-dispatcher.jsonl".to_string()),
+                flush_interval: Duration::from_millis(settings.get("trace_flush_ms", 1000)),
+            },
+            access_log: AccessLogSettings {
+                target: settings.get("access_log", String::new()),
+                format: settings.get("access_log_format", AccessLogFormat::Common),
+                max_bytes: settings.get("access_log_max_bytes", 10 * 1024 * 1024),
+                max_files: settings.get("access_log_max_files", 5),
+            },
+        }
+    }
+
+    // Tiempo limite de una tarea segun su ruta
+    pub fn timeout_for(&self, path: &str) -> Duration {
+        self.route_timeouts.get(path).unwrap_or(self.request_timeout)
+    }
+}
+
+/*
+Tiempos limite por ruta en milisegundos, en ROUTE_TIMEOUTS o --route-timeouts:
+    /fibonacci=5000,/montecarlo=60000
+Las rutas que no aparecen usan REQUEST_TIMEOUT_MS.
+*/
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RouteTimeouts(Vec<(String, Duration)>);
+
+impl RouteTimeouts {
+    pub fn get(&self, path: &str) -> Option<Duration> {
+        self.0.iter().find(|(route, _)| route == path).map(|(_, timeout)| *timeout)
+    }
+}
+
+impl FromStr for RouteTimeouts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<RouteTimeouts, String> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (route, ms) = entry.split_once('=').ok_or_else(|| format!("se esperaba ruta=ms en '{}'", entry))?;
+                let ms = ms.trim().parse::<u64>().map_err(|_| format!("milisegundos invalidos en '{}'", entry))?;
+                Ok((route.trim().to_string(), Duration::from_millis(ms)))
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .map(RouteTimeouts)
+    }
+}
+
+impl fmt::Display for RouteTimeouts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries: Vec<String> = self.0.iter().map(|(route, timeout)| format!("{}={}", route, timeout.as_millis())).collect();
+        write!(f, "{}", entries.join(","))
+    }
+}
+
+// Lista de codigos HTTP separados por coma, por ejemplo RETRY_ON_STATUS=502,503,504
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatusCodes(Vec<u16>);
+
+impl StatusCodes {
+    pub fn contains(&self, status: u16) -> bool {
+        self.0.contains(&status)
+    }
+}
+
+impl FromStr for StatusCodes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StatusCodes, String> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|code| !code.is_empty())
+            .map(|code| match code.parse::<u16>() {
+                Ok(status) if (100..=599).contains(&status) => Ok(status),
+                _ => Err(format!("codigo HTTP invalido '{}'", code)),
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .map(StatusCodes)
+    }
+}
+
+impl fmt::Display for StatusCodes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let codes: Vec<String> = self.0.iter().map(u16::to_string).collect();
+        write!(f, "{}", codes.join(","))
+    }
+}
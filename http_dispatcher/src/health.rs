@@ -0,0 +1,182 @@
+// Verificacion periodica del estado de los workers
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::stream::{self, StreamExt};
+use http_common::protocol::{is_compatible, Readiness, PROTOCOL_VERSION};
+use http_common::warn;
+use tokio::sync::Mutex;
+
+use crate::auxiliares::DispatcherState;
+use crate::circuit_breaker::CircuitState;
+use crate::config::Config;
+use crate::metrics::Metrics;
+
+// Pausa minima entre rondas, evita un ciclo ocupado si varios workers vencen casi juntos
+const MIN_TICK: Duration = Duration::from_millis(50);
+
+//Lo que el healthcheck recuerda de cada worker entre rondas
+#[derive(Debug)]
+struct ProbeState {
+    last_probe: Option<Instant>, // Cuando se le hizo ping por ultima vez
+    successes: u32,              // Pings exitosos seguidos
+    failures: u32,               // Pings fallidos seguidos
+    last_state: CircuitState,    // Estado del circuito en la ronda anterior, para notar cambios hechos por el reenvio
+}
+
+/*
+Revisa los workers en segundo plano.
+- Los workers con el circuito cerrado se revisan cada HEALTH_INTERVAL_SECS y los que lo tienen abierto
+  cada HEALTH_INACTIVE_INTERVAL_SECS, asi un worker que se recupera vuelve rapido a recibir tareas.
+- UNHEALTHY_THRESHOLD pings malos seguidos abren el circuito del worker y HEALTHY_THRESHOLD buenos
+  lo cierran si se habia abierto porque el worker no respondia (ver circuit_breaker).
+- Un worker que avisa que se esta apagando sale de la rotacion con el primer ping, sin esperar
+  el umbral: sigue atendiendo solo durante su DRAIN_DELAY_SECS.
+- Los pings de una ronda se hacen en paralelo, hasta HEALTH_MAX_CONCURRENT a la vez.
+*/
+pub async fn health_check(state_dispatcher: Arc<Mutex<DispatcherState>>, client: reqwest::Client, config: Arc<Config>, metrics: Arc<Metrics>) {
+    let mut probes: HashMap<String, ProbeState> = HashMap::new();
+
+    loop {
+        let now = Instant::now();
+
+        let workers = {
+            let state = state_dispatcher.lock().await;
+            state.workers.iter().map(|w| (w.id.clone(), w.address.clone(), w.circuit.state())).collect::<Vec<_>>()
+        };
+
+        //Se olvidan los workers que ya no estan en la lista
+        probes.retain(|id, _| workers.iter().any(|(w_id, _, _)| w_id == id));
+
+        let mut due = Vec::new();
+        for (id, address, circuit) in &workers {
+            let probe = probes.entry(id.clone()).or_insert_with(|| ProbeState {
+                last_probe: None,
+                successes: 0,
+                failures: 0,
+                last_state: *circuit,
+            });
+
+            //Si el reenvio abrio el circuito, los pings buenos de antes ya no cuentan
+            if probe.last_state != *circuit {
+                probe.successes = 0;
+                probe.failures = 0;
+                probe.last_state = *circuit;
+            }
+
+            let interval = probe_interval(*circuit, &config);
+            if probe.last_probe.is_none_or(|last| now.duration_since(last) >= interval) {
+                probe.last_probe = Some(now);
+                due.push((id.clone(), address.clone()));
+            }
+        }
+
+        let results: Vec<(String, Result<(), PingError>, Duration)> = stream::iter(due)
+            .map(|(id, address)| {
+                let client = client.clone();
+                let timeout = config.health_timeout;
+                async move {
+                    let started = Instant::now();
+                    let result = ping(&client, &address, timeout).await;
+                    (id, result, started.elapsed())
+                }
+            })
+            .buffer_unordered(config.health_max_concurrent.max(1))
+            .collect()
+            .await;
+
+        if !results.is_empty() {
+            let mut state = state_dispatcher.lock().await;
+            for (id, result, elapsed) in results {
+                //Si el worker se quito durante el ping no se le crean metricas de nuevo
+                let (Some(probe), Some(worker)) = (probes.get_mut(&id), state.workers.iter_mut().find(|w| w.id == id)) else {
+                    continue;
+                };
+                metrics.record_health_check(&id, result.is_ok(), elapsed);
+
+                let changed = match result {
+                    Ok(()) => {
+                        probe.successes += 1;
+                        probe.failures = 0;
+                        if probe.successes >= config.healthy_threshold { worker.circuit.health_passed() } else { None }
+                    }
+                    Err(e) => {
+                        probe.failures += 1;
+                        probe.successes = 0;
+                        // Un worker caido se revisa seguido, solo se reporta el primer fallo para no llenar el log
+                        if worker.circuit.state() != CircuitState::Open || probe.failures == 1 {
+                            warn!("healthcheck", worker = worker.id, address = worker.address; "Fallo al contactar al worker: {}", e);
+                        }
+                        let draining = matches!(e, PingError::Draining);
+                        if draining || probe.failures >= config.unhealthy_threshold { worker.circuit.health_failed(&config.breaker) } else { None }
+                    }
+                };
+                if let Some(circuit) = changed {
+                    worker.log_circuit(circuit);
+                }
+                probe.last_state = worker.circuit.state();
+            }
+        }
+
+        // Dormimos hasta que le toque al proximo worker
+        let now = Instant::now();
+        let next = workers
+            .iter()
+            .filter_map(|(id, _, circuit)| {
+                let last = probes.get(id)?.last_probe?;
+                Some((last + probe_interval(*circuit, &config)).saturating_duration_since(now))
+            })
+            .min()
+            .unwrap_or(config.health_inactive_interval);
+        // Nunca mas que el intervalo de los abiertos, por si el reenvio abre un circuito mientras tanto
+        tokio::time::sleep(next.min(config.health_inactive_interval).max(MIN_TICK)).await;
+    }
+}
+
+fn probe_interval(circuit: CircuitState, config: &Config) -> Duration {
+    match circuit {
+        CircuitState::Open => config.health_inactive_interval,
+        CircuitState::Closed | CircuitState::HalfOpen => config.health_interval,
+    }
+}
+
+// Por que un ping no paso; el motivo queda en el log
+#[derive(Debug)]
+enum PingError {
+    Draining,       // El worker se esta apagando, no hay que esperar el umbral
+    Failed(String),
+}
+
+impl fmt::Display for PingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PingError::Draining => f.write_str("no esta listo: se esta apagando"),
+            PingError::Failed(reason) => f.write_str(reason),
+        }
+    }
+}
+
+/*
+Pregunta al worker si puede recibir tareas (/ready).
+Se usa el JSON y no solo el codigo: un worker vivo pero drenando, sin disco
+o con otra version del protocolo cuenta como fallo y el motivo queda en el log.
+*/
+async fn ping(client: &reqwest::Client, address: &str, timeout: Duration) -> Result<(), PingError> {
+    let ready_url = format!("{}/ready", address);
+    let response = client.get(&ready_url).timeout(timeout).send().await.map_err(|e| PingError::Failed(e.to_string()))?;
+    let status = response.status();
+
+    match response.json::<Readiness>().await {
+        Ok(readiness) if !is_compatible(readiness.protocol_version) => Err(PingError::Failed(format!(
+            "version de protocolo {} incompatible, el dispatcher usa {}",
+            readiness.protocol_version, PROTOCOL_VERSION
+        ))),
+        Ok(readiness) if readiness.ready => Ok(()),
+        Ok(readiness) if readiness.draining => Err(PingError::Draining),
+        Ok(readiness) if !readiness.storage_writable => Err(PingError::Failed("no esta listo: no puede escribir archivos".to_string())),
+        Ok(_) => Err(PingError::Failed(format!("no esta listo (status {})", status))),
+        Err(e) => Err(PingError::Failed(format!("respuesta invalida en /ready (status {}): {}", status, e))),
+    }
+}
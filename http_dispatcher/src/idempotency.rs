@@ -0,0 +1,208 @@
+/*
+Soporte del header Idempotency-Key para las tareas que cambian algo en el worker.
+El dispatcher guarda la respuesta de cada clave durante IDEMPOTENCY_TTL_SECS:
+- Si el cliente repite la solicitud con la misma clave recibe la respuesta guardada
+  (con el header Idempotent-Replayed) y la tarea no se vuelve a ejecutar.
+- Si la primera todavia esta en curso se responde 409.
+- Si la clave se usa con otra solicitud (otro metodo, ruta o body) se responde 422.
+Un 5xx se descarta solo si ningun worker pudo ejecutar la tarea, asi el cliente la reintenta
+con la misma clave. Si hubo un 504 o se corto la conexion no se sabe si se ejecuto y se guarda
+la respuesta: repetir la clave nunca ejecuta la tarea otra vez. Lo mismo si la solicitud se
+interrumpe a mitad (por ejemplo al apagar el dispatcher): la clave queda con un 502.
+*/
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use http_common::request::Request;
+use http_common::response::Response;
+
+use crate::responses::http_response_502;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+pub const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+// Largo maximo de una clave, las claves tipicas son UUIDs
+const MAX_KEY_LEN: usize = 255;
+
+// Claves guardadas como maximo; al llenarse se descarta la respuesta que vence primero
+const MAX_ENTRIES: usize = 10_000;
+
+#[derive(Debug)]
+struct Entry {
+    fingerprint: u64,           // Metodo, ruta y body de la solicitud original
+    response: Option<Response>, // None mientras la tarea esta en curso
+    expires_at: Instant,        // Las claves en curso no vencen, las libera su Reservation
+}
+
+// Resultado de buscar la clave de una solicitud
+pub enum Claim<'a> {
+    New(Reservation<'a>),
+    Replay(Response),
+    InProgress,
+    Mismatch,
+}
+
+#[derive(Debug)]
+pub struct IdempotencyStore {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl IdempotencyStore {
+    pub fn new(ttl: Duration) -> IdempotencyStore {
+        IdempotencyStore {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Valida la clave del header; Err con el mensaje para el 400
+    pub fn key_of(request: &Request) -> Result<Option<&str>, String> {
+        match request.header(IDEMPOTENCY_KEY_HEADER).map(str::trim) {
+            None => Ok(None),
+            Some(key) if key.is_empty() || key.len() > MAX_KEY_LEN => {
+                Err(format!("{} debe tener entre 1 y {} caracteres", IDEMPOTENCY_KEY_HEADER, MAX_KEY_LEN))
+            }
+            Some(key) => Ok(Some(key)),
+        }
+    }
+
+    /*
+    Busca la clave y, si no existe, la reserva para esta solicitud.
+    Si la tarea termina sin llamar a `complete` la clave queda como resultado desconocido.
+    */
+    pub fn claim(&self, key: &str, request: &Request) -> Claim<'_> {
+        let fingerprint = fingerprint(request);
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.retain(|_, entry| entry.response.is_none() || entry.expires_at > now);
+
+        if let Some(entry) = entries.get(key) {
+            return match &entry.response {
+                _ if entry.fingerprint != fingerprint => Claim::Mismatch,
+                Some(response) => Claim::Replay(response.clone().header(REPLAYED_HEADER, "true")),
+                None => Claim::InProgress,
+            };
+        }
+
+        if entries.len() >= MAX_ENTRIES {
+            let oldest = entries
+                .iter()
+                .filter(|(_, entry)| entry.response.is_some())
+                .min_by_key(|(_, entry)| entry.expires_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(key.to_string(), Entry { fingerprint, response: None, expires_at: now + self.ttl });
+        Claim::New(Reservation { store: self, key: key.to_string(), done: false })
+    }
+}
+
+// Clave reservada por una solicitud en curso
+pub struct Reservation<'a> {
+    store: &'a IdempotencyStore,
+    key: String,
+    done: bool,
+}
+
+impl Reservation<'_> {
+    // Guarda la respuesta final de la tarea; `may_have_run` dice si algun worker pudo ejecutarla
+    pub fn complete(mut self, response: &Response, may_have_run: bool) {
+        self.done = true;
+        let mut entries = self.store.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if response.status() >= 500 && !may_have_run {
+            entries.remove(&self.key);
+        } else if let Some(entry) = entries.get_mut(&self.key) {
+            entry.response = Some(response.clone());
+            entry.expires_at = Instant::now() + self.store.ttl;
+        }
+    }
+}
+
+/*
+La solicitud se corto antes de terminar: el worker pudo haber recibido la tarea, asi que
+borrar la clave permitiria ejecutarla dos veces. Se guarda un 502 hasta que venza la clave.
+*/
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            let mut entries = self.store.entries.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(entry) = entries.get_mut(&self.key) {
+                entry.response = Some(http_response_502("La solicitud se interrumpio y no se sabe si la tarea se ejecuto"));
+                entry.expires_at = Instant::now() + self.store.ttl;
+            }
+        }
+    }
+}
+
+fn fingerprint(request: &Request) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    request.method.hash(&mut hasher);
+    request.target.hash(&mut hasher);
+    request.body.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_common::request::{parse_request, Limits};
+
+    const LIMITS: Limits = Limits { max_header_bytes: 8 * 1024, max_body_bytes: 1024 };
+
+    fn create(name: &str) -> Request {
+        let raw = format!("POST /createfile?name={}&content=hola HTTP/1.1\r\nHost: x\r\nIdempotency-Key: k\r\n\r\n", name);
+        parse_request(raw.as_bytes(), &LIMITS).unwrap().unwrap().0
+    }
+
+    fn reserve<'a>(store: &'a IdempotencyStore, key: &str, request: &Request) -> Reservation<'a> {
+        match store.claim(key, request) {
+            Claim::New(reservation) => reservation,
+            _ => panic!("la clave {} ya estaba reservada", key),
+        }
+    }
+
+    #[test]
+    fn an_interrupted_request_keeps_its_key() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+        let request = create("a");
+        drop(reserve(&store, "k", &request));
+
+        match store.claim("k", &request) {
+            Claim::Replay(response) => assert_eq!(response.status(), 502),
+            _ => panic!("la clave se libero y la tarea podria ejecutarse dos veces"),
+        }
+    }
+
+    #[test]
+    fn unreached_failures_release_the_key() {
+        let store = IdempotencyStore::new(Duration::from_secs(60));
+        let request = create("a");
+        reserve(&store, "k", &request).complete(&http_response_502("sin workers"), false);
+        drop(reserve(&store, "k", &request));
+
+        reserve(&store, "otra", &request).complete(&http_response_502("se corto"), true);
+        assert!(matches!(store.claim("otra", &request), Claim::Replay(_)));
+    }
+
+    #[test]
+    fn keys_in_progress_do_not_expire() {
+        let store = IdempotencyStore::new(Duration::from_millis(10));
+        let request = create("a");
+        let reservation = reserve(&store, "k", &request);
+        std::thread::sleep(Duration::from_millis(30));
+
+        // Otra clave limpia las vencidas, pero la que sigue en curso no se toca
+        drop(reserve(&store, "otra", &request));
+        assert!(matches!(store.claim("k", &request), Claim::InProgress));
+        assert!(matches!(store.claim("k", &create("b")), Claim::Mismatch));
+
+        reservation.complete(&Response::new(200), true);
+        assert!(matches!(store.claim("k", &request), Claim::Replay(_)));
+    }
+}
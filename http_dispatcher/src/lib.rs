@@ -0,0 +1,18 @@
+// Dispatcher: recibe las tareas de los clientes y las reparte entre los workers
+
+pub mod admin;
+pub mod auxiliares;
+pub mod circuit_breaker;
+pub mod config;
+pub mod health;
+pub mod idempotency;
+pub mod load_balancer;
+pub mod metrics;
+pub mod reload;
+pub mod responses;
+pub mod retry;
+pub mod server;
+
+pub use auxiliares::Worker;
+pub use config::Config;
+pub use server::{run, run_until, shutdown_signal};
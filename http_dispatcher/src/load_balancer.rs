@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use http_common::request::Request;
+use http_common::warn;
+use rand::Rng;
+
+use crate::auxiliares::Worker;
+
+/*
+    Estrategias para elegir a que worker se envia una tarea.
+    Cada estrategia recibe la lista completa de workers y solo considera los disponibles
+    (ver Worker::is_available).
+    Se elige con la variable LB_STRATEGY.
+*/
+pub trait LoadBalancer: Send + Sync + Debug {
+    // Nombre con el que se configura y se reporta en /workers
+    fn name(&self) -> &'static str;
+
+    // Devuelve el indice del worker elegido, `key` solo lo usan las estrategias con hashing
+    fn select(&self, workers: &[Worker], key: &str) -> Option<usize>;
+}
+
+// Nombres aceptados en LB_STRATEGY
+pub const STRATEGIES: [&str; 6] = [
+    "round_robin",
+    "least_outstanding",
+    "least_connections",
+    "weighted_round_robin",
+    "power_of_two",
+    "consistent_hash",
+];
+
+/*
+Crea la estrategia a partir de su nombre.
+Si el nombre no se reconoce se usa round robin, que era el comportamiento original.
+*/
+pub fn from_name(name: &str) -> Box<dyn LoadBalancer> {
+    match name.trim().to_ascii_lowercase().as_str() {
+        "round_robin" => Box::new(RoundRobin::default()),
+        //Los dos nombres eligen el worker con menos tareas en curso
+        "least_outstanding" | "least_connections" => Box::new(LeastOutstanding::default()),
+        "weighted_round_robin" => Box::new(WeightedRoundRobin::default()),
+        "power_of_two" => Box::new(PowerOfTwo),
+        "consistent_hash" => Box::new(ConsistentHash::default()),
+        other => {
+            warn!("lb", strategy = other; "Estrategia desconocida, se usa round_robin. Opciones: {}", STRATEGIES.join(", "));
+            Box::new(RoundRobin::default())
+        }
+    }
+}
+
+/*
+Clave con la que se reparte en consistent_hash, segun LB_HASH_KEY:
+  "target"        ruta con query (por defecto), la misma tarea cae en el mismo worker
+  "path"          solo la ruta
+  "header:<name>" valor de un header, por ejemplo header:x-user-id
+  "query:<name>"  valor de un parametro de la query o del formulario
+Si el header o parametro no viene se usa la ruta con query.
+*/
+pub fn request_key(request: &Request, spec: &str) -> String {
+    let key = match spec.split_once(':') {
+        Some(("header", name)) => request.header(name).map(|v| v.to_string()),
+        Some(("query", name)) => request.params().get(name).map(|v| v.to_string()),
+        None if spec == "path" => Some(request.path.clone()),
+        _ => None,
+    };
+    key.unwrap_or_else(|| request.target.clone())
+}
+
+fn available_indices(workers: &[Worker]) -> Vec<usize> {
+    workers
+        .iter()
+        .enumerate()
+        .filter(|(_, w)| w.is_available())
+        .map(|(i, _)| i)
+        .collect()
+}
+
+//Round robin: recorre los workers en orden saltando los que no estan disponibles
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl LoadBalancer for RoundRobin {
+    fn name(&self) -> &'static str {
+        "round_robin"
+    }
+
+    fn select(&self, workers: &[Worker], _key: &str) -> Option<usize> {
+        let num_workers = workers.len();
+        if num_workers == 0 {
+            return None;
+        }
+
+        let start = self.next.load(Ordering::Relaxed) % num_workers;
+        for offset in 0..num_workers {
+            let index = (start + offset) % num_workers;
+            if workers[index].is_available() {
+                self.next.store((index + 1) % num_workers, Ordering::Relaxed);
+                return Some(index);
+            }
+        }
+        None
+    }
+}
+
+/*
+Menos solicitudes pendientes: elige el worker con menos tareas en curso.
+Los empates se rompen en round robin; si no, con poca carga todos estan en 0
+y todas las tareas caerian en el primer worker.
+*/
+#[derive(Debug, Default)]
+pub struct LeastOutstanding {
+    next: AtomicUsize,
+}
+
+impl LoadBalancer for LeastOutstanding {
+    fn name(&self) -> &'static str {
+        "least_outstanding"
+    }
+
+    fn select(&self, workers: &[Worker], _key: &str) -> Option<usize> {
+        let candidates = available_indices(workers);
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..candidates.len())
+            .map(|offset| candidates[(start + offset) % candidates.len()])
+            .min_by_key(|&i| workers[i].in_flight())
+    }
+}
+
+/*
+Round robin ponderado "suave" (el que usa nginx).
+En cada seleccion se suma el peso a cada worker, se elige el de mayor acumulado
+y se le resta el total; asi los workers pesados no reciben rafagas seguidas.
+*/
+#[derive(Debug, Default)]
+pub struct WeightedRoundRobin {
+    current: Mutex<HashMap<String, i64>>, // Peso acumulado por id de worker
+}
+
+impl LoadBalancer for WeightedRoundRobin {
+    fn name(&self) -> &'static str {
+        "weighted_round_robin"
+    }
+
+    fn select(&self, workers: &[Worker], _key: &str) -> Option<usize> {
+        let candidates = available_indices(workers);
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let mut current = self.current.lock().unwrap();
+        let total: i64 = candidates.iter().map(|&i| workers[i].weight as i64).sum();
+
+        let mut best: Option<(usize, i64)> = None;
+        for &i in &candidates {
+            let acc = current.entry(workers[i].id.clone()).or_insert(0);
+            *acc += workers[i].weight as i64;
+            if best.is_none_or(|(_, best_acc)| *acc > best_acc) {
+                best = Some((i, *acc));
+            }
+        }
+
+        let (chosen, _) = best?;
+        if let Some(acc) = current.get_mut(&workers[chosen].id) {
+            *acc -= total;
+        }
+        Some(chosen)
+    }
+}
+
+/*
+Power of two choices: se toman dos workers activos al azar y se elige
+el que tenga menos tareas en curso. Casi tan bueno como revisar todos y sin sesgo.
+*/
+#[derive(Debug)]
+pub struct PowerOfTwo;
+
+impl LoadBalancer for PowerOfTwo {
+    fn name(&self) -> &'static str {
+        "power_of_two"
+    }
+
+    fn select(&self, workers: &[Worker], _key: &str) -> Option<usize> {
+        let candidates = available_indices(workers);
+        match candidates.len() {
+            0 => None,
+            1 => Some(candidates[0]),
+            len => {
+                let mut rng = rand::rng();
+                let first = rng.random_range(0..len);
+                // El segundo se elige entre los restantes para que sean distintos
+                let second = (first + rng.random_range(1..len)) % len;
+                let (a, b) = (candidates[first], candidates[second]);
+                if workers[b].in_flight() < workers[a].in_flight() { Some(b) } else { Some(a) }
+            }
+        }
+    }
+}
+
+// Nodos virtuales por worker en el anillo, reparten mejor las claves
+const VIRTUAL_NODES: usize = 100;
+
+/*
+Hashing consistente: la misma clave siempre va al mismo worker mientras este activo.
+Si un worker se cae solo se mueven las claves que le tocaban a el.
+*/
+#[derive(Debug, Default)]
+pub struct ConsistentHash {
+    ring: Mutex<Ring>,
+}
+
+// Anillo construido para un conjunto de workers activos
+#[derive(Debug, Default)]
+struct Ring {
+    members: Vec<String>,       // Ids de los workers con que se armo
+    points: Vec<(u64, String)>, // (posicion, id del worker), ordenado por posicion
+}
+
+impl LoadBalancer for ConsistentHash {
+    fn name(&self) -> &'static str {
+        "consistent_hash"
+    }
+
+    fn select(&self, workers: &[Worker], key: &str) -> Option<usize> {
+        let members: Vec<String> = available_indices(workers).into_iter().map(|i| workers[i].id.clone()).collect();
+        if members.is_empty() {
+            return None;
+        }
+
+        let mut ring = self.ring.lock().unwrap();
+        // Solo se reconstruye cuando cambia el conjunto de workers activos
+        if ring.members != members {
+            let mut points: Vec<(u64, String)> = members
+                .iter()
+                .flat_map(|id| (0..VIRTUAL_NODES).map(move |v| (hash_key(format!("{}#{}", id, v).as_bytes()), id.clone())))
+                .collect();
+            points.sort();
+            *ring = Ring { members, points };
+        }
+
+        // Primer punto del anillo con posicion >= hash de la clave, dando la vuelta al final
+        let hash = hash_key(key.as_bytes());
+        let pos = ring.points.partition_point(|(point, _)| *point < hash) % ring.points.len();
+        let owner = &ring.points[pos].1;
+        workers.iter().position(|w| &w.id == owner)
+    }
+}
+
+/*
+FNV-1a de 64 bits, estable entre ejecuciones (a diferencia de DefaultHasher).
+Al final se mezclan los bits (fmix64 de murmur3): claves que solo cambian en el
+ultimo caracter quedaban muy juntas en el anillo y casi todas iban al mismo worker.
+*/
+fn hash_key(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit_breaker::CircuitBreaker;
+
+    // Workers con el circuito cerrado, como despues del primer healthcheck
+    fn workers(weights: &[u32]) -> Vec<Worker> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, &weight)| {
+                let mut worker = Worker::new(&format!("worker{}", i + 1), &format!("127.0.0.1:{}", 9001 + i), weight);
+                worker.circuit.health_passed();
+                worker
+            })
+            .collect()
+    }
+
+    fn picks(balancer: &dyn LoadBalancer, workers: &[Worker], count: usize) -> Vec<usize> {
+        (0..count).map(|_| balancer.select(workers, "").unwrap()).collect()
+    }
+
+    #[test]
+    fn least_outstanding_picks_the_least_busy_worker() {
+        let workers = workers(&[1, 1, 1, 1]);
+        for (worker, in_flight) in workers.iter().zip([4, 1, 3, 1]) {
+            worker.in_flight.store(in_flight, Ordering::Relaxed);
+        }
+        let balancer = LeastOutstanding::default();
+
+        // worker2 y worker4 empatan con 1 y se reparten las tareas entre ellos
+        let chosen = picks(&balancer, &workers, 8);
+        assert!(chosen.iter().all(|&i| i == 1 || i == 3), "{:?}", chosen);
+        assert_eq!(chosen.iter().filter(|&&i| i == 1).count(), 4, "{:?}", chosen);
+
+        workers[3].in_flight.store(0, Ordering::Relaxed);
+        assert_eq!(picks(&balancer, &workers, 3), vec![3, 3, 3]);
+    }
+
+    #[test]
+    fn least_outstanding_rotates_when_every_worker_is_idle() {
+        let workers = workers(&[1, 1, 1]);
+        let balancer = LeastOutstanding::default();
+        assert_eq!(picks(&balancer, &workers, 6), vec![0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn power_of_two_never_picks_an_unavailable_worker() {
+        let mut workers = workers(&[1, 1, 1, 1, 1]);
+        // El menos cargado esta fuera: uno drenando y otro con el circuito abierto
+        workers[0].draining = true;
+        workers[4].circuit = CircuitBreaker::new();
+        for (worker, in_flight) in workers.iter().zip([0, 5, 7, 9, 0]) {
+            worker.in_flight.store(in_flight, Ordering::Relaxed);
+        }
+
+        let mut seen = [0; 5];
+        for index in picks(&PowerOfTwo, &workers, 500) {
+            seen[index] += 1;
+        }
+        assert_eq!((seen[0], seen[4]), (0, 0));
+        // El mas cargado de los disponibles nunca gana un par
+        assert_eq!(seen[3], 0);
+        assert!(seen[1] > seen[2] && seen[2] > 0, "{:?}", seen);
+
+        for worker in &mut workers {
+            worker.draining = true;
+        }
+        assert_eq!(PowerOfTwo.select(&workers, ""), None);
+    }
+
+    #[test]
+    fn weighted_round_robin_interleaves_the_heavy_worker() {
+        let workers = workers(&[5, 1, 1]);
+        let balancer = WeightedRoundRobin::default();
+
+        // La secuencia de nginx para 5/1/1, y se repite en cada vuelta
+        let round = vec![0, 0, 1, 0, 2, 0, 0];
+        assert_eq!(picks(&balancer, &workers, 7), round);
+        assert_eq!(picks(&balancer, &workers, 7), round);
+    }
+
+    #[test]
+    fn consistent_hash_only_moves_the_keys_of_the_missing_worker() {
+        let mut workers = workers(&[1, 1, 1, 1]);
+        let balancer = ConsistentHash::default();
+        let keys: Vec<String> = (0..1000).map(|i| format!("/reverse?text={}", i)).collect();
+        let owners: Vec<usize> = keys.iter().map(|key| balancer.select(&workers, key).unwrap()).collect();
+
+        // La misma clave sigue yendo al mismo worker, y otra instancia arma el mismo anillo
+        let again = ConsistentHash::default();
+        for (key, &owner) in keys.iter().zip(&owners) {
+            assert_eq!(balancer.select(&workers, key), Some(owner));
+            assert_eq!(again.select(&workers, key), Some(owner));
+        }
+        // Con 100 nodos virtuales ningun worker se queda con casi todo ni con casi nada
+        for index in 0..workers.len() {
+            let count = owners.iter().filter(|&&owner| owner == index).count();
+            assert!((150..=350).contains(&count), "worker{} tiene {} claves", index + 1, count);
+        }
+
+        workers[2].draining = true;
+        for (key, &owner) in keys.iter().zip(&owners) {
+            let now = balancer.select(&workers, key).unwrap();
+            if owner == 2 {
+                assert_ne!(now, 2);
+            } else {
+                assert_eq!(now, owner, "{} cambio de worker", key);
+            }
+        }
+
+        // Cuando vuelve recupera exactamente sus claves
+        workers[2].draining = false;
+        for (key, &owner) in keys.iter().zip(&owners) {
+            assert_eq!(balancer.select(&workers, key), Some(owner));
+        }
+    }
+}
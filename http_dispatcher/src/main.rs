@@ -1,54 +1,30 @@
-use std::sync::{Arc, Mutex};
-use std::net::{TcpListener};
-use std::thread;
+use http_dispatcher::auxiliares::initialize_workers;
+use http_common::{error, info, log};
+use http_dispatcher::Config;
+use tokio::net::TcpListener;
 
-use tokio::runtime::Runtime;
+#[tokio::main]
+async fn main() {
+    let config = Config::load();
+    log::init(config.log_level, config.log_format);
 
-use crate::auxiliares::{handle_cliente, health_check, initialize_workers, DispatcherState};
-
-mod auxiliares;
-mod responses;
-
-fn main() {
-    println!("Iniciado Dispatcher...");
-
-    //Creamos un runtime de Tokio para ejecutar las tareas asincronicos
-    let rt = Runtime::new().unwrap();
+    info!("dispatcher", "Iniciando el dispatcher");
 
     // Incializa el estados de los workers
-    let workers = initialize_workers();
-
-    let initial_state = DispatcherState {
-        workers,
-        next_worker_index: 0,
-    };
-
-    //Inicializamos el estado del dispatcher
-    let dispatcher_state = Arc::new(Mutex::new(initial_state));
-
-    //Iniciamos el hilo en segundo plano para el healthcheck
-    let healthcheck_state = dispatcher_state.clone();
-    rt.spawn(async move {
-        health_check(healthcheck_state).await;
-    });
-    println!("Hilo de healthcheck iniciado.");
+    let workers = initialize_workers(&config);
 
     //Abrimos el TCP para escuchar las solicituides de los clientes
-    let listener = TcpListener::bind("0.0.0.0:8080").expect("No se pudo iniciar el servidor en el puerto 8080");
-    println!("Dispatcher escuchando en http://0.0.0.0:8080");
-
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                let state_clone = dispatcher_state.clone();
-                
-                thread::spawn(move || {
-                    handle_cliente(stream, state_clone);
-                });
-            }
-            Err(e) => {
-                eprintln!("Error al aceptar conexion: {}", e);
-            }
+    let listener = match TcpListener::bind(&config.listen_addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("dispatcher", address = config.listen_addr; "No se pudo iniciar el servidor: {}", e);
+            std::process::exit(1);
         }
-    }
-}
\ No newline at end of file
+    };
+    info!("dispatcher", address = config.listen_addr; "Dispatcher escuchando");
+
+    // Sin esto SIGTERM cortaba las tareas en curso, por ejemplo un /montecarlo repartido
+    let finished = http_dispatcher::run_until(listener, config, workers, http_dispatcher::shutdown_signal()).await;
+    info!("dispatcher", "Dispatcher apagado");
+    std::process::exit(if finished { 0 } else { 1 });
+}
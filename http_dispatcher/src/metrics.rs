@@ -0,0 +1,141 @@
+// Metricas del dispatcher que se publican en /metrics, en el formato de Prometheus
+use std::time::Duration;
+
+use http_common::metrics::{method_label, Counter, Gauge, Histogram, LATENCY_BUCKETS};
+
+use crate::auxiliares::Worker;
+use crate::circuit_breaker::CircuitState;
+
+#[derive(Debug)]
+pub struct Metrics {
+    requests: Counter,
+    request_duration: Histogram,
+    pub requests_in_flight: Gauge,
+    forward_duration: Histogram, // Lo que tarda cada worker en responder una tarea reenviada
+    forward_errors: Counter,
+    pub retries: Counter,
+    health_checks: Counter,
+    health_check_duration: Histogram,
+    montecarlo_points: Counter,
+    montecarlo_rate: Gauge,
+    worker_in_flight: Gauge,     // Se copian de la lista de workers al leer /metrics
+    worker_circuit: Gauge,
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            requests: Counter::new("http_requests_total", "Solicitudes de clientes por ruta, metodo y codigo", &["route", "method", "status"]),
+            request_duration: Histogram::new(
+                "http_request_duration_seconds",
+                "Tiempo para responder a un cliente por ruta y codigo, incluidos los reintentos",
+                &["route", "status"],
+                LATENCY_BUCKETS,
+            ),
+            requests_in_flight: Gauge::new("http_requests_in_flight", "Solicitudes de clientes que se estan atendiendo", &[]),
+            forward_duration: Histogram::new(
+                "dispatcher_forward_duration_seconds",
+                "Tiempo de respuesta de cada worker a una tarea reenviada",
+                &["worker"],
+                LATENCY_BUCKETS,
+            ),
+            forward_errors: Counter::new(
+                "dispatcher_forward_errors_total",
+                "Tareas reenviadas que fallaron: unreachable, timeout, transport o status (5xx)",
+                &["worker", "kind"],
+            ),
+            retries: Counter::new("dispatcher_retries_total", "Tareas que se volvieron a enviar a otro worker", &[]),
+            health_checks: Counter::new("dispatcher_health_checks_total", "Pings a /ready por worker y resultado", &["worker", "result"]),
+            health_check_duration: Histogram::new(
+                "dispatcher_health_check_duration_seconds",
+                "Tiempo de respuesta de los pings a /ready",
+                &["worker"],
+                LATENCY_BUCKETS,
+            ),
+            montecarlo_points: Counter::new("dispatcher_montecarlo_points_total", "Puntos de Montecarlo simulados entre todos los workers", &[]),
+            montecarlo_rate: Gauge::new(
+                "dispatcher_montecarlo_points_per_second",
+                "Puntos por segundo de la ultima solicitud de Montecarlo",
+                &[],
+            ),
+            worker_in_flight: Gauge::new("dispatcher_worker_in_flight", "Tareas en curso en cada worker", &["worker"]),
+            worker_circuit: Gauge::new(
+                "dispatcher_worker_circuit_state",
+                "Estado del circuit breaker de cada worker, 1 en el estado actual",
+                &["worker", "state"],
+            ),
+        }
+    }
+
+    // `route` ya viene acotada (ver auxiliares::route_label) para no crear una serie por cada URL
+    pub fn record_request(&self, route: &str, method: &str, status: u16, elapsed: Duration) {
+        let status = status.to_string();
+        self.requests.inc(&[route, method_label(method), &status]);
+        self.request_duration.observe(&[route, &status], elapsed.as_secs_f64());
+    }
+
+    pub fn record_forward(&self, worker: &str, elapsed: Duration) {
+        self.forward_duration.observe(&[worker], elapsed.as_secs_f64());
+    }
+
+    pub fn record_forward_error(&self, worker: &str, kind: &str) {
+        self.forward_errors.inc(&[worker, kind]);
+    }
+
+    pub fn record_health_check(&self, worker: &str, ok: bool, elapsed: Duration) {
+        self.health_checks.inc(&[worker, if ok { "ok" } else { "fail" }]);
+        self.health_check_duration.observe(&[worker], elapsed.as_secs_f64());
+    }
+
+    /*
+    Borra las series de un worker que se quito de la lista. Los ids no se reutilizan,
+    asi que sin esto cada registro o recarga dejaria series para siempre.
+    */
+    pub fn forget_worker(&self, worker: &str) {
+        self.forward_duration.remove("worker", worker);
+        self.forward_errors.remove("worker", worker);
+        self.health_checks.remove("worker", worker);
+        self.health_check_duration.remove("worker", worker);
+    }
+
+    pub fn record_montecarlo(&self, points: u64, elapsed: Duration) {
+        self.montecarlo_points.add(&[], points as f64);
+        if !elapsed.is_zero() {
+            self.montecarlo_rate.set(&[], points as f64 / elapsed.as_secs_f64());
+        }
+    }
+
+    pub fn render(&self, workers: &[Worker]) -> String {
+        // Los workers que se quitaron no deben seguir apareciendo
+        self.worker_in_flight.clear();
+        self.worker_circuit.clear();
+        for worker in workers {
+            self.worker_in_flight.set(&[&worker.id], worker.in_flight() as f64);
+            let state = worker.circuit.state();
+            for (name, value) in [(CircuitState::Closed, "closed"), (CircuitState::Open, "open"), (CircuitState::HalfOpen, "half_open")] {
+                self.worker_circuit.set(&[&worker.id, value], if state == name { 1.0 } else { 0.0 });
+            }
+        }
+
+        let mut out = String::new();
+        self.requests.render(&mut out);
+        self.request_duration.render(&mut out);
+        self.requests_in_flight.render(&mut out);
+        self.forward_duration.render(&mut out);
+        self.forward_errors.render(&mut out);
+        self.retries.render(&mut out);
+        self.health_checks.render(&mut out);
+        self.health_check_duration.render(&mut out);
+        self.montecarlo_points.render(&mut out);
+        self.montecarlo_rate.render(&mut out);
+        self.worker_in_flight.render(&mut out);
+        self.worker_circuit.render(&mut out);
+        out
+    }
+}
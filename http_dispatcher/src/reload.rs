@@ -0,0 +1,206 @@
+/*
+Recarga de la lista de workers sin reiniciar el dispatcher.
+Con WORKERS_FILE el dispatcher revisa el archivo cada WORKERS_FILE_POLL_SECS
+y tambien lo vuelve a leer al recibir SIGHUP.
+
+Formato del archivo, un worker por linea con peso opcional:
+    # comentario
+    http://worker1:7878
+    http://worker2:7878 3
+*/
+use std::fs;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use http_common::{info, warn};
+use tokio::sync::Mutex;
+
+use crate::auxiliares::{DispatcherState, Worker, WorkerSource};
+use crate::config::Config;
+use crate::metrics::Metrics;
+
+// Cada cuanto se revisa si los workers que se estan drenando ya terminaron sus tareas
+const DRAIN_TICK: Duration = Duration::from_millis(100);
+
+// Entrada del archivo de workers
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkerEntry {
+    pub address: String,
+    pub weight: u32,
+}
+
+// Lo que cambio en una recarga, para el log
+#[derive(Debug, Default, PartialEq)]
+pub struct ReloadSummary {
+    pub added: Vec<String>,
+    pub kept: Vec<String>,
+    pub draining: Vec<String>,
+}
+
+pub fn read_worker_list(path: &str) -> Result<Vec<WorkerEntry>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("No se pudo leer {}: {}", path, e))?;
+    parse_worker_list(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+pub fn parse_worker_list(text: &str) -> Result<Vec<WorkerEntry>, String> {
+    let mut entries: Vec<WorkerEntry> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let address = fields.next().unwrap_or_default().trim_end_matches('/').to_string();
+        if !address.starts_with("http://") && !address.starts_with("https://") {
+            return Err(format!("linea {}: '{}' no es una URL http://", number + 1, address));
+        }
+        let weight = match fields.next() {
+            Some(w) => w.parse::<u32>().ok().filter(|w| *w > 0).ok_or_else(|| format!("linea {}: peso invalido '{}'", number + 1, w))?,
+            None => 1,
+        };
+        if fields.next().is_some() {
+            return Err(format!("linea {}: se esperaba 'direccion [peso]'", number + 1));
+        }
+        // Una direccion repetida se toma una sola vez
+        if !entries.iter().any(|e| e.address == address) {
+            entries.push(WorkerEntry { address, weight });
+        }
+    }
+    Ok(entries)
+}
+
+/*
+Ajusta la lista de workers a las entradas del archivo.
+Solo toca los workers de la configuracion; los de /admin y los auto-registrados se mantienen.
+- Direccion que ya estaba: se conserva el worker con sus contadores y se actualiza el peso.
+- Direccion nueva: se agrega con el circuito abierto hasta que pase el healthcheck.
+- Direccion que ya no esta: se drena, deja de recibir tareas y se borra cuando termina las que tenia.
+*/
+pub fn reconcile(state: &mut DispatcherState, entries: &[WorkerEntry]) -> ReloadSummary {
+    let mut summary = ReloadSummary::default();
+
+    for entry in entries {
+        match state.workers.iter_mut().find(|w| w.address == entry.address) {
+            Some(worker) => {
+                worker.weight = entry.weight;
+                worker.source = WorkerSource::Config;
+                worker.expires_at = None;
+                // Vuelve a la lista antes de terminar de drenarse, el circuito sigue como estaba
+                worker.draining = false;
+                summary.kept.push(worker.id.clone());
+            }
+            None => {
+                let id = format!("worker{}", state.next_worker_id);
+                state.next_worker_id += 1;
+                state.workers.push(Worker::new(&id, &entry.address, entry.weight));
+                summary.added.push(id);
+            }
+        }
+    }
+
+    for worker in state.workers.iter_mut() {
+        let listed = entries.iter().any(|e| e.address == worker.address);
+        if worker.source == WorkerSource::Config && !listed && !worker.draining {
+            worker.draining = true;
+            summary.draining.push(worker.id.clone());
+        }
+    }
+    summary
+}
+
+// Borra los workers drenados que ya no tienen tareas en curso
+pub fn remove_drained(state: &mut DispatcherState, metrics: &Metrics) {
+    state.workers.retain(|w| {
+        let done = w.draining && w.in_flight() == 0;
+        if done {
+            info!("reload", worker = w.id, address = w.address; "Worker drenado, se elimina");
+            metrics.forget_worker(&w.id);
+        }
+        !done
+    });
+}
+
+/*
+Tarea en segundo plano que vigila WORKERS_FILE.
+Se compara el contenido y no la fecha de modificacion, que en algunos sistemas
+de archivos solo tiene precision de segundos.
+*/
+pub async fn watch_worker_list(state_dispatcher: Arc<Mutex<DispatcherState>>, config: Arc<Config>, metrics: Arc<Metrics>) {
+    let path = config.workers_file.clone();
+    let mut last_text = fs::read_to_string(&path).ok();
+    let mut last_check = Instant::now();
+    let mut hangup = hangup_signal();
+
+    loop {
+        let reload_now = tokio::select! {
+            _ = tokio::time::sleep(DRAIN_TICK) => false,
+            _ = recv_hangup(&mut hangup) => {
+                info!("reload", file = path; "SIGHUP recibido, se recarga la lista");
+                true
+            }
+        };
+
+        let poll_due = !config.workers_file_poll.is_zero() && last_check.elapsed() >= config.workers_file_poll;
+        if reload_now || poll_due {
+            last_check = Instant::now();
+            let text = fs::read_to_string(&path).ok();
+            if reload_now || text != last_text {
+                last_text = text;
+                reload(&state_dispatcher, &path).await;
+            }
+        }
+
+        remove_drained(&mut *state_dispatcher.lock().await, &metrics);
+    }
+}
+
+async fn reload(state_dispatcher: &Arc<Mutex<DispatcherState>>, path: &str) {
+    // Si el archivo no se puede leer o tiene errores se deja la lista como estaba
+    let entries = match read_worker_list(path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("reload", "{}. Se mantiene la lista actual", e);
+            return;
+        }
+    };
+
+    let summary = reconcile(&mut *state_dispatcher.lock().await, &entries);
+    info!(
+        "reload",
+        added = summary.added.join(","),
+        kept = summary.kept.join(","),
+        draining = summary.draining.join(",");
+        "Lista de workers recargada"
+    );
+}
+
+#[cfg(unix)]
+type Hangup = Option<tokio::signal::unix::Signal>;
+#[cfg(not(unix))]
+type Hangup = ();
+
+#[cfg(unix)]
+fn hangup_signal() -> Hangup {
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(signal) => Some(signal),
+        Err(e) => {
+            warn!("reload", "No se pudo escuchar SIGHUP: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn hangup_signal() -> Hangup {}
+
+// Espera un SIGHUP; si no se puede escuchar la senal nunca termina
+async fn recv_hangup(hangup: &mut Hangup) {
+    #[cfg(unix)]
+    if let Some(signal) = hangup {
+        signal.recv().await;
+        return;
+    }
+    let _ = hangup;
+    std::future::pending::<()>().await
+}
@@ -1,28 +1,75 @@
-pub fn http_response_200(body : &str) -> String {
-    let json = format!("{{\"status\":200,\"message\":\"{}\"}}", body);
-    println!("Json que genera: {}", json);
-    format!(
-        "HTTP/1.0 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
-        json.len(),
-        json
-    )
-}
-
-pub fn http_resonse_400(msg: &str) -> String {
-    let json = format!("{{\"status\" : 400, \"error\" : \"{}\"}}", msg);
-    format!(
-        "HTTP/1.0 400 Bad Request\r\nContent-Length: {}\r\nContent-Type: text/plain\r\n\r\n{}",
-        json.len(),
-        json
-    )
-}
-
-pub fn http_response_500_json(msg: &str) -> String {
-    let json = format!("{{\"status\":500,\"message\":\"{}\"}}", msg);
-    format!(
-        "HTTP/1.0 500 Internal Server Error\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
-        json.len(),
-        json
-    )
+use http_common::api::ApiError;
+use http_common::metrics;
+use http_common::response::Response;
+use serde::Serialize;
+
+// Los bodies se serializan con serde y los errores usan el formato comun ApiError
+
+// Valor del header Server en las respuestas propias del dispatcher
+pub const SERVER_NAME: &str = concat!("http_dispatcher/", env!("CARGO_PKG_VERSION"));
+
+pub fn http_response_200<T: Serialize>(body: &T) -> Response {
+    Response::json(200, body)
+}
+
+// Respuesta de /metrics, texto en el formato de Prometheus
+pub fn http_response_metrics(text: String) -> Response {
+    Response::new(200).body(metrics::CONTENT_TYPE, text.into_bytes())
+}
+
+pub fn http_response_201<T: Serialize>(body: &T) -> Response {
+    Response::json(201, body)
+}
+
+pub fn http_resonse_400(msg: &str) -> Response {
+    Response::error(&ApiError::bad_request(msg))
+}
+
+pub fn http_response_401(msg: &str) -> Response {
+    Response::error(&ApiError::unauthorized(msg)).header("WWW-Authenticate", "Bearer")
+}
+
+pub fn http_response_403(msg: &str) -> Response {
+    Response::error(&ApiError::forbidden(msg))
+}
+
+pub fn http_response_404(msg: &str) -> Response {
+    Response::error(&ApiError::not_found(msg))
+}
+
+pub fn http_response_405(allow: &str) -> Response {
+    let error = ApiError::method_not_allowed(&format!("Metodo no permitido, use: {}", allow));
+    Response::error(&error).header("Allow", allow)
+}
+
+pub fn http_response_409(msg: &str) -> Response {
+    Response::error(&ApiError::conflict(msg)).header("Retry-After", "1")
+}
+
+pub fn http_response_413(msg: &str) -> Response {
+    Response::error(&ApiError::payload_too_large(msg))
+}
+
+pub fn http_response_422(msg: &str) -> Response {
+    Response::error(&ApiError::unprocessable(msg))
+}
+
+pub fn http_response_431(msg: &str) -> Response {
+    Response::error(&ApiError::headers_too_large(msg))
+}
+
+pub fn http_response_500_json(msg: &str) -> Response {
+    Response::error(&ApiError::internal(msg))
+}
+
+pub fn http_response_502(msg: &str) -> Response {
+    Response::error(&ApiError::bad_gateway(msg))
+}
+
+pub fn http_response_503(msg: &str) -> Response {
+    Response::error(&ApiError::unavailable(msg)).header("Retry-After", "1")
 }
 
+pub fn http_response_504(msg: &str) -> Response {
+    Response::error(&ApiError::gateway_timeout(msg))
+}
@@ -0,0 +1,130 @@
+/*
+Politica de reintentos del dispatcher.
+Una tarea se vuelve a enviar a otro worker cuando falla la conexion o cuando el worker
+responde con un codigo de RETRY_ON_STATUS, hasta RETRY_MAX_ATTEMPTS intentos en total.
+
+- Solo se reintentan solas las rutas idempotentes: GET, HEAD y OPTIONS que no esten
+  en NON_IDEMPOTENT_ROUTES. Las demas, con Idempotency-Key, solo cuando el worker responde
+  un codigo de RETRY_ON_STATUS; un error a mitad de la respuesta no dice si se ejecutaron.
+- Si no se pudo conectar la solicitud nunca llego al worker, eso se reintenta siempre.
+- Entre intentos se espera un backoff exponencial con jitter.
+- Cada reintento gasta del presupuesto; cuando se acaba no se reintenta, asi una caida
+  de varios workers no multiplica la carga sobre los que quedan.
+*/
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use http_common::request::Request;
+use rand::Rng;
+
+use crate::config::{Config, StatusCodes};
+
+// Como maximo se acumulan las fichas de estos segundos, para que un rato tranquilo no habilite una rafaga enorme
+const BUDGET_WINDOW_SECS: f64 = 10.0;
+
+#[derive(Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    backoff_base: Duration,
+    backoff_max: Duration,
+    retry_on: StatusCodes,
+    non_idempotent_routes: Vec<String>,
+    budget: RetryBudget,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &Config) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: config.retry_max_attempts.max(1),
+            backoff_base: config.retry_backoff_base,
+            backoff_max: config.retry_backoff_max,
+            retry_on: config.retry_on_status.clone(),
+            non_idempotent_routes: config
+                .non_idempotent_routes
+                .split(',')
+                .map(str::trim)
+                .filter(|route| !route.is_empty())
+                .map(str::to_string)
+                .collect(),
+            budget: RetryBudget::new(config.retry_budget_ratio, config.retry_budget_min_per_sec),
+        }
+    }
+
+    // La tarea se puede repetir sin que el cliente lo pida
+    pub fn is_idempotent(&self, request: &Request) -> bool {
+        matches!(request.method.as_str(), "GET" | "HEAD" | "OPTIONS") && !self.non_idempotent_routes.contains(&request.path)
+    }
+
+    pub fn retries_status(&self, status: u16) -> bool {
+        self.retry_on.contains(status)
+    }
+
+    // Cada solicitud recibida suma al presupuesto
+    pub fn record_request(&self) {
+        self.budget.deposit();
+    }
+
+    // Gasta un reintento del presupuesto; false si no queda
+    pub fn try_retry(&self) -> bool {
+        self.budget.withdraw()
+    }
+
+    /*
+    Espera antes del reintento numero `retry` (1 es el primero).
+    Full jitter: un valor al azar entre 0 y base * 2^(retry-1), con tope en RETRY_BACKOFF_MAX_MS,
+    para que los clientes que fallaron juntos no vuelvan a llegar juntos.
+    */
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self.backoff_base.saturating_mul(1u32 << retry.saturating_sub(1).min(16));
+        let ceiling = exponential.min(self.backoff_max);
+        if ceiling.is_zero() {
+            return ceiling;
+        }
+        Duration::from_micros(rand::rng().random_range(0..=ceiling.as_micros() as u64))
+    }
+}
+
+/*
+Presupuesto de reintentos como token bucket: cada solicitud suma RETRY_BUDGET_RATIO fichas
+y cada reintento gasta una. Ademas se reponen RETRY_BUDGET_MIN_PER_SEC fichas por segundo,
+para que con poco trafico igual se pueda reintentar.
+*/
+#[derive(Debug)]
+struct RetryBudget {
+    ratio: f64,
+    min_per_sec: f64,
+    cap: f64,
+    state: Mutex<(f64, Instant)>, // Fichas disponibles y ultima reposicion
+}
+
+impl RetryBudget {
+    fn new(ratio: f64, min_per_sec: f64) -> RetryBudget {
+        let ratio = ratio.max(0.0);
+        let min_per_sec = min_per_sec.max(0.0);
+        RetryBudget {
+            ratio,
+            min_per_sec,
+            cap: (min_per_sec * BUDGET_WINDOW_SECS).max(1.0),
+            // Se empieza con el minimo de un segundo
+            state: Mutex::new((min_per_sec, Instant::now())),
+        }
+    }
+
+    fn deposit(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.0 = (state.0 + self.ratio).min(self.cap);
+    }
+
+    fn withdraw(&self) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let refill = now.duration_since(state.1).as_secs_f64() * self.min_per_sec;
+        *state = ((state.0 + refill).min(self.cap), now);
+        if state.0 >= 1.0 {
+            state.0 -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use http_common::access_log::AccessLog;
+use http_common::trace::Tracer;
+use http_common::{debug, info, warn};
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+
+use crate::admin::expire_registrations;
+use crate::auxiliares::{build_http_client, handle_cliente, AppContext, DispatcherState, Worker};
+use crate::config::Config;
+use crate::health::health_check;
+use crate::idempotency::IdempotencyStore;
+use crate::load_balancer;
+use crate::metrics::Metrics;
+use crate::reload::watch_worker_list;
+use crate::retry::RetryPolicy;
+
+/*
+Corre el dispatcher sobre un listener ya abierto.
+`main` lo usa con el puerto publico y las pruebas con un puerto libre de localhost.
+Inicia el healthcheck en segundo plano y atiende clientes hasta que se cancele la tarea.
+*/
+pub async fn run(listener: TcpListener, config: Config, workers: Vec<Worker>) {
+    run_until(listener, config, workers, std::future::pending()).await;
+}
+
+/*
+Igual que `run`, pero cuando termina `shutdown` deja de aceptar clientes y espera
+a que terminen las solicitudes en curso (incluidas las de /montecarlo), como maximo
+SHUTDOWN_TIMEOUT_SECS. Devuelve false si hubo que cortar alguna.
+*/
+pub async fn run_until(listener: TcpListener, config: Config, workers: Vec<Worker>, shutdown: impl Future<Output = ()>) -> bool {
+    let config = Arc::new(config);
+
+    //Un solo cliente HTTP para todo el dispatcher, asi se reutilizan las conexiones a los workers
+    let client = build_http_client(&config);
+
+    let balancer = load_balancer::from_name(&config.lb_strategy);
+    info!("dispatcher", strategy = balancer.name(); "Estrategia de balanceo");
+    if config.admin_token.is_empty() {
+        warn!("admin", "ADMIN_TOKEN no esta definido, /admin solo acepta conexiones desde localhost");
+    }
+
+    let initial_state = DispatcherState {
+        next_worker_id: workers.len() + 1,
+        workers,
+        balancer,
+    };
+
+    //Inicializamos el estado del dispatcher
+    let dispatcher_state = Arc::new(Mutex::new(initial_state));
+    let metrics = Arc::new(Metrics::new());
+
+    //Iniciamos la tarea en segundo plano para el healthcheck
+    let health_task = tokio::spawn(health_check(dispatcher_state.clone(), client.clone(), config.clone(), metrics.clone()));
+    debug!("dispatcher", "Tarea de healthcheck iniciada");
+    //Si se cancela `run` el healthcheck no debe quedar corriendo solo
+    let _health_task = AbortOnDrop(health_task);
+
+    //Borra los workers auto-registrados que dejan de enviar heartbeats
+    let _expiry_task = AbortOnDrop(tokio::spawn(expire_registrations(dispatcher_state.clone(), config.registration_ttl, metrics.clone())));
+
+    //Recarga la lista de workers cuando cambia WORKERS_FILE o llega SIGHUP
+    let _reload_task = if config.workers_file.is_empty() {
+        None
+    } else {
+        info!("reload", file = config.workers_file; "Vigilando la lista de workers");
+        Some(AbortOnDrop(tokio::spawn(watch_worker_list(dispatcher_state.clone(), config.clone(), metrics.clone()))))
+    };
+
+    let ctx = AppContext {
+        state: dispatcher_state,
+        client,
+        retry: Arc::new(RetryPolicy::from_config(&config)),
+        idempotency: Arc::new(IdempotencyStore::new(config.idempotency_ttl)),
+        metrics,
+        tracer: Arc::new(Tracer::new("dispatcher", &config.trace)),
+        access_log: Arc::new(AccessLog::new(&config.access_log)),
+        config,
+    };
+
+    //Conexiones en curso, para poder esperarlas al apagar
+    let mut connections = JoinSet::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    //Cada cliente se atiende en su propia tarea de Tokio
+                    connections.spawn(handle_cliente(stream, ctx.clone()));
+                }
+                Err(e) => {
+                    warn!("dispatcher", "Error al aceptar conexion: {}", e);
+                    //Por ejemplo si se acabaron los descriptores de archivo, esperamos un poco antes de reintentar
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            },
+            //Se van recogiendo las conexiones que terminan para que el JoinSet no crezca
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+        }
+    }
+
+    drop(listener);
+    info!("dispatcher", connections = connections.len(); "Se dejo de aceptar clientes, esperando las conexiones en curso");
+    let wait_all = async { while connections.join_next().await.is_some() {} };
+    let finished = tokio::time::timeout(ctx.config.shutdown_timeout, wait_all).await.is_ok();
+    if finished {
+        info!("dispatcher", "Todas las conexiones terminaron");
+    } else {
+        warn!("dispatcher", connections = connections.len(); "Quedaron conexiones despues de {:?}, se cortan", ctx.config.shutdown_timeout);
+        connections.abort_all();
+    }
+    finished
+}
+
+/*
+Termina con SIGTERM (docker compose down) o Ctrl+C.
+Una segunda senal mientras se espera a las conexiones termina el proceso sin esperar.
+*/
+pub async fn shutdown_signal() {
+    wait_for_signal().await;
+    info!("dispatcher", "Senal de apagado recibida");
+    tokio::spawn(async {
+        wait_for_signal().await;
+        warn!("dispatcher", "Segunda senal, se termina sin esperar las conexiones");
+        std::process::exit(1);
+    });
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+        }
+        Err(e) => {
+            warn!("dispatcher", "No se pudo escuchar SIGTERM: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+// Cancela una tarea de Tokio cuando se suelta
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
@@ -0,0 +1,626 @@
+// Pruebas de punta a punta: dispatcher y workers reales hablando por HTTP
+mod common;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{active_count, circuit_state, worker_field, Cluster, Options};
+
+#[tokio::test]
+async fn forwards_tasks_in_round_robin() {
+    let cluster = Cluster::start(Options::default()).await;
+
+    for _ in 0..9 {
+        let (status, body) = cluster.get("/reverse?text=hola%20mundo").await;
+        assert_eq!(status, 200);
+        assert_eq!(body["result"], "odnum aloh");
+    }
+
+    let workers = cluster.workers().await;
+    assert_eq!(workers["strategy"], "round_robin");
+    for i in 0..3 {
+        assert_eq!(worker_field(&workers, i, "tasks_completed"), 3, "worker {}: {}", i, workers);
+        assert_eq!(worker_field(&workers, i, "in_flight"), 0);
+    }
+}
+
+#[tokio::test]
+async fn forwards_worker_errors_unchanged() {
+    let cluster = Cluster::start(Options::default()).await;
+
+    let (status, body) = cluster.get("/fibonacci?num=abc").await;
+    assert_eq!(status, 400);
+    assert_eq!(body["code"], "bad_request");
+
+    let (status, body) = cluster.get("/no-existe").await;
+    assert_eq!(status, 404);
+    assert_eq!(body["status"], 404);
+}
+
+#[tokio::test]
+async fn fails_over_to_the_next_worker() {
+    // Healthcheck lento: el fallo lo detecta el reenvio, no el healthcheck
+    let mut cluster = Cluster::start(Options {
+        workers: 2,
+        health_interval: Duration::from_secs(60),
+        ..Options::default()
+    })
+    .await;
+
+    cluster.stop_worker(0);
+
+    for _ in 0..4 {
+        let (status, body) = cluster.get("/reverse?text=abc").await;
+        assert_eq!(status, 200);
+        assert_eq!(body["result"], "cba");
+    }
+
+    let workers = cluster.workers().await;
+    assert_eq!(circuit_state(&workers, 0), "open");
+    assert_eq!(worker_field(&workers, 0, "tasks_failed"), 1);
+    assert_eq!(worker_field(&workers, 1, "tasks_completed"), 4);
+}
+
+#[tokio::test]
+async fn answers_503_when_no_worker_is_active() {
+    let mut cluster = Cluster::start(Options { workers: 1, ..Options::default() }).await;
+
+    cluster.stop_worker(0);
+    cluster.wait_for("worker inactivo", |w| active_count(w) == 0).await;
+
+    let (status, body) = cluster.get("/reverse?text=abc").await;
+    assert_eq!(status, 503);
+    assert_eq!(body["code"], "service_unavailable");
+}
+
+#[tokio::test]
+async fn health_check_marks_workers_down_and_up() {
+    let mut cluster = Cluster::start(Options { workers: 2, ..Options::default() }).await;
+
+    cluster.stop_worker(1);
+    let workers = cluster.wait_for("circuito de worker2 abierto", |w| circuit_state(w, 1) == "open").await;
+    assert_eq!(worker_field(&workers, 1, "circuit")["reason"], "healthcheck");
+    assert_eq!(circuit_state(&workers, 0), "closed");
+
+    cluster.restart_worker(1);
+    cluster.wait_for("worker2 activo otra vez", |w| active_count(w) == 2).await;
+}
+
+#[tokio::test]
+async fn montecarlo_aggregates_every_worker() {
+    let cluster = Cluster::start(Options::default()).await;
+
+    let (status, body) = cluster.get("/montecarlo?points=300000").await;
+    assert_eq!(status, 200);
+    assert_eq!(body["total_points_simulated"], 300000);
+    let pi = body["pi_estimate"].as_f64().unwrap();
+    assert!((3.0..3.3).contains(&pi), "pi fuera de rango: {}", pi);
+
+    let workers = cluster.workers().await;
+    for i in 0..3 {
+        assert_eq!(worker_field(&workers, i, "tasks_completed"), 1);
+    }
+}
+
+#[tokio::test]
+async fn rejects_invalid_montecarlo_points() {
+    let cluster = Cluster::start(Options::default()).await;
+
+    let (status, body) = cluster.get("/montecarlo?points=-5").await;
+    assert_eq!(status, 400);
+    assert_eq!(body["code"], "bad_request");
+}
+
+#[tokio::test]
+async fn draining_worker_stops_receiving_tasks() {
+    let cluster = Cluster::start(Options { workers: 2, ..Options::default() }).await;
+
+    let response = reqwest::get(cluster.worker_url(0, "/ready")).await.unwrap();
+    assert_eq!(response.status(), 200);
+
+    cluster.drain_worker(0);
+    let response = reqwest::get(cluster.worker_url(0, "/ready")).await.unwrap();
+    assert_eq!(response.status(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["draining"], true);
+
+    cluster.wait_for("circuito de worker1 abierto", |w| circuit_state(w, 0) == "open").await;
+    for _ in 0..3 {
+        assert_eq!(cluster.get("/timestamp").await.0, 200);
+    }
+    let workers = cluster.workers().await;
+    assert_eq!(worker_field(&workers, 1, "tasks_completed"), 3);
+}
+
+#[tokio::test]
+async fn traffic_keeps_succeeding_while_a_worker_drains_and_stops() {
+    // Con este umbral los pings fallidos tardarian 2 s en abrir el circuito; el aviso de drenado no espera
+    let mut cluster = Cluster::start(Options { health_interval: Duration::from_millis(200), unhealthy_threshold: 10, ..Options::default() }).await;
+
+    // Tareas no idempotentes con Idempotency-Key: un corte a mitad seria un 502 sin reintento
+    let running = Arc::new(AtomicBool::new(true));
+    let traffic = {
+        let (client, url, running) = (cluster.client.clone(), cluster.url("/createfile"), running.clone());
+        tokio::spawn(async move {
+            let mut statuses = Vec::new();
+            for i in 0.. {
+                if !running.load(Ordering::SeqCst) {
+                    break;
+                }
+                let response = client
+                    .post(&url)
+                    .header("Content-Type", "application/x-www-form-urlencoded")
+                    .header("Idempotency-Key", format!("drenado-{}", i))
+                    .body(format!("name=drenado{}&content=hola", i))
+                    .send()
+                    .await
+                    .unwrap();
+                statuses.push(response.status().as_u16());
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            statuses
+        })
+    };
+
+    // Lo que hace el worker con SIGTERM: /ready en 503, sigue atendiendo DRAIN_DELAY_SECS y se apaga
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    cluster.drain_worker(0);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(circuit_state(&cluster.workers().await, 0), "open");
+    cluster.stop_worker(0);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    running.store(false, Ordering::SeqCst);
+    let statuses = traffic.await.unwrap();
+    assert!(statuses.len() > 20, "{:?}", statuses);
+    assert!(statuses.iter().all(|&status| status == 200), "{:?}", statuses);
+}
+
+#[tokio::test]
+async fn admin_api_adds_and_removes_workers() {
+    let mut cluster = Cluster::start(Options { workers: 1, ..Options::default() }).await;
+    let extra = cluster.add_worker(None);
+    let address = cluster.worker_url(extra, "");
+
+    let response = cluster.client.post(cluster.url("/admin/workers")).body(format!(r#"{{"address":"{}","weight":2}}"#, address)).send().await.unwrap();
+    assert_eq!(response.status(), 201);
+    let ack: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(ack["id"], "worker2");
+    assert_eq!(ack["expires_in_secs"], serde_json::Value::Null);
+
+    let workers = cluster.wait_for("el worker nuevo activo", |w| active_count(w) == 2).await;
+    assert_eq!(worker_field(&workers, 1, "source"), "admin");
+    assert_eq!(worker_field(&workers, 1, "weight"), 2);
+
+    // Registrar la misma direccion otra vez no duplica el worker
+    let response = cluster.client.post(cluster.url("/admin/workers")).body(format!(r#"{{"address":"{}"}}"#, address)).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+
+    let metrics = || async { reqwest::get(cluster.url("/metrics")).await.unwrap().text().await.unwrap() };
+    assert!(metrics().await.contains("worker=\"worker2\""));
+
+    let response = cluster.client.delete(cluster.url("/admin/workers/worker2")).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let workers = cluster.workers().await;
+    assert_eq!(workers["workers"].as_array().unwrap().len(), 1);
+    // Las series del worker se van con el
+    let text = metrics().await;
+    assert!(!text.contains("worker=\"worker2\""), "{}", text);
+    assert!(text.contains("dispatcher_health_checks_total{worker=\"worker1\",result=\"ok\"}"), "{}", text);
+
+    let response = cluster.client.delete(cluster.url("/admin/workers/worker2")).send().await.unwrap();
+    assert_eq!(response.status(), 404);
+    let response = cluster.client.post(cluster.url("/admin/workers")).body("no es json").send().await.unwrap();
+    assert_eq!(response.status(), 400);
+}
+
+// Varios hilos: al detenerse, el worker se da de baja en el dispatcher mientras la prueba espera
+#[tokio::test(flavor = "multi_thread")]
+async fn workers_register_themselves_and_expire() {
+    let mut cluster = Cluster::start(Options {
+        workers: 1,
+        registration_ttl: Duration::from_secs(1),
+        ..Options::default()
+    })
+    .await;
+
+    let extra = cluster.add_worker(Some(Duration::from_millis(100)));
+    let workers = cluster.wait_for("el worker registrado activo", |w| active_count(w) == 2).await;
+    assert_eq!(worker_field(&workers, 1, "source"), "heartbeat");
+    assert_eq!(worker_field(&workers, 1, "address"), cluster.worker_url(extra, ""));
+
+    // Al detenerse se da de baja
+    cluster.stop_worker(extra);
+    cluster.wait_for("la baja del worker", |w| w["workers"].as_array().unwrap().len() == 1).await;
+
+    // Un registro que no se renueva vence solo
+    let response = cluster.client.post(cluster.url("/admin/workers")).body(r#"{"address":"http://127.0.0.1:1","heartbeat":true}"#).send().await.unwrap();
+    assert_eq!(response.status(), 201);
+    cluster.wait_for("el registro agregado", |w| w["workers"].as_array().unwrap().len() == 2).await;
+    cluster.wait_for("el vencimiento del registro", |w| w["workers"].as_array().unwrap().len() == 1).await;
+    let text = reqwest::get(cluster.url("/metrics")).await.unwrap().text().await.unwrap();
+    assert!(!text.contains("worker=\"worker2\"") && !text.contains("worker=\"worker3\""), "{}", text);
+}
+
+#[tokio::test]
+async fn reloads_the_worker_list_file() {
+    let mut cluster = Cluster::start(Options { workers: 2, workers_file: true, ..Options::default() }).await;
+
+    for _ in 0..4 {
+        assert_eq!(cluster.get("/reverse?text=abc").await.0, 200);
+    }
+
+    // Esta tarea cae en worker1 (round robin) y sigue en curso cuando se quita del archivo
+    let slow = tokio::spawn(cluster.client.get(cluster.url("/sleep?seconds=1")).send());
+    cluster.wait_for("la tarea lenta en curso", |w| worker_field(w, 0, "in_flight") == 1).await;
+
+    let extra = cluster.add_worker(None);
+    cluster.write_workers_file(&[1, extra]);
+    cluster.wait_for("worker1 drenando", |w| worker_field(w, 0, "draining") == true).await;
+
+    let response = slow.await.unwrap().unwrap();
+    assert_eq!(response.status(), 200);
+
+    let workers = cluster.wait_for("la lista recargada", |w| active_count(w) == 2 && worker_field(w, 0, "id") == "worker2").await;
+    assert_eq!(worker_field(&workers, 0, "tasks_completed"), 2);
+    assert_eq!(worker_field(&workers, 1, "id"), "worker3");
+    assert_eq!(workers["workers"].as_array().unwrap().len(), 2);
+    let text = reqwest::get(cluster.url("/metrics")).await.unwrap().text().await.unwrap();
+    assert!(!text.contains("worker=\"worker1\""), "{}", text);
+
+    // Un archivo invalido no cambia la lista
+    cluster.write_workers_text("no-es-una-url\n");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(active_count(&cluster.workers().await), 2);
+}
+
+#[tokio::test]
+async fn shutdown_finishes_in_flight_requests() {
+    let mut cluster = Cluster::start(Options { workers: 1, ..Options::default() }).await;
+
+    let slow = tokio::spawn(cluster.client.get(cluster.url("/sleep?seconds=1")).send());
+    cluster.wait_for("la tarea lenta en curso", |w| worker_field(w, 0, "in_flight") == 1).await;
+
+    assert!(cluster.shutdown_dispatcher().await);
+
+    let response = slow.await.unwrap().unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(tokio::net::TcpStream::connect(cluster.dispatcher).await.is_err(), "el dispatcher sigue aceptando clientes");
+}
+
+#[tokio::test]
+async fn slow_tasks_time_out_with_504() {
+    let cluster = Cluster::start(Options { workers: 1, ..Options::default() }).await;
+
+    let started = std::time::Instant::now();
+    let response = cluster.client.get(cluster.url("/fibonacci?num=70")).header("X-Deadline-Ms", "300").send().await.unwrap();
+    assert_eq!(response.status(), 504);
+    let response = cluster.client.get(cluster.url("/montecarlo?points=100000000000")).header("X-Deadline-Ms", "300").send().await.unwrap();
+    assert_eq!(response.status(), 504);
+    assert!(started.elapsed() < Duration::from_secs(3), "tardo {:?}", started.elapsed());
+
+    // El worker no queda ocupado ni se marca como caido
+    let (status, _) = cluster.get("/reverse?text=abc").await;
+    assert_eq!(status, 200);
+    assert_eq!(circuit_state(&cluster.workers().await, 0), "closed");
+
+    // Los limites cortos del cliente no cuentan como tareas lentas para el circuito
+    for _ in 0..6 {
+        let response = cluster.client.get(cluster.url("/fibonacci?num=70")).header("X-Deadline-Ms", "20").send().await.unwrap();
+        assert_eq!(response.status(), 504);
+    }
+    let workers = cluster.workers().await;
+    assert_eq!(circuit_state(&workers, 0), "closed");
+    assert_eq!(workers["workers"][0]["circuit"]["slow_rate"], 0.0);
+}
+
+#[tokio::test]
+async fn retries_idempotent_tasks_and_honors_idempotency_keys() {
+    let cluster = Cluster::start(Options { workers: 1, ..Options::default() }).await;
+    let failing = common::start_fake_upstream(503).await;
+    let response = cluster.client.post(cluster.url("/admin/workers")).body(format!(r#"{{"address":"{}"}}"#, failing.url)).send().await.unwrap();
+    assert_eq!(response.status(), 201);
+    cluster.wait_for("el worker que falla activo", |w| active_count(w) == 2).await;
+
+    // Un GET que recibe 503 se reintenta en el otro worker
+    for _ in 0..4 {
+        let (status, _) = cluster.get("/reverse?text=abc").await;
+        assert_eq!(status, 200);
+    }
+    assert!(failing.tasks.load(Ordering::SeqCst) >= 1);
+
+    // /createfile no es idempotente: sin clave el 503 llega al cliente
+    let create = |name: &str, key: Option<&str>| {
+        let mut request = cluster
+            .client
+            .post(cluster.url("/createfile"))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(format!("name={}&content=hola", name));
+        if let Some(key) = key {
+            request = request.header("Idempotency-Key", key);
+        }
+        request.send()
+    };
+    let mut statuses = Vec::new();
+    for name in ["sinclave1", "sinclave2"] {
+        statuses.push(create(name, None).await.unwrap().status().as_u16());
+    }
+    assert!(statuses.contains(&503), "{:?}", statuses);
+
+    // Con Idempotency-Key se reintenta, y repetir la clave devuelve la respuesta guardada
+    for (name, key) in [("conclave1", "clave-1"), ("conclave2", "clave-2")] {
+        assert_eq!(create(name, Some(key)).await.unwrap().status(), 200);
+    }
+    let before = failing.tasks.load(Ordering::SeqCst);
+    let replay = create("conclave1", Some("clave-1")).await.unwrap();
+    assert_eq!(replay.status(), 200);
+    assert_eq!(replay.headers()["idempotent-replayed"], "true");
+    assert_eq!(failing.tasks.load(Ordering::SeqCst), before);
+
+    // La misma clave con otra solicitud se rechaza
+    assert_eq!(create("otro", Some("clave-1")).await.unwrap().status(), 422);
+}
+
+// Si la conexion se corta despues de enviar la tarea no se sabe si el worker la ejecuto
+#[tokio::test]
+async fn idempotency_keys_do_not_retry_ambiguous_failures() {
+    let cluster = Cluster::start(Options { workers: 1, ..Options::default() }).await;
+    let cutting = common::start_fake_upstream(0).await;
+    let response = cluster.client.post(cluster.url("/admin/workers")).body(format!(r#"{{"address":"{}"}}"#, cutting.url)).send().await.unwrap();
+    assert_eq!(response.status(), 201);
+    cluster.wait_for("el worker que corta activo", |w| active_count(w) == 2).await;
+
+    let create = |name: &str, key: &str| {
+        cluster
+            .client
+            .post(cluster.url("/createfile"))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Idempotency-Key", key)
+            .body(format!("name={}&content=hola", name))
+            .send()
+    };
+
+    // Round robin: una de las dos cae en el worker que corta y no se repite en el otro
+    let mut cut = None;
+    for (name, key) in [("ambigua1", "ambigua-1"), ("ambigua2", "ambigua-2")] {
+        if create(name, key).await.unwrap().status() == 502 {
+            cut = Some((name, key));
+        }
+    }
+    let (name, key) = cut.expect("ninguna tarea llego al worker que corta");
+    assert_eq!(cutting.tasks.load(Ordering::SeqCst), 1);
+    assert!(!cluster.worker_file(0, name).exists());
+
+    // La respuesta queda guardada: repetir la clave no vuelve a ejecutar la tarea
+    let replay = create(name, key).await.unwrap();
+    assert_eq!(replay.status(), 502);
+    assert_eq!(replay.headers()["idempotent-replayed"], "true");
+    assert_eq!(cutting.tasks.load(Ordering::SeqCst), 1);
+    assert!(!cluster.worker_file(0, name).exists());
+}
+
+#[tokio::test]
+async fn a_body_cut_by_the_worker_is_an_error_not_an_empty_response() {
+    let cluster = Cluster::start(Options { workers: 1, ..Options::default() }).await;
+    let cutting = common::start_fake_upstream(1).await;
+    let response = cluster.client.post(cluster.url("/admin/workers")).body(format!(r#"{{"address":"{}"}}"#, cutting.url)).send().await.unwrap();
+    assert_eq!(response.status(), 201);
+    cluster.wait_for("el worker que corta activo", |w| active_count(w) == 2).await;
+
+    let create = |name: &str, key: &str| {
+        cluster
+            .client
+            .post(cluster.url("/createfile"))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Idempotency-Key", key)
+            .body(format!("name={}&content=hola", name))
+            .send()
+    };
+
+    // El 200 con el body cortado llega al cliente como 502 y no se reintenta en el otro worker
+    let mut cut = None;
+    for (name, key) in [("cortada1", "cortada-1"), ("cortada2", "cortada-2")] {
+        let response = create(name, key).await.unwrap();
+        if response.status() != 200 {
+            assert_eq!(response.status(), 502);
+            cut = Some((name, key));
+        }
+    }
+    let (name, key) = cut.expect("ninguna tarea llego al worker que corta");
+    assert_eq!(cutting.tasks.load(Ordering::SeqCst), 1);
+    assert!(!cluster.worker_file(0, name).exists());
+
+    // Lo que se guarda para la clave es el error, no un 200 vacio
+    let replay = create(name, key).await.unwrap();
+    assert_eq!(replay.status(), 502);
+    assert_eq!(replay.headers()["idempotent-replayed"], "true");
+    assert_eq!(cutting.tasks.load(Ordering::SeqCst), 1);
+
+    let text = reqwest::get(cluster.url("/metrics")).await.unwrap().text().await.unwrap();
+    assert!(text.contains("kind=\"transport\"} 1"), "{}", text);
+}
+
+#[tokio::test]
+async fn circuit_breaker_opens_on_errors_and_closes_after_trial_requests() {
+    let cluster = Cluster::start(Options { workers: 1, breaker_open: Duration::from_millis(500), ..Options::default() }).await;
+    let upstream = common::start_fake_upstream(500).await;
+    let response = cluster.client.post(cluster.url("/admin/workers")).body(format!(r#"{{"address":"{}"}}"#, upstream.url)).send().await.unwrap();
+    assert_eq!(response.status(), 201);
+    cluster.wait_for("el worker que falla activo", |w| active_count(w) == 2).await;
+
+    // Los 500 no se reintentan, pero al llegar al minimo de tareas abren el circuito
+    for _ in 0..10 {
+        cluster.client.get(cluster.url("/reverse?text=abc")).send().await.unwrap();
+    }
+    let workers = cluster.workers().await;
+    assert_eq!(circuit_state(&workers, 1), "open");
+    assert_eq!(worker_field(&workers, 1, "circuit")["reason"], "error_rate");
+    assert_eq!(upstream.tasks.load(Ordering::SeqCst), 5);
+
+    // Pasado BREAKER_OPEN_SECS recibe tareas de prueba; si salen bien el circuito se cierra
+    upstream.status.store(200, Ordering::SeqCst);
+    cluster.wait_for("circuito en half_open", |w| circuit_state(w, 1) == "half_open").await;
+    for _ in 0..6 {
+        assert_eq!(cluster.client.get(cluster.url("/reverse?text=abc")).send().await.unwrap().status(), 200);
+    }
+    assert_eq!(circuit_state(&cluster.workers().await, 1), "closed");
+}
+
+#[tokio::test]
+async fn metrics_report_forwarding_health_checks_and_circuits() {
+    let cluster = Cluster::start(Options::default()).await;
+
+    for _ in 0..3 {
+        assert_eq!(cluster.get("/reverse?text=hola").await.0, 200);
+    }
+    assert_eq!(cluster.get("/montecarlo?points=30000").await.0, 200);
+
+    let response = reqwest::get(cluster.url("/metrics")).await.unwrap();
+    assert_eq!(response.status(), 200);
+    let text = response.text().await.unwrap();
+    assert!(text.contains("http_requests_total{route=\"/reverse\",method=\"GET\",status=\"200\"} 3"), "{}", text);
+    assert!(text.contains("dispatcher_forward_duration_seconds_count{worker=\"worker1\"} 1"), "{}", text);
+    assert!(text.contains("dispatcher_montecarlo_points_total 30000"), "{}", text);
+    assert!(text.contains("dispatcher_health_checks_total{worker=\"worker2\",result=\"ok\"}"), "{}", text);
+    assert!(text.contains("dispatcher_worker_circuit_state{worker=\"worker3\",state=\"closed\"} 1"), "{}", text);
+    assert!(text.contains("dispatcher_worker_in_flight{worker=\"worker1\"} 0"), "{}", text);
+
+    // Rutas y metodos que no conoce ningun worker no crean series propias
+    for path in ["/no-existe/1", "/no-existe/2"] {
+        assert_eq!(cluster.get(path).await.0, 404);
+    }
+    let brew = reqwest::Method::from_bytes(b"BREW").unwrap();
+    assert_eq!(cluster.client.request(brew, cluster.url("/reverse?text=hola")).send().await.unwrap().status(), 405);
+    let text = reqwest::get(cluster.url("/metrics")).await.unwrap().text().await.unwrap();
+    assert!(text.contains("http_requests_total{route=\"other\",method=\"GET\",status=\"404\"} 2"), "{}", text);
+    assert!(text.contains("http_requests_total{route=\"/reverse\",method=\"other\",status=\"405\"} 1"), "{}", text);
+    assert!(!text.contains("no-existe"), "{}", text);
+}
+
+#[tokio::test]
+async fn propagates_and_echoes_request_ids() {
+    let cluster = Cluster::start(Options { workers: 1, ..Options::default() }).await;
+    let upstream = common::start_fake_upstream(200).await;
+    let response = cluster.client.post(cluster.url("/admin/workers")).body(format!(r#"{{"address":"{}"}}"#, upstream.url)).send().await.unwrap();
+    assert_eq!(response.status(), 201);
+    cluster.wait_for("el worker falso activo", |w| active_count(w) == 2).await;
+
+    // El id del cliente llega a los workers y vuelve en la respuesta
+    let sent: Vec<String> = (0..4).map(|i| format!("prueba-{}", i)).collect();
+    for id in &sent {
+        let response = cluster.client.get(cluster.url("/reverse?text=abc")).header("X-Request-Id", id).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["x-request-id"], id.as_str());
+        assert_eq!(response.headers().get_all("x-request-id").iter().count(), 1);
+    }
+    let seen = upstream.request_ids.lock().unwrap().clone();
+    assert!(!seen.is_empty());
+    assert!(seen.iter().all(|id| sent.contains(id)), "{:?}", seen);
+
+    // Sin id, o con uno invalido, el dispatcher genera uno nuevo
+    for header in [None, Some("con espacios")] {
+        let mut request = cluster.client.get(cluster.url("/workers"));
+        if let Some(id) = header {
+            request = request.header("X-Request-Id", id);
+        }
+        let response = request.send().await.unwrap();
+        let id = response.headers()["x-request-id"].to_str().unwrap();
+        assert_eq!(id.len(), 32, "{}", id);
+        assert!(id.bytes().all(|b| b.is_ascii_hexdigit()));
+    }
+}
+
+#[tokio::test]
+async fn exports_one_trace_across_dispatcher_and_workers() {
+    let collector = common::start_fake_collector().await;
+    let cluster = Cluster::start(Options { workers: 2, trace_endpoint: Some(collector.url.clone()), ..Options::default() }).await;
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+    let client_span = "00f067aa0ba902b7";
+    let traceparent = format!("00-{}-{}-01", trace_id, client_span);
+
+    // Una tarea: el span del cliente es el padre del dispatcher y el envio al worker es el padre del worker
+    let response = cluster.client.get(cluster.url("/reverse?text=abc")).header("traceparent", &traceparent).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    // Cada servicio exporta por su cuenta, se espera el span de solicitud de los dos
+    let spans = collector.wait_for_trace(trace_id, |spans| spans.iter().filter(|s| s["kind"] == 2).count() == 2).await;
+    let find = |service: &str, name: &str| {
+        spans.iter().find(|s| s["service"] == service && s["name"] == name).unwrap_or_else(|| panic!("falta {} {}: {:?}", service, name, spans)).clone()
+    };
+    let server = find("dispatcher", "GET /reverse");
+    assert_eq!(server["parentSpanId"], client_span);
+    assert_eq!(server["kind"], 2);
+    let select = find("dispatcher", "select_worker");
+    assert_eq!(select["parentSpanId"], server["spanId"]);
+    let forward = find("dispatcher", "forward");
+    assert_eq!(forward["parentSpanId"], server["spanId"]);
+    assert_eq!(forward["kind"], 3);
+    let worker = find("worker", "GET /reverse");
+    assert_eq!(worker["parentSpanId"], forward["spanId"]);
+    let execute = find("worker", "execute");
+    assert_eq!(execute["parentSpanId"], worker["spanId"]);
+
+    // Montecarlo: un envio por worker, cada uno con su span en el worker
+    let trace_id = "0af7651916cd43dd8448eb211c80319c";
+    let traceparent = format!("00-{}-{}-01", trace_id, client_span);
+    let response = cluster.client.get(cluster.url("/montecarlo?points=2000")).header("traceparent", &traceparent).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let spans = collector.wait_for_trace(trace_id, |spans| spans.iter().filter(|s| s["kind"] == 2).count() == 3).await;
+    let forwards: Vec<_> = spans.iter().filter(|s| s["name"] == "forward").map(|s| s["spanId"].clone()).collect();
+    assert_eq!(forwards.len(), 2);
+    for worker in spans.iter().filter(|s| s["service"] == "worker" && s["kind"] == 2) {
+        assert!(forwards.contains(&worker["parentSpanId"]), "{:?}", spans);
+    }
+
+    // La primera solicitud de una conexion nueva incluye la espera en la cola del pool
+    let trace_id = "5b8aa5a2d2c872e8321cf37308d69df2";
+    let traceparent = format!("00-{}-{}-01", trace_id, client_span);
+    let response = reqwest::Client::new().get(cluster.worker_url(0, "/timestamp")).header("traceparent", &traceparent).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let spans = collector.wait_for_trace(trace_id, |spans| spans.len() == 3).await;
+    let worker = spans.iter().find(|s| s["kind"] == 2).unwrap();
+    assert_eq!(worker["parentSpanId"], client_span);
+    for name in ["queue", "execute"] {
+        assert!(spans.iter().any(|s| s["name"] == name && s["parentSpanId"] == worker["spanId"]), "falta {}: {:?}", name, spans);
+    }
+
+    // Un traceparent sin muestrear se propaga pero no se exporta
+    let trace_id = "11111111111111111111111111111111";
+    let response = cluster.client.get(cluster.url("/reverse?text=abc")).header("traceparent", format!("00-{}-{}-00", trace_id, client_span)).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(collector.spans.lock().unwrap().iter().all(|s| s["traceId"] != trace_id));
+}
+
+#[tokio::test]
+async fn writes_access_logs_with_the_selected_worker() {
+    let cluster = Cluster::start(Options { workers: 2, access_log: true, ..Options::default() }).await;
+
+    let response = cluster
+        .client
+        .get(cluster.url("/reverse?text=abc"))
+        .header("X-Request-Id", "acceso-1")
+        .header("User-Agent", "prueba/1.0")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let body_len = response.bytes().await.unwrap().len();
+    assert_eq!(cluster.get("/montecarlo?points=2000").await.0, 200);
+
+    let log = cluster.wait_for_log("las lineas del dispatcher", Cluster::dispatcher_access_log, "/montecarlo").await;
+    let line = log.lines().find(|l| l.contains("\"acceso-1\"")).unwrap_or_else(|| panic!("{}", log));
+    assert!(line.starts_with("127.0.0.1 - - ["), "{}", line);
+    let expected = format!("\"GET /reverse?text=abc HTTP/1.1\" 200 {} \"-\" \"prueba/1.0\" ", body_len);
+    assert!(line.contains(&expected), "{}", line);
+    let worker = ["worker1", "worker2"].into_iter().find(|w| line.contains(&format!("\"{}\"", w))).unwrap_or_else(|| panic!("{}", line));
+    let montecarlo = log.lines().find(|l| l.contains("/montecarlo")).unwrap();
+    assert!(montecarlo.contains("\"worker1,worker2\""), "{}", montecarlo);
+
+    // El worker elegido tiene su propia linea con el mismo request_id
+    let index = if worker == "worker1" { 0 } else { 1 };
+    let worker_log = cluster.wait_for_log("la linea del worker", |c| c.worker_access_log(index), "\"acceso-1\"").await;
+    let worker_line = worker_log.lines().find(|l| l.contains("\"acceso-1\"")).unwrap_or_else(|| panic!("{}", worker_log));
+    assert!(worker_line.contains("\"GET /reverse?text=abc HTTP/1.1\" 200 "), "{}", worker_line);
+    assert!(worker_line.contains(" \"-\" \"acceso-1\""), "{}", worker_line);
+}
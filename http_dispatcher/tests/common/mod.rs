@@ -0,0 +1,486 @@
+/*
+Arnes para las pruebas de integracion.
+Levanta un dispatcher y N workers reales dentro del mismo proceso, cada uno en
+un puerto libre de localhost, con intervalos de healthcheck cortos.
+*/
+#![allow(dead_code)] // Cada archivo de pruebas usa una parte distinta del arnes
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use http_common::access_log::{AccessLogFormat, AccessLogSettings};
+use http_common::protocol::{Readiness, PROTOCOL_VERSION};
+use http_common::trace::{TraceExporter, TraceSettings};
+use http_dispatcher::auxiliares::initialize_workers;
+use http_dispatcher::Worker;
+use serde_json::Value;
+use so_server_rust::{Server, ServerHandle};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+// Tiempo maximo que se espera a que el sistema llegue a un estado
+pub const WAIT: Duration = Duration::from_secs(10);
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Cluster {
+    pub dispatcher: SocketAddr,
+    pub client: reqwest::Client,
+    workers: Vec<TestWorker>,
+    workers_file: Option<PathBuf>,
+    trace_endpoint: Option<String>,
+    access_log: Option<PathBuf>,       // Access log del dispatcher; los workers escriben junto a su carpeta
+    stop: Option<oneshot::Sender<()>>, // Hace de SIGTERM para el dispatcher
+    task: JoinHandle<bool>,
+}
+
+struct TestWorker {
+    addr: SocketAddr,
+    files_dir: PathBuf,
+    handle: Option<ServerHandle>,
+}
+
+// Ajustes del dispatcher que cambian entre pruebas
+pub struct Options {
+    pub workers: usize,
+    pub health_interval: Duration,
+    pub unhealthy_threshold: u32,       // Pings fallidos seguidos que abren el circuito
+    pub strategy: &'static str,
+    pub registration_ttl: Duration,
+    pub workers_file: bool,             // La lista de workers sale de un archivo que se puede reescribir con write_workers_file
+    pub breaker_open: Duration,
+    pub trace_endpoint: Option<String>, // Collector OTLP para el dispatcher y los workers, ver start_fake_collector
+    pub access_log: bool,               // Access log a archivos, ver dispatcher_access_log y worker_access_log
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            workers: 3,
+            health_interval: Duration::from_millis(100),
+            unhealthy_threshold: 1,
+            strategy: "round_robin",
+            registration_ttl: Duration::from_secs(30),
+            workers_file: false,
+            breaker_open: Duration::from_secs(5),
+            trace_endpoint: None,
+            access_log: false,
+        }
+    }
+}
+
+impl Cluster {
+    pub async fn start(options: Options) -> Cluster {
+        let mut workers = Vec::new();
+        for _ in 0..options.workers {
+            let files_dir = temp_files_dir();
+            let handle = start_worker("127.0.0.1:0", &files_dir, None, options.trace_endpoint.as_deref(), options.access_log);
+            workers.push(TestWorker { addr: handle.local_addr(), files_dir, handle: Some(handle) });
+        }
+
+        let mut config = http_dispatcher::Config::from_env();
+        config.lb_strategy = options.strategy.to_string();
+        config.health_interval = options.health_interval;
+        config.health_inactive_interval = Duration::from_millis(50);
+        config.health_timeout = Duration::from_millis(500);
+        config.healthy_threshold = 1;
+        config.unhealthy_threshold = options.unhealthy_threshold;
+        config.registration_ttl = options.registration_ttl;
+        config.breaker.open_for = options.breaker_open;
+        if let Some(endpoint) = &options.trace_endpoint {
+            config.trace = trace_settings(endpoint);
+        }
+        let access_log = options.access_log.then(|| {
+            let path = temp_files_dir().with_extension("access.log");
+            config.access_log = access_log_settings(&path);
+            path
+        });
+
+        let workers_file = options.workers_file.then(|| {
+            let path = temp_files_dir().with_extension("workers");
+            std::fs::write(&path, worker_list(workers.iter().map(|w| w.addr))).unwrap();
+            path
+        });
+        let dispatcher_workers = match &workers_file {
+            Some(path) => {
+                config.workers_file = path.to_string_lossy().into_owned();
+                config.workers_file_poll = Duration::from_millis(50);
+                initialize_workers(&config)
+            }
+            None => workers
+                .iter()
+                .enumerate()
+                .map(|(i, w)| Worker::new(&format!("worker{}", i + 1), &format!("http://{}", w.addr), 1))
+                .collect(),
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dispatcher = listener.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let shutdown = async {
+            let _ = stopped.await;
+        };
+        let task = tokio::spawn(http_dispatcher::run_until(listener, config, dispatcher_workers, shutdown));
+
+        let cluster = Cluster {
+            dispatcher,
+            client: reqwest::Client::new(),
+            workers,
+            workers_file,
+            trace_endpoint: options.trace_endpoint,
+            access_log,
+            stop: Some(stop),
+            task,
+        };
+        let count = options.workers;
+        cluster.wait_for("todos los workers activos", |w| active_count(w) == count).await;
+        cluster
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.dispatcher, path)
+    }
+
+    pub fn worker_url(&self, index: usize, path: &str) -> String {
+        format!("http://{}{}", self.workers[index].addr, path)
+    }
+
+    // Archivo que /createfile crea en el worker `index`
+    pub fn worker_file(&self, index: usize, name: &str) -> PathBuf {
+        self.workers[index].files_dir.join(format!("{}.txt", name))
+    }
+
+    // GET al dispatcher, devuelve el codigo y el body como JSON
+    pub async fn get(&self, path: &str) -> (u16, Value) {
+        let response = self.client.get(self.url(path)).send().await.unwrap();
+        let status = response.status().as_u16();
+        (status, response.json().await.unwrap())
+    }
+
+    pub async fn workers(&self) -> Value {
+        self.get("/workers").await.1
+    }
+
+    /*
+    Levanta un worker que el dispatcher no conoce y devuelve su indice.
+    Con `heartbeat` el worker se registra solo en el dispatcher con ese intervalo.
+    */
+    pub fn add_worker(&mut self, heartbeat: Option<Duration>) -> usize {
+        let files_dir = temp_files_dir();
+        let register = heartbeat.map(|interval| (self.url(""), interval));
+        let handle = start_worker("127.0.0.1:0", &files_dir, register, self.trace_endpoint.as_deref(), self.access_log.is_some());
+        self.workers.push(TestWorker { addr: handle.local_addr(), files_dir, handle: Some(handle) });
+        self.workers.len() - 1
+    }
+
+    // Apaga el dispatcher como si recibiera SIGTERM y devuelve si terminaron todas las conexiones
+    pub async fn shutdown_dispatcher(&mut self) -> bool {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        (&mut self.task).await.unwrap()
+    }
+
+    // Reescribe el archivo de workers con los workers de esos indices
+    pub fn write_workers_file(&self, indices: &[usize]) {
+        self.write_workers_text(&worker_list(indices.iter().map(|&i| self.workers[i].addr)));
+    }
+
+    pub fn write_workers_text(&self, text: &str) {
+        let path = self.workers_file.as_ref().expect("el cluster no usa archivo de workers");
+        std::fs::write(path, text).unwrap();
+    }
+
+    // Detiene un worker, deja de aceptar conexiones y cierra las que tenia
+    pub fn stop_worker(&mut self, index: usize) {
+        if let Some(handle) = self.workers[index].handle.take() {
+            handle.shutdown();
+        }
+    }
+
+    // El worker sigue aceptando conexiones pero /ready responde 503
+    pub fn drain_worker(&self, index: usize) {
+        if let Some(handle) = &self.workers[index].handle {
+            handle.health().start_draining();
+        }
+    }
+
+    // Vuelve a levantar un worker detenido en la misma direccion
+    pub fn restart_worker(&mut self, index: usize) {
+        let worker = &mut self.workers[index];
+        worker.handle = Some(start_worker(worker.addr, &worker.files_dir, None, self.trace_endpoint.as_deref(), self.access_log.is_some()));
+    }
+
+    pub fn dispatcher_access_log(&self) -> String {
+        std::fs::read_to_string(self.access_log.as_ref().expect("el cluster no usa access log")).unwrap_or_default()
+    }
+
+    pub fn worker_access_log(&self, index: usize) -> String {
+        std::fs::read_to_string(self.workers[index].files_dir.with_extension("access.log")).unwrap_or_default()
+    }
+
+    // Las lineas del access log se escriben en otro hilo, se espera a que aparezca `text`
+    pub async fn wait_for_log(&self, what: &str, read: impl Fn(&Cluster) -> String, text: &str) -> String {
+        let deadline = Instant::now() + WAIT;
+        loop {
+            let log = read(self);
+            if log.contains(text) {
+                return log;
+            }
+            assert!(Instant::now() < deadline, "No se llego a: {}. Ultimo log: {}", what, log);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    // Espera hasta que /workers cumpla la condicion
+    pub async fn wait_for(&self, what: &str, condition: impl Fn(&Value) -> bool) -> Value {
+        let deadline = Instant::now() + WAIT;
+        loop {
+            let workers = self.workers().await;
+            if condition(&workers) {
+                return workers;
+            }
+            assert!(Instant::now() < deadline, "No se llego a: {}. Ultimo estado: {}", what, workers);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        self.task.abort();
+        for worker in &mut self.workers {
+            if let Some(handle) = worker.handle.take() {
+                handle.shutdown();
+            }
+            let _ = std::fs::remove_dir_all(&worker.files_dir);
+            let _ = std::fs::remove_file(worker.files_dir.with_extension("access.log"));
+        }
+        for path in self.workers_file.iter().chain(&self.access_log) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn worker_list(addrs: impl Iterator<Item = SocketAddr>) -> String {
+    addrs.map(|addr| format!("http://{}\n", addr)).collect()
+}
+
+fn temp_files_dir() -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "http_distribuido-test-{}-{}",
+        std::process::id(),
+        NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+// `register` es (URL del dispatcher, intervalo de heartbeat) para los workers que se registran solos
+fn start_worker(
+    addr: impl std::net::ToSocketAddrs,
+    files_dir: &Path,
+    register: Option<(String, Duration)>,
+    trace_endpoint: Option<&str>,
+    access_log: bool,
+) -> ServerHandle {
+    let mut config = so_server_rust::Config::from_env();
+    config.pool_threads = 4;
+    config.files_dir = files_dir.to_path_buf();
+    if let Some((url, interval)) = register {
+        config.register_url = url;
+        config.heartbeat_interval = interval;
+    }
+    if let Some(endpoint) = trace_endpoint {
+        config.trace = trace_settings(endpoint);
+    }
+    if access_log {
+        config.access_log = access_log_settings(&files_dir.with_extension("access.log"));
+    }
+    Server::bind(addr, config).unwrap().spawn().unwrap()
+}
+
+// Exporta a `endpoint` con lotes frecuentes para no esperar en las pruebas
+fn trace_settings(endpoint: &str) -> TraceSettings {
+    TraceSettings {
+        exporter: TraceExporter::Otlp,
+        otlp_endpoint: endpoint.to_string(),
+        file: String::new(),
+        flush_interval: Duration::from_millis(50),
+    }
+}
+
+// El dispatcher y los workers escriben en formato extended, sin rotar
+fn access_log_settings(path: &Path) -> AccessLogSettings {
+    AccessLogSettings { target: path.to_string_lossy().into_owned(), format: AccessLogFormat::Extended, max_bytes: 0, max_files: 0 }
+}
+
+/*
+Servidor falso que pasa el healthcheck (/ready responde listo) pero responde `status`
+con un JSON vacio a todas las tareas. El codigo se puede cambiar durante la prueba;
+con 0 cierra la conexion sin responder despues de leer la tarea y con 1 responde 200
+pero corta la conexion a mitad del body.
+*/
+pub struct FakeUpstream {
+    pub url: String,
+    pub tasks: AtomicUsize,              // Tareas recibidas, sin contar los pings
+    pub status: AtomicU16,
+    pub request_ids: Mutex<Vec<String>>, // X-Request-Id de cada tarea, en minusculas
+}
+
+pub async fn start_fake_upstream(status: u16) -> Arc<FakeUpstream> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream = Arc::new(FakeUpstream {
+        url: format!("http://{}", listener.local_addr().unwrap()),
+        tasks: AtomicUsize::new(0),
+        status: AtomicU16::new(status),
+        request_ids: Mutex::new(Vec::new()),
+    });
+    let shared = upstream.clone();
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let upstream = shared.clone();
+            tokio::spawn(async move {
+                // Se lee la solicitud completa antes de responder para no cortarla a la mitad
+                let Some((head, _)) = read_fake_request(&mut stream).await else {
+                    return;
+                };
+
+                let (code, body) = if head.starts_with("get /ready ") {
+                    let readiness = Readiness { ready: true, draining: false, storage_writable: true, protocol_version: PROTOCOL_VERSION };
+                    (200, serde_json::to_string(&readiness).unwrap())
+                } else {
+                    upstream.tasks.fetch_add(1, Ordering::SeqCst);
+                    if let Some(id) = head.lines().find_map(|line| line.strip_prefix("x-request-id:")) {
+                        upstream.request_ids.lock().unwrap().push(id.trim().to_string());
+                    }
+                    (upstream.status.load(Ordering::SeqCst), "{}".to_string())
+                };
+                if code == 0 {
+                    return;
+                }
+                if code == 1 {
+                    let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 100\r\n\r\n{\"a").await;
+                    return;
+                }
+                let reply = format!(
+                    "HTTP/1.1 {} Prueba\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    code,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(reply.as_bytes()).await;
+            });
+        }
+    });
+    upstream
+}
+
+/*
+Collector OTLP falso: guarda los spans de cada POST, con el service.name
+del lote en el campo "service", y responde 200.
+*/
+pub struct FakeCollector {
+    pub url: String,
+    pub spans: Mutex<Vec<Value>>,
+}
+
+pub async fn start_fake_collector() -> Arc<FakeCollector> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let collector = Arc::new(FakeCollector {
+        url: format!("http://{}/v1/traces", listener.local_addr().unwrap()),
+        spans: Mutex::new(Vec::new()),
+    });
+    let shared = collector.clone();
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let collector = shared.clone();
+            tokio::spawn(async move {
+                let Some((_, body)) = read_fake_request(&mut stream).await else {
+                    return;
+                };
+                let export: Value = serde_json::from_slice(&body).unwrap_or_default();
+                for resource in export["resourceSpans"].as_array().into_iter().flatten() {
+                    let service = resource["resource"]["attributes"][0]["value"]["stringValue"].clone();
+                    for scope in resource["scopeSpans"].as_array().into_iter().flatten() {
+                        for span in scope["spans"].as_array().into_iter().flatten() {
+                            let mut span = span.clone();
+                            span["service"] = service.clone();
+                            collector.spans.lock().unwrap().push(span);
+                        }
+                    }
+                }
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+            });
+        }
+    });
+    collector
+}
+
+impl FakeCollector {
+    // Espera hasta que los spans de la traza cumplan la condicion y los devuelve
+    pub async fn wait_for_trace(&self, trace_id: &str, condition: impl Fn(&[Value]) -> bool) -> Vec<Value> {
+        let deadline = Instant::now() + WAIT;
+        loop {
+            let spans: Vec<Value> = self.spans.lock().unwrap().iter().filter(|s| s["traceId"] == trace_id).cloned().collect();
+            if condition(&spans) {
+                return spans;
+            }
+            assert!(Instant::now() < deadline, "La traza {} no llego completa: {:?}", trace_id, spans);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+}
+
+// Lee una solicitud completa; devuelve el encabezado en minusculas y el body
+async fn read_fake_request(stream: &mut tokio::net::TcpStream) -> Option<(String, Vec<u8>)> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let head = loop {
+        if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break String::from_utf8_lossy(&buffer[..end]).to_ascii_lowercase();
+        }
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+        }
+    };
+    let length: usize = head
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length:"))
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(0);
+    while buffer.len() < head.len() + 4 + length {
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+        }
+    }
+    let body = buffer[head.len() + 4..].to_vec();
+    Some((head, body))
+}
+
+// Helpers para leer el JSON de /workers
+
+// Workers que reciben tareas: circuito cerrado y sin drenar
+pub fn active_count(workers: &Value) -> usize {
+    workers["workers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|w| w["circuit"]["state"] == "closed" && w["draining"] == false)
+        .count()
+}
+
+pub fn circuit_state(workers: &Value, index: usize) -> Value {
+    workers["workers"][index]["circuit"]["state"].clone()
+}
+
+pub fn worker_field(workers: &Value, index: usize, field: &str) -> Value {
+    workers["workers"][index][field].clone()
+}